@@ -1,29 +1,214 @@
 pragma solidity ^0.5.0;
 
 contract SSPermissions {
+  /* A grant is valid from validFrom until validUntil (block timestamps), 0 means no limit */
+  struct Grant {
+    bool allowed;
+    uint256 validFrom;
+    uint256 validUntil;
+  }
+
   struct Access {
     address owner;
     bool created;
-    mapping (address => bool) allowed;
+    mapping (address => Grant) grants;
+    /* Users with a grant, memberIndex is the position in members + 1 */
+    address[] members;
+    mapping (address => uint256) memberIndex;
   }
 
   mapping (bytes32 => Access) docs;
 
+  event AccessGranted(bytes32 indexed id, address indexed user, uint256 validFrom, uint256 validUntil);
+  event AccessRevoked(bytes32 indexed id, address indexed user);
+  event AccessExpired(bytes32 indexed id, address indexed user, uint256 validUntil);
+
+  /* EIP-712 domain and type hashes used to verify permits submitted by a relayer */
+  bytes32 public DOMAIN_SEPARATOR;
+  bytes32 public constant ALLOW_ACCESS_TYPEHASH = keccak256(
+    "AllowAccess(bytes32 id,address[] users,address owner,uint256 nonce,uint256 deadline)"
+  );
+  mapping (address => uint256) public nonces;
+
+  constructor() public {
+    uint256 chainId;
+    assembly { chainId := chainid() }
+    DOMAIN_SEPARATOR = keccak256(abi.encode(
+      keccak256("EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)"),
+      keccak256(bytes("SSPermissions")),
+      keccak256(bytes("1")),
+      chainId,
+      address(this)
+    ));
+  }
+
   function allow_access(bytes32 id, address[] calldata users) external {
-    require(
-        msg.sender == docs[id].owner || docs[id].created == false,
-        "Sender not authorized."
-        );
+    grant(msg.sender, id, users, 0, 0);
+  }
+
+  function allow_access_until(
+    bytes32 id,
+    address[] calldata users,
+    uint256 validFrom,
+    uint256 validUntil
+  ) external {
+    require(validUntil == 0 || validUntil > validFrom, "Invalid validity window.");
+    grant(msg.sender, id, users, validFrom, validUntil);
+  }
+
+  /* Same as allow_access but authorized by an off-chain signature of the owner,
+   * so any relayer can pay for the transaction */
+  function allow_access_with_permit(
+    bytes32 id,
+    address[] calldata users,
+    address owner,
+    uint256 nonce,
+    uint256 deadline,
+    uint8 v,
+    bytes32 r,
+    bytes32 s
+  ) external {
+    require(block.timestamp <= deadline, "Permit expired.");
+    require(nonce == nonces[owner], "Invalid permit nonce.");
+
+    bytes32 structHash = keccak256(abi.encode(
+      ALLOW_ACCESS_TYPEHASH,
+      id,
+      keccak256(abi.encodePacked(users)),
+      owner,
+      nonce,
+      deadline
+    ));
+    bytes32 digest = keccak256(abi.encodePacked("\x19\x01", DOMAIN_SEPARATOR, structHash));
+    address signer = ecrecover(digest, v, r, s);
+    require(signer != address(0) && signer == owner, "Invalid permit signature.");
 
-    if (docs[id].created == false) docs[id].owner = msg.sender;
-    
+    nonces[owner]++;
+    grant(owner, id, users, 0, 0);
+  }
+
+  /* Same as allow_access for many documents, e.g. to share the history of a room with a
+   * new member in one transaction */
+  function allow_access_many(bytes32[] calldata ids, address[] calldata users) external {
+    for (uint i = 0; i < ids.length; i++) {
+      grant(msg.sender, ids[i], users, 0, 0);
+    }
+  }
+
+  function revoke_access(bytes32 id, address[] calldata users) external {
+    revoke(msg.sender, id, users);
+  }
+
+  function revoke_access_many(bytes32[] calldata ids, address[] calldata users) external {
+    for (uint i = 0; i < ids.length; i++) {
+      revoke(msg.sender, ids[i], users);
+    }
+  }
+
+  /* Remove grants which ran out, anybody can call this. Clients watch AccessExpired
+   * to purge cached keys */
+  function expire(bytes32 id, address[] calldata users) external {
     for (uint i = 0; i < users.length; i++) {
-      docs[id].allowed[users[i]] = true;
+      Grant storage g = docs[id].grants[users[i]];
+      if (g.allowed && g.validUntil != 0 && g.validUntil <= block.timestamp) {
+        uint256 validUntil = g.validUntil;
+        delete docs[id].grants[users[i]];
+        removeMember(id, users[i]);
+        emit AccessExpired(id, users[i], validUntil);
+      }
     }
   }
 
   function checkPermissions(address user, bytes32 id) public view returns (bool) {
-    if (docs[id].allowed[user] == true || docs[id].owner == user) return true;
-    return false;
+    if (docs[id].owner == user) return true;
+    Grant storage g = docs[id].grants[user];
+    return g.allowed
+      && (g.validFrom == 0 || g.validFrom <= block.timestamp)
+      && (g.validUntil == 0 || block.timestamp < g.validUntil);
+  }
+
+  function getGrant(address user, bytes32 id) external view returns (bool allowed, uint256 validFrom, uint256 validUntil) {
+    Grant storage g = docs[id].grants[user];
+    return (g.allowed, g.validFrom, g.validUntil);
+  }
+
+  function ownerOf(bytes32 id) external view returns (address) {
+    return docs[id].owner;
+  }
+
+  function memberCount(bytes32 id) external view returns (uint256) {
+    return docs[id].members.length;
+  }
+
+  function membersOf(bytes32 id) external view returns (address[] memory) {
+    return docs[id].members;
+  }
+
+  /* Members from start on, at most count of them, for documents with many members */
+  function membersPage(bytes32 id, uint256 start, uint256 count) external view returns (address[] memory) {
+    address[] storage members = docs[id].members;
+    if (start >= members.length) return new address[](0);
+    uint256 end = start + count;
+    if (end > members.length || end < start) end = members.length;
+
+    address[] memory page = new address[](end - start);
+    for (uint i = start; i < end; i++) {
+      page[i - start] = members[i];
+    }
+    return page;
+  }
+
+  function addMember(bytes32 id, address user) internal {
+    Access storage doc = docs[id];
+    if (doc.memberIndex[user] != 0) return;
+    doc.members.push(user);
+    doc.memberIndex[user] = doc.members.length;
+  }
+
+  /* Swap with the last member so removing doesn't leave holes */
+  function removeMember(bytes32 id, address user) internal {
+    Access storage doc = docs[id];
+    uint256 index = doc.memberIndex[user];
+    if (index == 0) return;
+
+    address last = doc.members[doc.members.length - 1];
+    doc.members[index - 1] = last;
+    doc.memberIndex[last] = index;
+    doc.members.pop();
+    delete doc.memberIndex[user];
+  }
+
+  function revoke(address sender, bytes32 id, address[] memory users) internal {
+    require(sender == docs[id].owner, "Sender not authorized.");
+
+    for (uint i = 0; i < users.length; i++) {
+      delete docs[id].grants[users[i]];
+      removeMember(id, users[i]);
+      emit AccessRevoked(id, users[i]);
+    }
+  }
+
+  function grant(
+    address sender,
+    bytes32 id,
+    address[] memory users,
+    uint256 validFrom,
+    uint256 validUntil
+  ) internal {
+    require(
+        sender == docs[id].owner || docs[id].created == false,
+        "Sender not authorized."
+        );
+
+    if (docs[id].created == false) {
+      docs[id].owner = sender;
+      docs[id].created = true;
+    }
+
+    for (uint i = 0; i < users.length; i++) {
+      docs[id].grants[users[i]] = Grant(true, validFrom, validUntil);
+      addMember(id, users[i]);
+      emit AccessGranted(id, users[i], validFrom, validUntil);
+    }
   }
 }
@@ -0,0 +1,36 @@
+#!/bin/sh
+# Compile SSPermissions.sol and write the ABI, bytecode and metadata to SSPermissions.json,
+# needs solc 0.5.16 (the version the contract is written for) and python3
+set -e
+cd "$(dirname "$0")"
+solc --version | grep -q "0.5.16" || echo "Warning: SSPermissions.json was built with solc 0.5.16" >&2
+
+solc --evm-version istanbul --combined-json abi,bin,bin-runtime,metadata SSPermissions.sol > SSPermissions.compiled.json
+python3 - <<'PY'
+import datetime, json
+
+with open("SSPermissions.compiled.json") as f:
+    compiled = json.load(f)["contracts"]["SSPermissions.sol:SSPermissions"]
+with open("SSPermissions.json") as f:
+    artifact = json.load(f)
+with open("SSPermissions.sol") as f:
+    source = f.read()
+
+abi = compiled["abi"]
+artifact["abi"] = json.loads(abi) if isinstance(abi, str) else abi
+artifact["bytecode"] = "0x" + compiled["bin"]
+artifact["deployedBytecode"] = "0x" + compiled["bin-runtime"]
+artifact["metadata"] = compiled["metadata"]
+artifact["source"] = source
+artifact["sourcePath"] = "SSPermissions.sol"
+artifact["compiler"]["version"] = json.loads(compiled["metadata"])["compiler"]["version"]
+artifact["updatedAt"] = datetime.datetime.utcnow().isoformat(timespec="milliseconds") + "Z"
+# Source maps and ASTs of the old build don't match the new bytecode
+for key in ("sourceMap", "deployedSourceMap", "ast", "legacyAST"):
+    artifact.pop(key, None)
+
+with open("SSPermissions.json", "w") as f:
+    json.dump(artifact, f, indent=2)
+    f.write("\n")
+PY
+rm SSPermissions.compiled.json
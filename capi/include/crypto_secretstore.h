@@ -0,0 +1,159 @@
+#ifndef CRYPTO_SECRETSTORE_H
+#define CRYPTO_SECRETSTORE_H
+
+/* Generated by cbindgen from src/lib.rs, don't edit it by hand */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+typedef enum {
+  SS_STATUS_OK = 0,
+  SS_STATUS_INVALID_ARGUMENT = 1,
+  SS_STATUS_ACCESS_DENIED = 2,
+  SS_STATUS_KEY_NOT_FOUND = 3,
+  SS_STATUS_KEY_EXISTS = 4,
+  SS_STATUS_INVALID_THRESHOLD = 5,
+  /**
+   * Retrying later can help
+   */
+  SS_STATUS_UNAVAILABLE = 6,
+  SS_STATUS_OTHER = 7,
+} SsStatus;
+
+/**
+ * Opaque handle of a store, free it with `ss_store_free`. A handle can be used from any
+ * thread, requests of the same handle run one after the other.
+ */
+typedef struct SsStore SsStore;
+
+/**
+ * Bytes owned by the library, free them with `ss_buffer_free`.
+ */
+typedef struct {
+  uint8_t *data;
+  size_t len;
+} SsBuffer;
+
+typedef void (*SsBufferCallback)(void *user_data, SsStatus status, SsBuffer buffer, char *error);
+
+typedef void (*SsStatusCallback)(void *user_data, SsStatus status, char *error);
+
+typedef void (*SsCheckCallback)(void *user_data, SsStatus status, bool allowed, char *error);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Allow the `count` addresses in `addresses` to decrypt the document.
+ */
+SsStatus ss_allow_access(const SsStore *store,
+                         const char *id,
+                         const char *const *addresses,
+                         size_t count,
+                         char **error);
+
+/**
+ * Like `ss_allow_access`, the callback owns the error message.
+ */
+void ss_allow_access_async(const SsStore *store,
+                           const char *id,
+                           const char *const *addresses,
+                           size_t count,
+                           SsStatusCallback callback,
+                           void *user_data);
+
+void ss_buffer_free(SsBuffer buffer);
+
+/**
+ * Whether `address` can decrypt the document right now.
+ */
+SsStatus ss_check_permissions(const SsStore *store,
+                              const char *address,
+                              const char *id,
+                              bool *allowed,
+                              char **error);
+
+/**
+ * Like `ss_check_permissions`, the callback owns the error message.
+ */
+void ss_check_permissions_async(const SsStore *store,
+                                const char *address,
+                                const char *id,
+                                SsCheckCallback callback,
+                                void *user_data);
+
+/**
+ * Decrypt a ciphertext of `ss_encrypt`, the document is written to `out`.
+ */
+SsStatus ss_decrypt(const SsStore *store,
+                    const char *id,
+                    const uint8_t *data,
+                    size_t len,
+                    SsBuffer *out,
+                    char **error);
+
+/**
+ * Like `ss_decrypt`, the callback owns the buffer and the error message.
+ */
+void ss_decrypt_async(const SsStore *store,
+                      const char *id,
+                      const uint8_t *data,
+                      size_t len,
+                      SsBufferCallback callback,
+                      void *user_data);
+
+/**
+ * Encrypt `len` bytes at `data`, the ciphertext is written to `out`.
+ */
+SsStatus ss_encrypt(const SsStore *store,
+                    const char *id,
+                    const uint8_t *data,
+                    size_t len,
+                    uint32_t threshold,
+                    SsBuffer *out,
+                    char **error);
+
+/**
+ * Like `ss_encrypt`, the callback owns the buffer and the error message.
+ */
+void ss_encrypt_async(const SsStore *store,
+                      const char *id,
+                      const uint8_t *data,
+                      size_t len,
+                      uint32_t threshold,
+                      SsBufferCallback callback,
+                      void *user_data);
+
+/**
+ * Document ID of a document, free it with `ss_string_free`. The bytes are hashed as they
+ * are, for UTF-8 documents it's the ID `generate_id` of the library returns.
+ */
+char *ss_generate_id(const uint8_t *document, size_t len);
+
+/**
+ * Requests which are still running keep their own reference to the store.
+ */
+void ss_store_free(SsStore *store);
+
+/**
+ * Create a store for the account at `address`. `config_path` is a TOML config file like
+ * the one of secretstore-cli, the defaults are used if it's NULL.
+ */
+SsStatus ss_store_new(const char *address,
+                      const char *password,
+                      const char *config_path,
+                      SsStore **store,
+                      char **error);
+
+/**
+ * Frees error messages and document IDs.
+ */
+void ss_string_free(char *string);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* CRYPTO_SECRETSTORE_H */
@@ -0,0 +1,756 @@
+/* C API for clients which can't link Rust, e.g. GLib based Matrix clients. The header
+ * include/crypto_secretstore.h is generated with cbindgen, see the README.
+ *
+ * Every function returns a status, if it isn't SS_STATUS_OK and error isn't NULL a message is
+ * written to error, which has to be freed with ss_string_free. Buffers are freed with
+ * ss_buffer_free. The _async functions return right away and call the callback from one of
+ * the store's worker threads once the request is done, GLib clients should hand the result to the main
+ * loop with g_idle_add. Pointers have to be valid for the duration of the call, they aren't
+ * kept after it returns */
+#![allow(clippy::missing_safety_doc)]
+use crypto_secretstore::{generate_id_bytes, Config, CryptoSecretStore, ErrorClass, SecretString};
+use failure::{format_err, Error};
+use primitive_types::H160;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_void};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SsStatus {
+    Ok = 0,
+    InvalidArgument = 1,
+    AccessDenied = 2,
+    KeyNotFound = 3,
+    KeyExists = 4,
+    InvalidThreshold = 5,
+    /// Retrying later can help
+    Unavailable = 6,
+    Other = 7,
+}
+
+impl From<&Error> for SsStatus {
+    fn from(error: &Error) -> Self {
+        match ErrorClass::of(error) {
+            ErrorClass::AccessDenied => SsStatus::AccessDenied,
+            ErrorClass::KeyNotFound => SsStatus::KeyNotFound,
+            ErrorClass::KeyExists => SsStatus::KeyExists,
+            ErrorClass::InvalidThreshold => SsStatus::InvalidThreshold,
+            ErrorClass::Unavailable => SsStatus::Unavailable,
+            ErrorClass::Other => SsStatus::Other,
+        }
+    }
+}
+
+/// Bytes owned by the library, free them with `ss_buffer_free`.
+#[repr(C)]
+pub struct SsBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl SsBuffer {
+    fn empty() -> SsBuffer {
+        SsBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    fn new(bytes: Vec<u8>) -> SsBuffer {
+        let bytes = bytes.into_boxed_slice();
+        let len = bytes.len();
+        SsBuffer {
+            data: Box::into_raw(bytes) as *mut u8,
+            len,
+        }
+    }
+}
+
+/// Opaque handle of a store, free it with `ss_store_free`. A handle can be used from any
+/// thread, requests of the same handle run at the same time.
+pub struct SsStore {
+    store: Arc<CryptoSecretStore>,
+    pool: Pool,
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/* Fixed number of worker threads for the _async requests of a store, further requests wait
+ * in the queue. The workers exit once the store is freed and the queue is empty */
+struct Pool {
+    jobs: Mutex<mpsc::Sender<Job>>,
+}
+
+impl Pool {
+    fn new(size: usize) -> Pool {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..size.max(1) {
+            let receiver = receiver.clone();
+            thread::spawn(move || loop {
+                let job = receiver.lock().unwrap().recv();
+                match job {
+                    Ok(job) => job(),
+                    Err(_) => break,
+                }
+            });
+        }
+        Pool {
+            jobs: Mutex::new(sender),
+        }
+    }
+
+    fn execute(&self, job: Job) {
+        /* The workers only stop after the sender is dropped */
+        let _ = self.jobs.lock().unwrap().send(job);
+    }
+}
+
+pub type SsStatusCallback =
+    Option<unsafe extern "C" fn(user_data: *mut c_void, status: SsStatus, error: *mut c_char)>;
+pub type SsBufferCallback = Option<
+    unsafe extern "C" fn(
+        user_data: *mut c_void,
+        status: SsStatus,
+        buffer: SsBuffer,
+        error: *mut c_char,
+    ),
+>;
+pub type SsCheckCallback = Option<
+    unsafe extern "C" fn(
+        user_data: *mut c_void,
+        status: SsStatus,
+        allowed: bool,
+        error: *mut c_char,
+    ),
+>;
+
+/* The pointer is only given back to the callback */
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+
+/* Input is copied before a request is moved to another thread */
+enum Input {
+    Invalid(Error),
+    Valid(Request),
+}
+
+enum Request {
+    Encrypt {
+        id: String,
+        data: Vec<u8>,
+        threshold: u32,
+    },
+    Decrypt {
+        id: String,
+        data: Vec<u8>,
+    },
+    AllowAccess {
+        id: String,
+        addresses: Vec<H160>,
+    },
+    CheckPermissions {
+        id: String,
+        address: H160,
+    },
+}
+
+enum Output {
+    Done,
+    Buffer(Vec<u8>),
+    Allowed(bool),
+}
+
+impl Request {
+    fn run(self, store: &CryptoSecretStore) -> Result<Output, Error> {
+        match self {
+            Request::Encrypt {
+                id,
+                data,
+                threshold,
+            } => {
+                let mut ciphertext = Vec::new();
+                store.encrypt_stream(&id, &mut data.as_slice(), &mut ciphertext, threshold)?;
+                Ok(Output::Buffer(ciphertext))
+            }
+            Request::Decrypt { id, data } => {
+                let mut document = Vec::new();
+                store.decrypt_stream(&id, &mut data.as_slice(), &mut document)?;
+                Ok(Output::Buffer(document))
+            }
+            Request::AllowAccess { id, addresses } => {
+                store.allow_access(&id, &addresses)?;
+                Ok(Output::Done)
+            }
+            Request::CheckPermissions { id, address } => Ok(Output::Allowed(
+                store.check_permissions(address, &id)?.is_allowed(),
+            )),
+        }
+    }
+}
+
+fn run(store: &CryptoSecretStore, input: Input) -> Result<Output, (SsStatus, Error)> {
+    let request = match input {
+        Input::Valid(request) => request,
+        Input::Invalid(error) => return Err((SsStatus::InvalidArgument, error)),
+    };
+    match catch_unwind(AssertUnwindSafe(|| request.run(store))) {
+        Ok(Ok(output)) => Ok(output),
+        Ok(Err(error)) => Err(((&error).into(), error)),
+        Err(_) => Err((SsStatus::Other, format_err!("The request panicked"))),
+    }
+}
+
+fn message(error: &Error) -> *mut c_char {
+    CString::new(error.to_string().replace('\0', ""))
+        .map(CString::into_raw)
+        .unwrap_or(ptr::null_mut())
+}
+
+unsafe fn set_error(error_out: *mut *mut c_char, error: &Error) {
+    if !error_out.is_null() {
+        *error_out = message(error);
+    }
+}
+
+unsafe fn string(value: *const c_char, name: &str) -> Result<String, Error> {
+    if value.is_null() {
+        return Err(format_err!("{} is NULL", name));
+    }
+    Ok(CStr::from_ptr(value)
+        .to_str()
+        .map_err(|_| format_err!("{} isn't valid UTF-8", name))?
+        .to_string())
+}
+
+unsafe fn bytes(data: *const u8, len: usize) -> Result<Vec<u8>, Error> {
+    if data.is_null() && len > 0 {
+        return Err(format_err!("data is NULL"));
+    }
+    if len == 0 {
+        return Ok(vec![]);
+    }
+    Ok(slice::from_raw_parts(data, len).to_vec())
+}
+
+fn address(address: &str) -> Result<H160, Error> {
+    H160::from_str(address.trim_start_matches("0x"))
+        .map_err(|_| format_err!("Invalid address {}", address))
+}
+
+unsafe fn addresses(addresses: *const *const c_char, count: usize) -> Result<Vec<H160>, Error> {
+    if addresses.is_null() && count > 0 {
+        return Err(format_err!("addresses is NULL"));
+    }
+    (0..count)
+        .map(|i| address(&string(*addresses.add(i), "address")?))
+        .collect()
+}
+
+unsafe fn encrypt_input(id: *const c_char, data: *const u8, len: usize, threshold: u32) -> Input {
+    match (string(id, "id"), bytes(data, len)) {
+        (Ok(id), Ok(data)) => Input::Valid(Request::Encrypt {
+            id,
+            data,
+            threshold,
+        }),
+        (Err(error), _) | (_, Err(error)) => Input::Invalid(error),
+    }
+}
+
+unsafe fn decrypt_input(id: *const c_char, data: *const u8, len: usize) -> Input {
+    match (string(id, "id"), bytes(data, len)) {
+        (Ok(id), Ok(data)) => Input::Valid(Request::Decrypt { id, data }),
+        (Err(error), _) | (_, Err(error)) => Input::Invalid(error),
+    }
+}
+
+unsafe fn allow_access_input(
+    id: *const c_char,
+    addresses: *const *const c_char,
+    count: usize,
+) -> Input {
+    match (string(id, "id"), self::addresses(addresses, count)) {
+        (Ok(id), Ok(addresses)) => Input::Valid(Request::AllowAccess { id, addresses }),
+        (Err(error), _) | (_, Err(error)) => Input::Invalid(error),
+    }
+}
+
+unsafe fn check_input(address: *const c_char, id: *const c_char) -> Input {
+    let address = string(address, "address").and_then(|value| self::address(&value));
+    match (string(id, "id"), address) {
+        (Ok(id), Ok(address)) => Input::Valid(Request::CheckPermissions { id, address }),
+        (Err(error), _) | (_, Err(error)) => Input::Invalid(error),
+    }
+}
+
+/* Runs a request on the calling thread */
+unsafe fn call(
+    store: *const SsStore,
+    input: Input,
+    error_out: *mut *mut c_char,
+) -> Result<Output, SsStatus> {
+    if store.is_null() {
+        set_error(error_out, &format_err!("store is NULL"));
+        return Err(SsStatus::InvalidArgument);
+    }
+    run(&(*store).store, input).map_err(|(status, error)| {
+        set_error(error_out, &error);
+        status
+    })
+}
+
+/* Queues a request on the store's pool, done gets the result. Without a store the request
+ * fails right away, still through done */
+unsafe fn spawn<F>(store: *const SsStore, input: Input, done: F)
+where
+    F: FnOnce(Result<Output, (SsStatus, Error)>) + Send + 'static,
+{
+    if store.is_null() {
+        done(Err((
+            SsStatus::InvalidArgument,
+            format_err!("store is NULL"),
+        )));
+        return;
+    }
+    let SsStore { store, pool } = &*store;
+    let store = store.clone();
+    pool.execute(Box::new(move || done(run(&store, input))));
+}
+
+/// Create a store for the account at `address`. `config_path` is a TOML config file like
+/// the one of secretstore-cli, the defaults are used if it's NULL. Up to
+/// `parallel_requests` of the config `_async` requests run at the same time.
+#[no_mangle]
+pub unsafe extern "C" fn ss_store_new(
+    address: *const c_char,
+    password: *const c_char,
+    config_path: *const c_char,
+    store: *mut *mut SsStore,
+    error: *mut *mut c_char,
+) -> SsStatus {
+    if store.is_null() {
+        return SsStatus::InvalidArgument;
+    }
+    *store = ptr::null_mut();
+    let arguments = string(address, "address").and_then(|address| {
+        let password = SecretString::from(string(password, "password")?);
+        let config = if config_path.is_null() {
+            Config::default()
+        } else {
+            Config::from_file(string(config_path, "config_path")?)?
+        };
+        Ok((address, password, config))
+    });
+    let (address, password, config) = match arguments {
+        Ok(arguments) => arguments,
+        Err(err) => {
+            set_error(error, &err);
+            return SsStatus::InvalidArgument;
+        }
+    };
+
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        CryptoSecretStore::with_config(&address, password.expose(), &config)
+    }))
+    .unwrap_or_else(|_| Err(format_err!("Creating the store panicked")));
+    match result {
+        Ok(secret_store) => {
+            *store = Box::into_raw(Box::new(SsStore {
+                store: Arc::new(secret_store),
+                pool: Pool::new(config.parallel_requests),
+            }));
+            SsStatus::Ok
+        }
+        Err(err) => {
+            set_error(error, &err);
+            (&err).into()
+        }
+    }
+}
+
+/// Requests which are still running or queued keep their own reference to the store.
+#[no_mangle]
+pub unsafe extern "C" fn ss_store_free(store: *mut SsStore) {
+    if !store.is_null() {
+        drop(Box::from_raw(store));
+    }
+}
+
+/// Document ID of a document, free it with `ss_string_free`. The bytes are hashed as they
+/// are, for UTF-8 documents it's the ID `generate_id` of the library returns.
+#[no_mangle]
+pub unsafe extern "C" fn ss_generate_id(document: *const u8, len: usize) -> *mut c_char {
+    match bytes(document, len) {
+        Ok(document) => CString::new(generate_id_bytes(&document))
+            .map(CString::into_raw)
+            .unwrap_or(ptr::null_mut()),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Encrypt `len` bytes at `data`, the ciphertext is written to `out`.
+#[no_mangle]
+pub unsafe extern "C" fn ss_encrypt(
+    store: *const SsStore,
+    id: *const c_char,
+    data: *const u8,
+    len: usize,
+    threshold: u32,
+    out: *mut SsBuffer,
+    error: *mut *mut c_char,
+) -> SsStatus {
+    buffer_call(store, encrypt_input(id, data, len, threshold), out, error)
+}
+
+/// Decrypt a ciphertext of `ss_encrypt`, the document is written to `out`.
+#[no_mangle]
+pub unsafe extern "C" fn ss_decrypt(
+    store: *const SsStore,
+    id: *const c_char,
+    data: *const u8,
+    len: usize,
+    out: *mut SsBuffer,
+    error: *mut *mut c_char,
+) -> SsStatus {
+    buffer_call(store, decrypt_input(id, data, len), out, error)
+}
+
+unsafe fn buffer_call(
+    store: *const SsStore,
+    input: Input,
+    out: *mut SsBuffer,
+    error: *mut *mut c_char,
+) -> SsStatus {
+    if out.is_null() {
+        set_error(error, &format_err!("out is NULL"));
+        return SsStatus::InvalidArgument;
+    }
+    *out = SsBuffer::empty();
+    match call(store, input, error) {
+        Ok(Output::Buffer(bytes)) => {
+            *out = SsBuffer::new(bytes);
+            SsStatus::Ok
+        }
+        Ok(_) => SsStatus::Other,
+        Err(status) => status,
+    }
+}
+
+/// Allow the `count` addresses in `addresses` to decrypt the document.
+#[no_mangle]
+pub unsafe extern "C" fn ss_allow_access(
+    store: *const SsStore,
+    id: *const c_char,
+    addresses: *const *const c_char,
+    count: usize,
+    error: *mut *mut c_char,
+) -> SsStatus {
+    match call(store, allow_access_input(id, addresses, count), error) {
+        Ok(_) => SsStatus::Ok,
+        Err(status) => status,
+    }
+}
+
+/// Whether `address` can decrypt the document right now.
+#[no_mangle]
+pub unsafe extern "C" fn ss_check_permissions(
+    store: *const SsStore,
+    address: *const c_char,
+    id: *const c_char,
+    allowed: *mut bool,
+    error: *mut *mut c_char,
+) -> SsStatus {
+    if allowed.is_null() {
+        set_error(error, &format_err!("allowed is NULL"));
+        return SsStatus::InvalidArgument;
+    }
+    *allowed = false;
+    match call(store, check_input(address, id), error) {
+        Ok(Output::Allowed(value)) => {
+            *allowed = value;
+            SsStatus::Ok
+        }
+        Ok(_) => SsStatus::Other,
+        Err(status) => status,
+    }
+}
+
+fn buffer_callback(
+    callback: SsBufferCallback,
+    user_data: *mut c_void,
+) -> impl FnOnce(Result<Output, (SsStatus, Error)>) + Send + 'static {
+    let user_data = UserData(user_data);
+    move |result| {
+        let (status, buffer, error) = match result {
+            Ok(Output::Buffer(bytes)) => (SsStatus::Ok, SsBuffer::new(bytes), ptr::null_mut()),
+            Ok(_) => (SsStatus::Other, SsBuffer::empty(), ptr::null_mut()),
+            Err((status, error)) => (status, SsBuffer::empty(), message(&error)),
+        };
+        match callback {
+            Some(callback) => unsafe { callback(user_data.0, status, buffer, error) },
+            None => unsafe {
+                ss_buffer_free(buffer);
+                ss_string_free(error);
+            },
+        }
+    }
+}
+
+/// Like `ss_encrypt`, the callback owns the buffer and the error message.
+#[no_mangle]
+pub unsafe extern "C" fn ss_encrypt_async(
+    store: *const SsStore,
+    id: *const c_char,
+    data: *const u8,
+    len: usize,
+    threshold: u32,
+    callback: SsBufferCallback,
+    user_data: *mut c_void,
+) {
+    spawn(
+        store,
+        encrypt_input(id, data, len, threshold),
+        buffer_callback(callback, user_data),
+    )
+}
+
+/// Like `ss_decrypt`, the callback owns the buffer and the error message.
+#[no_mangle]
+pub unsafe extern "C" fn ss_decrypt_async(
+    store: *const SsStore,
+    id: *const c_char,
+    data: *const u8,
+    len: usize,
+    callback: SsBufferCallback,
+    user_data: *mut c_void,
+) {
+    spawn(
+        store,
+        decrypt_input(id, data, len),
+        buffer_callback(callback, user_data),
+    )
+}
+
+/// Like `ss_allow_access`, the callback owns the error message.
+#[no_mangle]
+pub unsafe extern "C" fn ss_allow_access_async(
+    store: *const SsStore,
+    id: *const c_char,
+    addresses: *const *const c_char,
+    count: usize,
+    callback: SsStatusCallback,
+    user_data: *mut c_void,
+) {
+    let user_data = UserData(user_data);
+    spawn(
+        store,
+        allow_access_input(id, addresses, count),
+        move |result| {
+            let (status, error) = match result {
+                Ok(_) => (SsStatus::Ok, ptr::null_mut()),
+                Err((status, error)) => (status, message(&error)),
+            };
+            match callback {
+                Some(callback) => callback(user_data.0, status, error),
+                None => ss_string_free(error),
+            }
+        },
+    )
+}
+
+/// Like `ss_check_permissions`, the callback owns the error message.
+#[no_mangle]
+pub unsafe extern "C" fn ss_check_permissions_async(
+    store: *const SsStore,
+    address: *const c_char,
+    id: *const c_char,
+    callback: SsCheckCallback,
+    user_data: *mut c_void,
+) {
+    let user_data = UserData(user_data);
+    spawn(store, check_input(address, id), move |result| {
+        let (status, allowed, error) = match result {
+            Ok(Output::Allowed(allowed)) => (SsStatus::Ok, allowed, ptr::null_mut()),
+            Ok(_) => (SsStatus::Other, false, ptr::null_mut()),
+            Err((status, error)) => (status, false, message(&error)),
+        };
+        match callback {
+            Some(callback) => callback(user_data.0, status, allowed, error),
+            None => ss_string_free(error),
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ss_buffer_free(buffer: SsBuffer) {
+    if !buffer.data.is_null() {
+        drop(Box::from_raw(slice::from_raw_parts_mut(
+            buffer.data,
+            buffer.len,
+        )));
+    }
+}
+
+/// Frees error messages and document IDs.
+#[no_mangle]
+pub unsafe extern "C" fn ss_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto_secretstore::generate_id;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn invalid_arguments() {
+        unsafe {
+            let mut error = ptr::null_mut();
+            let mut out = SsBuffer::empty();
+            let status = ss_encrypt(
+                ptr::null(),
+                ptr::null(),
+                ptr::null(),
+                0,
+                1,
+                &mut out,
+                &mut error,
+            );
+            assert_eq!(status, SsStatus::InvalidArgument);
+            assert!(!error.is_null());
+            assert!(out.data.is_null());
+            ss_string_free(error);
+
+            let id = ss_generate_id(b"Hello world".as_ptr(), 11);
+            assert_eq!(
+                CStr::from_ptr(id).to_str().unwrap(),
+                generate_id("Hello world")
+            );
+            ss_string_free(id);
+
+            /* Different invalid UTF-8 must not end up with the same ID */
+            let first = ss_generate_id([0xff, 0x00].as_ptr(), 2);
+            let second = ss_generate_id([0xfe, 0x00].as_ptr(), 2);
+            assert_ne!(CStr::from_ptr(first), CStr::from_ptr(second));
+            ss_string_free(first);
+            ss_string_free(second);
+        }
+    }
+
+    #[test]
+    fn pool_is_bounded() {
+        let pool = Pool::new(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..8 {
+            let (running, most, sender) = (running.clone(), most.clone(), sender.clone());
+            pool.execute(Box::new(move || {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                most.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+                sender.send(()).unwrap();
+            }));
+        }
+        /* Queued jobs still run after the pool is dropped */
+        drop(pool);
+        for _ in 0..8 {
+            receiver.recv().unwrap();
+        }
+        assert!(most.load(Ordering::SeqCst) <= 2);
+    }
+
+    unsafe extern "C" fn check_done(
+        user_data: *mut c_void,
+        status: SsStatus,
+        allowed: bool,
+        error: *mut c_char,
+    ) {
+        let sender = Box::from_raw(user_data as *mut mpsc::Sender<(SsStatus, bool)>);
+        ss_string_free(error);
+        sender.send((status, allowed)).unwrap();
+    }
+
+    #[test]
+    fn encrypt_and_check() {
+        let address = CString::new("27d39a0fe767025e7ea0f78dccd4665929e3a8f2").unwrap();
+        let password = CString::new("alicepwd").unwrap();
+        let other = CString::new(format!("{:x}", H160::random())).unwrap();
+        let document = b"Hello world";
+
+        unsafe {
+            let mut store = ptr::null_mut();
+            let status = ss_store_new(
+                address.as_ptr(),
+                password.as_ptr(),
+                ptr::null(),
+                &mut store,
+                ptr::null_mut(),
+            );
+            assert_eq!(status, SsStatus::Ok);
+
+            let id = CString::new(crypto_secretstore::DocumentId::random().to_string()).unwrap();
+            let mut ciphertext = SsBuffer::empty();
+            let status = ss_encrypt(
+                store,
+                id.as_ptr(),
+                document.as_ptr(),
+                document.len(),
+                1,
+                &mut ciphertext,
+                ptr::null_mut(),
+            );
+            assert_eq!(status, SsStatus::Ok);
+            /* The ACL contract has to give the owner access as well */
+            let owner = [address.as_ptr()];
+            let status = ss_allow_access(store, id.as_ptr(), owner.as_ptr(), 1, ptr::null_mut());
+            assert_eq!(status, SsStatus::Ok);
+            let mut plaintext = SsBuffer::empty();
+            let status = ss_decrypt(
+                store,
+                id.as_ptr(),
+                ciphertext.data,
+                ciphertext.len,
+                &mut plaintext,
+                ptr::null_mut(),
+            );
+            assert_eq!(status, SsStatus::Ok);
+            assert_eq!(
+                slice::from_raw_parts(plaintext.data, plaintext.len),
+                document
+            );
+            ss_buffer_free(ciphertext);
+            ss_buffer_free(plaintext);
+
+            let addresses = [other.as_ptr()];
+            let status =
+                ss_allow_access(store, id.as_ptr(), addresses.as_ptr(), 1, ptr::null_mut());
+            assert_eq!(status, SsStatus::Ok);
+
+            let (sender, receiver) = mpsc::channel();
+            let sender = Box::into_raw(Box::new(sender)) as *mut c_void;
+            ss_check_permissions_async(
+                store,
+                other.as_ptr(),
+                id.as_ptr(),
+                Some(check_done),
+                sender,
+            );
+            ss_store_free(store);
+            assert_eq!(receiver.recv().unwrap(), (SsStatus::Ok, true));
+        }
+    }
+}
@@ -0,0 +1,320 @@
+/* Python extension module, build it in this directory with `maturin build
+ * --cargo-extra-args="--features extension-module"`. Errors are raised as SecretStoreError or
+ * one of its subclasses, their args are the message, the kind of the Secret Store error and
+ * its HTTP status */
+use primitive_types::H160;
+use pyo3::create_exception;
+use pyo3::exceptions;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyString};
+use pyo3::wrap_pyfunction;
+use secretstore::{
+    Config, CryptoSecretStore, ErrorClass, LocalAcl, LocalNode, ReferenceKeyServer, ShadowError,
+    StaticPassword,
+};
+use std::str::FromStr;
+
+create_exception!(crypto_secretstore, SecretStoreError, exceptions::Exception);
+create_exception!(crypto_secretstore, AccessDeniedError, SecretStoreError);
+create_exception!(crypto_secretstore, KeyNotFoundError, SecretStoreError);
+create_exception!(crypto_secretstore, KeyExistsError, SecretStoreError);
+create_exception!(crypto_secretstore, InvalidThresholdError, SecretStoreError);
+/* The request can be retried later */
+create_exception!(crypto_secretstore, UnavailableError, SecretStoreError);
+
+fn error(error: failure::Error) -> PyErr {
+    let status = error
+        .downcast_ref::<ShadowError>()
+        .and_then(ShadowError::status);
+    let args = (error.to_string(), ErrorClass::kind_name(&error), status);
+    match ErrorClass::of(&error) {
+        ErrorClass::AccessDenied => PyErr::new::<AccessDeniedError, _>(args),
+        ErrorClass::KeyNotFound => PyErr::new::<KeyNotFoundError, _>(args),
+        ErrorClass::KeyExists => PyErr::new::<KeyExistsError, _>(args),
+        ErrorClass::InvalidThreshold => PyErr::new::<InvalidThresholdError, _>(args),
+        ErrorClass::Unavailable => PyErr::new::<UnavailableError, _>(args),
+        ErrorClass::Other => PyErr::new::<SecretStoreError, _>(args),
+    }
+}
+
+fn parse_address(address: &str) -> PyResult<H160> {
+    H160::from_str(address.trim_start_matches("0x"))
+        .map_err(|_| exceptions::ValueError::py_err(format!("Invalid address {}", address)))
+}
+
+fn config(path: Option<&str>) -> PyResult<Config> {
+    match path {
+        Some(path) => Config::from_file(path).map_err(error),
+        None => Ok(Config::default()),
+    }
+}
+
+enum Backend {
+    Node(CryptoSecretStore),
+    /* Grants, keys and the account are kept locally, no node is needed */
+    Mock(CryptoSecretStore<LocalAcl, ReferenceKeyServer, LocalNode>),
+}
+
+/* The GIL is released while the store waits for the node and the Secret Store */
+macro_rules! with_store {
+    ($self:ident, $py:ident, $store:ident => $body:expr) => {
+        match &$self.backend {
+            Backend::Node($store) => $py.allow_threads(|| $body),
+            Backend::Mock($store) => $py.allow_threads(|| $body),
+        }
+    };
+}
+
+/// Access of an address to a document, returned by `SecretStore.check_permissions`.
+#[pyclass]
+#[derive(Clone)]
+pub struct Grant {
+    #[pyo3(get)]
+    allowed: bool,
+    #[pyo3(get)]
+    valid_from: Option<u64>,
+    #[pyo3(get)]
+    valid_until: Option<u64>,
+}
+
+impl From<secretstore::Grant> for Grant {
+    fn from(grant: secretstore::Grant) -> Self {
+        Grant {
+            allowed: grant.is_allowed(),
+            valid_from: grant.valid_from,
+            valid_until: grant.valid_until,
+        }
+    }
+}
+
+/// `CryptoSecretStore` of one account.
+#[pyclass]
+pub struct SecretStore {
+    backend: Backend,
+}
+
+#[pymethods]
+impl SecretStore {
+    /* config is the path of a TOML config file, like the one of secretstore-cli */
+    #[new]
+    #[args(config = "None")]
+    fn new(address: &str, password: &str, config: Option<&str>) -> PyResult<Self> {
+        let store = CryptoSecretStore::with_config(address, password, &self::config(config)?)
+            .map_err(error)?;
+        Ok(SecretStore {
+            backend: Backend::Node(store),
+        })
+    }
+
+    /* Store with LocalAcl, ReferenceKeyServer and LocalNode instead of the ACL contract, the
+     * Secret Store and the node. The account has a random key unless secret (hex) is given,
+     * grants are kept in acl_file if it's given */
+    #[staticmethod]
+    #[args(secret = "None", config = "None", nodes = 3, acl_file = "None")]
+    fn mock(
+        password: &str,
+        secret: Option<&str>,
+        config: Option<&str>,
+        nodes: usize,
+        acl_file: Option<&str>,
+    ) -> PyResult<Self> {
+        let acl = match acl_file {
+            Some(path) => LocalAcl::open(path).map_err(error)?,
+            None => LocalAcl::in_memory(),
+        };
+        let node = LocalNode::new();
+        let address = match secret {
+            Some(secret) => {
+                let mut key = [0u8; 32];
+                hex::decode_to_slice(secret.trim_start_matches("0x"), &mut key).map_err(|_| {
+                    exceptions::ValueError::py_err("secret must be 32 bytes of hex")
+                })?;
+                node.add_account(&key, password).map_err(error)?
+            }
+            None => node.new_account(password),
+        };
+        let store = CryptoSecretStore::with_node(
+            &format!("{:x}", address),
+            StaticPassword::new(password),
+            acl.clone(),
+            ReferenceKeyServer::new(nodes, &acl),
+            node,
+            &self::config(config)?,
+        )
+        .map_err(error)?;
+        Ok(SecretStore {
+            backend: Backend::Mock(store),
+        })
+    }
+
+    #[getter]
+    fn address(&self) -> String {
+        match &self.backend {
+            Backend::Node(store) => format!("{:x}", store.address()),
+            Backend::Mock(store) => format!("{:x}", store.address()),
+        }
+    }
+
+    fn generate_id(&self, document: &str) -> String {
+        secretstore::generate_id(document)
+    }
+
+    /* A str is encrypted to a str, bytes are encrypted in chunks like encrypt_stream */
+    #[args(threshold = 1)]
+    fn encrypt(
+        &self,
+        py: Python,
+        id: &str,
+        document: &PyAny,
+        threshold: u32,
+    ) -> PyResult<PyObject> {
+        if let Ok(document) = document.downcast::<PyString>() {
+            let document = document.to_str()?;
+            let ciphertext = with_store!(self, py, store => store.encrypt(id, document, threshold))
+                .map_err(error)?;
+            Ok(ciphertext.to_object(py))
+        } else if let Ok(document) = document.downcast::<PyBytes>() {
+            let mut reader = document.as_bytes();
+            let mut ciphertext = Vec::new();
+            with_store!(self, py, store => store.encrypt_stream(id, &mut reader, &mut ciphertext, threshold))
+                .map_err(error)?;
+            Ok(PyBytes::new(py, &ciphertext).to_object(py))
+        } else {
+            Err(exceptions::TypeError::py_err(
+                "document must be str or bytes",
+            ))
+        }
+    }
+
+    /* Returns the type which was encrypted */
+    fn decrypt(&self, py: Python, id: &str, ciphertext: &PyAny) -> PyResult<PyObject> {
+        if let Ok(ciphertext) = ciphertext.downcast::<PyString>() {
+            let ciphertext = ciphertext.to_str()?;
+            let document =
+                with_store!(self, py, store => store.decrypt(id, ciphertext)).map_err(error)?;
+            Ok(document.to_object(py))
+        } else if let Ok(ciphertext) = ciphertext.downcast::<PyBytes>() {
+            let mut reader = ciphertext.as_bytes();
+            let mut document = Vec::new();
+            with_store!(self, py, store => store.decrypt_stream(id, &mut reader, &mut document))
+                .map_err(error)?;
+            Ok(PyBytes::new(py, &document).to_object(py))
+        } else {
+            Err(exceptions::TypeError::py_err(
+                "ciphertext must be str or bytes",
+            ))
+        }
+    }
+
+    /* Returns the hash of the transaction */
+    fn allow_access(&self, py: Python, id: &str, addresses: Vec<&str>) -> PyResult<String> {
+        let addresses = addresses
+            .into_iter()
+            .map(parse_address)
+            .collect::<PyResult<Vec<_>>>()?;
+        let result =
+            with_store!(self, py, store => store.allow_access(id, &addresses)).map_err(error)?;
+        Ok(format!("{:?}", result.hash()))
+    }
+
+    fn check_permissions(&self, py: Python, address: &str, id: &str) -> PyResult<Grant> {
+        let address = parse_address(address)?;
+        let grant =
+            with_store!(self, py, store => store.check_permissions(address, id)).map_err(error)?;
+        Ok(grant.into())
+    }
+}
+
+#[pyfunction]
+fn generate_id(document: &str) -> String {
+    secretstore::generate_id(document)
+}
+
+#[pymodule]
+fn crypto_secretstore(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<SecretStore>()?;
+    m.add_class::<Grant>()?;
+    m.add_wrapped(wrap_pyfunction!(generate_id))?;
+    m.add("SecretStoreError", py.get_type::<SecretStoreError>())?;
+    m.add("AccessDeniedError", py.get_type::<AccessDeniedError>())?;
+    m.add("KeyNotFoundError", py.get_type::<KeyNotFoundError>())?;
+    m.add("KeyExistsError", py.get_type::<KeyExistsError>())?;
+    m.add(
+        "InvalidThresholdError",
+        py.get_type::<InvalidThresholdError>(),
+    )?;
+    m.add("UnavailableError", py.get_type::<UnavailableError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use failure::format_err;
+    use pyo3::types::PyDict;
+    use secretstore::{ShadowErrorKind, ThresholdError};
+
+    #[test]
+    fn error_classes() {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+
+        let err = error(ShadowError::new(ShadowErrorKind::AccessDenied).into());
+        assert!(err.is_instance::<AccessDeniedError>(py));
+        assert!(err.is_instance::<SecretStoreError>(py));
+        let err = error(ShadowError::new(ShadowErrorKind::ConsensusTemporaryUnreachable).into());
+        assert!(err.is_instance::<UnavailableError>(py));
+        let err = error(ThresholdError::UnknownClusterSize.into());
+        assert!(err.is_instance::<InvalidThresholdError>(py));
+        let err = error(format_err!("Sender not authorized."));
+        assert!(err.is_instance::<SecretStoreError>(py));
+        assert!(!err.is_instance::<AccessDeniedError>(py));
+    }
+
+    #[test]
+    fn mock_store() {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+        let locals = PyDict::new(py);
+        locals
+            .set_item("SecretStore", py.get_type::<SecretStore>())
+            .unwrap();
+        locals
+            .set_item("KeyExistsError", py.get_type::<KeyExistsError>())
+            .unwrap();
+
+        py.run(
+            r#"
+store = SecretStore.mock("alicepwd")
+id = store.generate_id("Hello world")
+ciphertext = store.encrypt(id, "Hello world")
+store.allow_access(id, [store.address])
+assert store.decrypt(id, ciphertext) == "Hello world"
+
+try:
+    store.encrypt(id, "Other document")
+    assert False
+except KeyExistsError as error:
+    assert error.args[1] == "key_already_stored"
+
+data = bytes(range(256)) * 100
+ciphertext = store.encrypt("0x" + "11" * 32, data)
+assert isinstance(ciphertext, bytes)
+store.allow_access("0x" + "11" * 32, [store.address])
+assert store.decrypt("0x" + "11" * 32, ciphertext) == data
+
+user = "0x" + "22" * 20
+assert not store.check_permissions(user, id).allowed
+store.allow_access(id, [user])
+assert store.check_permissions(user, id).allowed
+
+secret = "11" * 32
+assert SecretStore.mock("alicepwd", secret).address == SecretStore.mock("bobpwd", secret).address
+"#,
+            None,
+            Some(locals),
+        )
+        .map_err(|err| err.print(py))
+        .unwrap();
+    }
+}
@@ -0,0 +1,810 @@
+/* Who may read which document. The Secret Store asks the SSPermissions contract, so
+ * EthereumAcl is what production setups use. LocalAcl keeps the grants in memory or in a
+ * file, for tests and for single node setups where the Secret Store runs without an ACL
+ * contract (acl_contract = "none"), there the grants are only checked by this crate */
+use crate::api::contract::deploy_new_contract;
+pub use crate::api::contract::AclContract;
+use crate::api::transport::{self, RpcTransport};
+use crate::config::Config;
+use crate::grant::{self, AccessEvent, Grant};
+use crate::permit::{keccak256, SignedPermit};
+use crate::Password;
+use async_trait::async_trait;
+use ethcontract::transaction::{Account, TransactionResult};
+use failure::{format_err, Error, SyncFailure};
+use futures::compat::Future01CompatExt;
+use log::warn;
+use primitive_types::{H160, H256, U256};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use web3::futures::Future;
+use web3::types::{BlockId, BlockNumber, Bytes, CallRequest, FilterBuilder};
+use web3::Web3;
+use zeroize::Zeroize;
+
+/* All identities of a store share one instance, implementations lock their own state */
+#[async_trait(?Send)]
+pub trait AccessControl {
+    /* Give users access to the document between two unix timestamps, None means no limit.
+     * The sender becomes the owner if the document doesn't have one yet */
+    async fn grant(
+        &self,
+        sender: H160,
+        password: &Password,
+        document_id: H256,
+        users: &[H160],
+        valid_from: Option<u64>,
+        valid_until: Option<u64>,
+    ) -> Result<TransactionResult, Error>;
+
+    /* Only the owner can revoke access */
+    async fn revoke(
+        &self,
+        sender: H160,
+        password: &Password,
+        document_id: H256,
+        users: &[H160],
+    ) -> Result<TransactionResult, Error>;
+
+    /* Grant users access to many documents at once, a single transaction where possible.
+     * The documents have to fit into one transaction, see documents_per_transaction. Without
+     * batch support every document is a transaction of its own, so only one is taken */
+    async fn grant_many(
+        &self,
+        sender: H160,
+        password: &Password,
+        document_ids: &[H256],
+        users: &[H160],
+    ) -> Result<TransactionResult, Error> {
+        match document_ids {
+            [document_id] => {
+                self.grant(sender, password, *document_id, users, None, None)
+                    .await
+            }
+            _ => Err(format_err!(
+                "{} documents given, only one fits into a transaction",
+                document_ids.len()
+            )),
+        }
+    }
+
+    async fn revoke_many(
+        &self,
+        sender: H160,
+        password: &Password,
+        document_ids: &[H256],
+        users: &[H160],
+    ) -> Result<TransactionResult, Error> {
+        match document_ids {
+            [document_id] => self.revoke(sender, password, *document_id, users).await,
+            _ => Err(format_err!(
+                "{} documents given, only one fits into a transaction",
+                document_ids.len()
+            )),
+        }
+    }
+
+    /* How many documents with these users fit into one grant_many or revoke_many */
+    async fn documents_per_transaction(
+        &self,
+        _sender: H160,
+        _users: &[H160],
+    ) -> Result<usize, Error> {
+        Ok(1)
+    }
+
+    async fn check(&self, user: H160, document_id: H256) -> Result<Grant, Error>;
+
+    /* The zero address if the document doesn't have an owner */
+    async fn owner(&self, document_id: H256) -> Result<H160, Error>;
+
+    /* Users with a grant, the owner isn't included */
+    async fn members(&self, document_id: H256) -> Result<Vec<H160>, Error>;
+
+    async fn member_count(&self, document_id: H256) -> Result<u64, Error> {
+        Ok(self.members(document_id).await?.len() as u64)
+    }
+
+    async fn members_page(
+        &self,
+        document_id: H256,
+        start: u64,
+        count: u64,
+    ) -> Result<Vec<H160>, Error> {
+        Ok(self
+            .members(document_id)
+            .await?
+            .into_iter()
+            .skip(start as usize)
+            .take(count as usize)
+            .collect())
+    }
+
+    /* Whether grants can be sent right now */
+    async fn is_reachable(&self) -> bool {
+        true
+    }
+
+    /* None while the transaction isn't final, otherwise whether it succeeded */
+    async fn transaction_status(&self, _transaction: H256) -> Result<Option<bool>, Error> {
+        Ok(Some(true))
+    }
+}
+
+/* An account unlocked on the node, the fingerprint of the password is kept so the unlock
+ * isn't used with other credentials for the same address */
+struct Unlock {
+    until: Instant,
+    fingerprint: [u8; 32],
+}
+
+/// Grants kept in the SSPermissions contract.
+pub struct EthereumAcl {
+    contract: AclContract,
+    web3: Web3<RpcTransport>,
+    unlock_duration: u16,
+    /* Requests of different identities run at the same time */
+    unlocked: Mutex<HashMap<H160, Unlock>>,
+    fingerprint_key: [u8; 32],
+}
+
+impl EthereumAcl {
+    /* Address and password of the user who creates the initial contract
+     * TODO: shouldn't be required since we create the contract on the setup of the network */
+    pub fn new(address: H160, password: &Password, config: &Config) -> Result<EthereumAcl, Error> {
+        let transport = RpcTransport::new(
+            config.rpc_url.as_str(),
+            transport::shared_client(&config.transport)?,
+        )?;
+        let web3 = web3::Web3::new(transport);
+        let account = Account::Locked(address, password.into(), None);
+
+        let contract = AclContract::new(account, &web3, &config.contract)?;
+
+        Ok(EthereumAcl {
+            contract,
+            web3,
+            unlock_duration: config.unlock_duration,
+            unlocked: Mutex::new(HashMap::new()),
+            fingerprint_key: rand::random(),
+        })
+    }
+
+    /* Deploy a new ACL contract and write its address to the configured address file */
+    pub fn deploy(address: H160, password: &Password, config: &Config) -> Result<H160, Error> {
+        let transport = RpcTransport::new(
+            config.rpc_url.as_str(),
+            transport::shared_client(&config.transport)?,
+        )?;
+        let web3 = web3::Web3::new(transport);
+        let account = Account::Locked(address, password.into(), None);
+        let contract =
+            futures::executor::block_on(deploy_new_contract(account, &web3, &config.contract))?;
+        Ok(contract.address())
+    }
+
+    /* Account used to send contract transactions. The account is unlocked on the node for
+     * unlock_duration seconds, so the password isn't part of every transaction. If the node
+     * doesn't allow timed unlocking the password is sent with the transaction instead.
+     * The secretstore_* RPC calls always need the password, that's how the API is defined */
+    async fn account(&self, address: H160, password: &Password) -> Account {
+        if self.unlock_duration == 0 {
+            return Account::Locked(address, password.into(), None);
+        }
+
+        /* Leave some time for the transaction to reach the node */
+        let margin = Duration::from_secs(5);
+        let fingerprint = self.fingerprint(password);
+        if let Some(unlock) = self.unlocked.lock().unwrap().get(&address) {
+            if unlock.fingerprint == fingerprint && unlock.until > Instant::now() + margin {
+                return Account::Local(address, None);
+            }
+        }
+
+        let now = Instant::now();
+        match self
+            .web3
+            .personal()
+            .unlock_account(address, password, Some(self.unlock_duration))
+            .compat()
+            .await
+        {
+            Ok(true) => {
+                self.unlocked.lock().unwrap().insert(
+                    address,
+                    Unlock {
+                        until: now + Duration::from_secs(self.unlock_duration.into()),
+                        fingerprint,
+                    },
+                );
+                Account::Local(address, None)
+            }
+            _ => {
+                warn!(
+                    "Unlocking account {:?} failed, send password with the transaction",
+                    address
+                );
+                Account::Locked(address, password.into(), None)
+            }
+        }
+    }
+
+    fn fingerprint(&self, password: &Password) -> [u8; 32] {
+        let mut input = self.fingerprint_key.to_vec();
+        input.extend_from_slice(password.as_bytes());
+        let fingerprint = keccak256(&input);
+        input.zeroize();
+        fingerprint
+    }
+
+    /* Number of key servers in the KeyServerSet contract of the Secret Store */
+    pub fn server_set_size(&self, server_set_contract: H160) -> Result<usize, Error> {
+        /* getCurrentKeyServers() returns address[], encoded as offset, length and entries */
+        let selector = keccak256(b"getCurrentKeyServers()")[..4].to_vec();
+        let request = CallRequest {
+            from: None,
+            to: Some(server_set_contract),
+            gas: None,
+            gas_price: None,
+            value: None,
+            data: Some(Bytes(selector)),
+        };
+        let output = self
+            .web3
+            .eth()
+            .call(request, None)
+            .wait()
+            .map_err(SyncFailure::new)?;
+        if output.0.len() < 64 {
+            return Err(format_err!(
+                "Invalid response of the server set contract at {:?}",
+                server_set_contract
+            ));
+        }
+        let count = U256::from_big_endian(&output.0[32..64]);
+        if count > U256::from(u32::MAX) {
+            return Err(format_err!(
+                "Invalid number of key servers {} in the server set contract at {:?}",
+                count,
+                server_set_contract
+            ));
+        }
+        Ok(count.low_u64() as usize)
+    }
+
+    /* Remove the grants of addresses which ran out, this emits AccessExpired events */
+    pub async fn expire(
+        &self,
+        sender: H160,
+        password: &Password,
+        document_id: H256,
+        users: &[H160],
+    ) -> Result<TransactionResult, Error> {
+        let account = self.account(sender, password).await;
+        let result = self
+            .contract
+            .expire(document_id.into(), users.into())
+            .from(account)
+            .gas(8000000.into())
+            .send()
+            .await
+            .map_err(SyncFailure::new)?;
+        Ok(result)
+    }
+
+    /* Grant changes since from_block with the block they're in, optionally only for one
+     * document. Poll with the last returned block + 1 to follow new events */
+    pub async fn access_events(
+        &self,
+        from_block: u64,
+        document_id: Option<H256>,
+    ) -> Result<Vec<(u64, AccessEvent)>, Error> {
+        let filter = FilterBuilder::default()
+            .address(vec![self.contract.address()])
+            .from_block(BlockNumber::Number(from_block.into()))
+            .topics(
+                Some(vec![
+                    grant::granted_topic(),
+                    grant::revoked_topic(),
+                    grant::expired_topic(),
+                ]),
+                document_id.map(|id| vec![id]),
+                None,
+                None,
+            )
+            .build();
+        let logs = self
+            .web3
+            .eth()
+            .logs(filter)
+            .compat()
+            .await
+            .map_err(SyncFailure::new)?;
+        Ok(logs
+            .iter()
+            .filter_map(|log| {
+                let block = log.block_number?.as_u64();
+                AccessEvent::from_log(log).map(|event| (block, event))
+            })
+            .collect())
+    }
+
+    /* Nonce and EIP-712 domain separator needed to sign a permit of owner */
+    pub async fn permit_domain(&self, owner: H160) -> Result<(U256, H256), Error> {
+        let nonce = self
+            .contract
+            .nonces(owner)
+            .call()
+            .await
+            .map_err(SyncFailure::new)?;
+        let domain_separator = self
+            .contract
+            .domain_separator()
+            .call()
+            .await
+            .map_err(SyncFailure::new)?;
+        Ok((nonce, H256::from(domain_separator)))
+    }
+
+    /* Submit a permit signed by the document owner, the relayer pays for the gas */
+    pub async fn submit_permit(
+        &self,
+        relayer: H160,
+        password: &Password,
+        signed_permit: &SignedPermit,
+    ) -> Result<TransactionResult, Error> {
+        let permit = &signed_permit.permit;
+        let account = self.account(relayer, password).await;
+        let result = self
+            .contract
+            .allow_access_with_permit(
+                permit.document_id.into(),
+                permit.users.clone(),
+                permit.owner,
+                permit.nonce,
+                permit.deadline,
+                signed_permit.v,
+                signed_permit.r.into(),
+                signed_permit.s.into(),
+            )
+            .from(account)
+            .gas(8000000.into())
+            .send()
+            .await
+            .map_err(SyncFailure::new)?;
+        Ok(result)
+    }
+}
+
+#[async_trait(?Send)]
+impl AccessControl for EthereumAcl {
+    async fn grant(
+        &self,
+        sender: H160,
+        password: &Password,
+        document_id: H256,
+        users: &[H160],
+        valid_from: Option<u64>,
+        valid_until: Option<u64>,
+    ) -> Result<TransactionResult, Error> {
+        let account = self.account(sender, password).await;
+        if valid_from.is_some() || valid_until.is_some() {
+            let result = self
+                .contract
+                .allow_access_until(
+                    document_id.into(),
+                    users.into(),
+                    valid_from.unwrap_or_default().into(),
+                    valid_until.unwrap_or_default().into(),
+                )
+                .from(account)
+                .gas(8000000.into())
+                .send()
+                .await
+                .map_err(SyncFailure::new)?;
+            return Ok(result);
+        }
+
+        let result = self
+            .contract
+            .allow_access(document_id.into(), users.into())
+            .from(account)
+            .gas(8000000.into())
+            .send()
+            .await
+            .map_err(SyncFailure::new)?;
+        Ok(result)
+    }
+
+    async fn revoke(
+        &self,
+        sender: H160,
+        password: &Password,
+        document_id: H256,
+        users: &[H160],
+    ) -> Result<TransactionResult, Error> {
+        let account = self.account(sender, password).await;
+        let result = self
+            .contract
+            .revoke_access(document_id.into(), users.into())
+            .from(account)
+            .gas(8000000.into())
+            .send()
+            .await
+            .map_err(SyncFailure::new)?;
+        Ok(result)
+    }
+
+    async fn grant_many(
+        &self,
+        sender: H160,
+        password: &Password,
+        document_ids: &[H256],
+        users: &[H160],
+    ) -> Result<TransactionResult, Error> {
+        let account = self.account(sender, password).await;
+        let ids: Vec<[u8; 32]> = document_ids.iter().map(|id| id.to_fixed_bytes()).collect();
+        let gas = self
+            .contract
+            .allow_access_many(ids.clone(), users.into())
+            .from(account.clone())
+            .into_inner()
+            .estimate_gas()
+            .await
+            .map_err(SyncFailure::new)?;
+        let result = self
+            .contract
+            .allow_access_many(ids, users.into())
+            .from(account)
+            .gas(gas + gas / 10)
+            .send()
+            .await
+            .map_err(SyncFailure::new)?;
+        Ok(result)
+    }
+
+    async fn revoke_many(
+        &self,
+        sender: H160,
+        password: &Password,
+        document_ids: &[H256],
+        users: &[H160],
+    ) -> Result<TransactionResult, Error> {
+        let account = self.account(sender, password).await;
+        let ids: Vec<[u8; 32]> = document_ids.iter().map(|id| id.to_fixed_bytes()).collect();
+        let gas = self
+            .contract
+            .revoke_access_many(ids.clone(), users.into())
+            .from(account.clone())
+            .into_inner()
+            .estimate_gas()
+            .await
+            .map_err(SyncFailure::new)?;
+        let result = self
+            .contract
+            .revoke_access_many(ids, users.into())
+            .from(account)
+            .gas(gas + gas / 10)
+            .send()
+            .await
+            .map_err(SyncFailure::new)?;
+        Ok(result)
+    }
+
+    /* Estimated from granting a single new document, which includes the base cost of the
+     * transaction and claiming the document, so the result errs on the safe side. A document
+     * of the batch could already belong to somebody else, then the estimate would fail or
+     * be too low. Revoking costs less than granting. A quarter of the block gas limit is
+     * left for other transactions */
+    async fn documents_per_transaction(
+        &self,
+        sender: H160,
+        users: &[H160],
+    ) -> Result<usize, Error> {
+        let block = self
+            .web3
+            .eth()
+            .block(BlockId::Number(BlockNumber::Latest))
+            .compat()
+            .await
+            .map_err(SyncFailure::new)?
+            .ok_or_else(|| format_err!("The node didn't return the latest block"))?;
+        let per_document = self
+            .contract
+            .allow_access_many(vec![H256::random().to_fixed_bytes()], users.into())
+            .from(Account::Local(sender, None))
+            .into_inner()
+            .estimate_gas()
+            .await
+            .map_err(SyncFailure::new)?;
+        let limit = block.gas_limit / 4 * 3;
+        Ok((limit / per_document.max(1.into())).low_u64().max(1) as usize)
+    }
+
+    /* Whether the user has access right now, together with the validity window of the grant */
+    async fn check(&self, user: H160, document_id: H256) -> Result<Grant, Error> {
+        let allowed = self
+            .contract
+            .check_permissions(user, document_id.into())
+            .from(user)
+            .call()
+            .await
+            .map_err(SyncFailure::new)?;
+        let (_, valid_from, valid_until) = self
+            .contract
+            .get_grant(user, document_id.into())
+            .from(user)
+            .call()
+            .await
+            .map_err(SyncFailure::new)?;
+        Ok(Grant {
+            allowed,
+            valid_from: grant::timestamp(valid_from),
+            valid_until: grant::timestamp(valid_until),
+        })
+    }
+
+    async fn owner(&self, document_id: H256) -> Result<H160, Error> {
+        let owner = self
+            .contract
+            .owner_of(document_id.into())
+            .call()
+            .await
+            .map_err(SyncFailure::new)?;
+        Ok(owner)
+    }
+
+    async fn members(&self, document_id: H256) -> Result<Vec<H160>, Error> {
+        let members = self
+            .contract
+            .members_of(document_id.into())
+            .call()
+            .await
+            .map_err(SyncFailure::new)?;
+        Ok(members)
+    }
+
+    async fn member_count(&self, document_id: H256) -> Result<u64, Error> {
+        let count = self
+            .contract
+            .member_count(document_id.into())
+            .call()
+            .await
+            .map_err(SyncFailure::new)?;
+        Ok(count.low_u64())
+    }
+
+    /* The order changes when members are removed */
+    async fn members_page(
+        &self,
+        document_id: H256,
+        start: u64,
+        count: u64,
+    ) -> Result<Vec<H160>, Error> {
+        let members = self
+            .contract
+            .members_page(document_id.into(), start.into(), count.into())
+            .call()
+            .await
+            .map_err(SyncFailure::new)?;
+        Ok(members)
+    }
+
+    async fn is_reachable(&self) -> bool {
+        self.web3.eth().block_number().compat().await.is_ok()
+    }
+
+    async fn transaction_status(&self, transaction: H256) -> Result<Option<bool>, Error> {
+        let receipt = self
+            .web3
+            .eth()
+            .transaction_receipt(transaction)
+            .compat()
+            .await
+            .map_err(SyncFailure::new)?;
+        Ok(receipt.map(|receipt| receipt.status != Some(0.into())))
+    }
+}
+
+/// Hash returned by `LocalAcl` for its changes, there is no transaction behind them.
+pub const LOCAL_TRANSACTION: H256 = H256([0; 32]);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+struct LocalDocument {
+    owner: H160,
+    /* In the order they were granted, like the members of the contract */
+    grants: Vec<(H160, Option<u64>, Option<u64>)>,
+}
+
+/// Grants kept in memory or in a JSON file, with the same rules as the contract. Meant for
+/// tests: passwords aren't checked and nothing stops a caller from editing the file.
+/// Every change returns `LOCAL_TRANSACTION` instead of a transaction hash.
+/// Clones share the grants, e.g. with `ReferenceKeyServer::new`.
+#[derive(Debug, Clone, Default)]
+pub struct LocalAcl {
+    path: Option<PathBuf>,
+    docs: Arc<Mutex<HashMap<H256, LocalDocument>>>,
+}
+
+impl LocalAcl {
+    pub fn in_memory() -> LocalAcl {
+        LocalAcl::default()
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<LocalAcl, Error> {
+        let path = path.as_ref().to_path_buf();
+        let docs = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            HashMap::new()
+        };
+        Ok(LocalAcl {
+            path: Some(path),
+            docs: Arc::new(Mutex::new(docs)),
+        })
+    }
+
+    /* Whether user has access right now, what the Secret Store asks the ACL contract */
+    pub fn allows(&self, user: H160, document_id: H256) -> bool {
+        self.grant_of(user, document_id).allowed
+    }
+
+    fn grant_of(&self, user: H160, document_id: H256) -> Grant {
+        let docs = self.docs();
+        let doc = match docs.get(&document_id) {
+            Some(doc) => doc,
+            None => {
+                return Grant {
+                    allowed: false,
+                    valid_from: None,
+                    valid_until: None,
+                }
+            }
+        };
+        let (valid_from, valid_until) = doc
+            .grants
+            .iter()
+            .find(|(member, _, _)| *member == user)
+            .map(|(_, from, until)| (*from, *until))
+            .unwrap_or_default();
+        let granted = doc.grants.iter().any(|(member, _, _)| *member == user);
+        let now = now();
+        let allowed = doc.owner == user
+            || (granted
+                && valid_from.map_or(true, |from| from <= now)
+                && valid_until.map_or(true, |until| now < until));
+        Grant {
+            allowed,
+            valid_from,
+            valid_until,
+        }
+    }
+
+    fn docs(&self) -> MutexGuard<HashMap<H256, LocalDocument>> {
+        self.docs.lock().unwrap()
+    }
+
+    fn save(&self, docs: &HashMap<H256, LocalDocument>) -> Result<TransactionResult, Error> {
+        if let Some(path) = &self.path {
+            let tmp = path.with_extension("tmp");
+            fs::write(&tmp, serde_json::to_string_pretty(docs)?)?;
+            fs::rename(tmp, path)?;
+        }
+        Ok(TransactionResult::Hash(LOCAL_TRANSACTION))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default()
+}
+
+#[async_trait(?Send)]
+impl AccessControl for LocalAcl {
+    async fn grant(
+        &self,
+        sender: H160,
+        _password: &Password,
+        document_id: H256,
+        users: &[H160],
+        valid_from: Option<u64>,
+        valid_until: Option<u64>,
+    ) -> Result<TransactionResult, Error> {
+        if let (Some(from), Some(until)) = (valid_from, valid_until) {
+            if until <= from {
+                return Err(format_err!("Invalid validity window."));
+            }
+        }
+        let mut docs = self.docs();
+        let doc = docs.entry(document_id).or_default();
+        if doc.owner.is_zero() {
+            doc.owner = sender;
+        } else if doc.owner != sender {
+            return Err(format_err!("Sender not authorized."));
+        }
+
+        for user in users {
+            doc.grants.retain(|(member, _, _)| member != user);
+            doc.grants.push((*user, valid_from, valid_until));
+        }
+        self.save(&docs)
+    }
+
+    async fn revoke(
+        &self,
+        sender: H160,
+        _password: &Password,
+        document_id: H256,
+        users: &[H160],
+    ) -> Result<TransactionResult, Error> {
+        let mut docs = self.docs();
+        let doc = match docs.get_mut(&document_id) {
+            Some(doc) if doc.owner == sender => doc,
+            _ => return Err(format_err!("Sender not authorized.")),
+        };
+        doc.grants.retain(|(member, _, _)| !users.contains(member));
+        self.save(&docs)
+    }
+
+    async fn check(&self, user: H160, document_id: H256) -> Result<Grant, Error> {
+        Ok(self.grant_of(user, document_id))
+    }
+
+    async fn owner(&self, document_id: H256) -> Result<H160, Error> {
+        Ok(self
+            .docs()
+            .get(&document_id)
+            .map(|doc| doc.owner)
+            .unwrap_or_default())
+    }
+
+    async fn members(&self, document_id: H256) -> Result<Vec<H160>, Error> {
+        Ok(self
+            .docs()
+            .get(&document_id)
+            .map(|doc| doc.grants.iter().map(|(member, _, _)| *member).collect())
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    #[test]
+    fn local_grants() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("acl.json");
+        let owner = H160::repeat_byte(1);
+        let other = H160::repeat_byte(2);
+        let users = [H160::repeat_byte(3), H160::repeat_byte(4)];
+        let id = H256::repeat_byte(5);
+
+        let acl = LocalAcl::open(&path).unwrap();
+        assert!(block_on(acl.revoke(owner, "", id, &users)).is_err());
+        block_on(acl.grant(owner, "", id, &users, None, None)).unwrap();
+        assert!(block_on(acl.grant(other, "", id, &[other], None, None)).is_err());
+        assert!(block_on(acl.revoke(other, "", id, &users)).is_err());
+        block_on(acl.grant(owner, "", id, &[other], None, Some(1))).unwrap();
+
+        let acl = LocalAcl::open(&path).unwrap();
+        assert_eq!(block_on(acl.owner(id)).unwrap(), owner);
+        assert!(block_on(acl.check(owner, id)).unwrap().is_allowed());
+        assert!(block_on(acl.check(users[0], id)).unwrap().is_allowed());
+        let expired = block_on(acl.check(other, id)).unwrap();
+        assert!(!expired.is_allowed());
+        assert_eq!(expired.valid_until, Some(1));
+
+        block_on(acl.revoke(owner, "", id, &users[..1])).unwrap();
+        assert!(!block_on(acl.check(users[0], id)).unwrap().is_allowed());
+        assert_eq!(block_on(acl.members(id)).unwrap(), vec![users[1], other]);
+        assert_eq!(block_on(acl.members_page(id, 1, 5)).unwrap(), vec![other]);
+    }
+}
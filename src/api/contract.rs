@@ -1,47 +1,317 @@
+use crate::api::transport::RpcTransport;
+use crate::config::ContractConfig;
+use crate::permit::keccak256;
 use ethcontract::transaction::Account;
 use ethcontract::web3::api::Web3;
 use ethcontract::web3::types::*;
-use std::fs::File;
-use std::io::prelude::*;
+use futures::compat::Future01CompatExt;
+use log::{info, warn};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use web3::transports::Http;
+use web3::futures::Future;
+use web3::Transport;
 
 ethcontract::contract!("./SSPermissions.json", contract = AclContract);
 
-impl AclContract {
-    pub fn new(account: Account, web3: &Web3<Http>) -> Result<Self, failure::Error> {
-        let file = File::open("./contract-address.txt");
-        let contract_address = file.and_then(|mut file| {
-            let mut contents = String::new();
-            let _ = file.read_to_string(&mut contents);
-            Ok(contents)
-        });
+const ARTIFACT: &str = include_str!("../../SSPermissions.json");
+
+#[derive(Debug)]
+pub enum Error {
+    NoCode(H160),
+    BytecodeMismatch(H160),
+    CodeHashMismatch {
+        expected: H256,
+        found: H256,
+    },
+    ChainIdMismatch {
+        expected: u64,
+        found: u64,
+    },
+    /// The address file has no chain ID and none is configured
+    UnknownChainId(PathBuf),
+    InvalidAddressFile(PathBuf),
+    InvalidArtifact,
+    /// SSPermissions.sol changed since SSPermissions.json was compiled
+    StaleArtifact,
+    Io(std::io::Error),
+    Web3(web3::Error),
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<web3::Error> for Error {
+    fn from(error: web3::Error) -> Self {
+        Error::Web3(error)
+    }
+}
 
-        let contract = if let Ok(address) = contract_address {
-            println!("Use contract at address loaded from file 'contract-address.txt'");
-            let contract_address =
-                H160::from_str(&address).expect("Error in contract-address.txt file");
-            /* Load contract from stored address. This doesn't perform any checks */
-            AclContract::at(web3, contract_address)
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::NoCode(address) => write!(f, "No contract deployed at {:?}.", address),
+            Error::BytecodeMismatch(address) => write!(
+                f,
+                "Contract at {:?} doesn't match the bytecode of SSPermissions.json.",
+                address
+            ),
+            Error::CodeHashMismatch { expected, found } => write!(
+                f,
+                "Contract code hash {:?} doesn't match the configured hash {:?}.",
+                found, expected
+            ),
+            Error::ChainIdMismatch { expected, found } => write!(
+                f,
+                "Node is on chain {} but chain {} is configured.",
+                found, expected
+            ),
+            Error::UnknownChainId(path) => write!(
+                f,
+                "'{}' has no chain ID, add it on the line after the address or set chain_id in the config.",
+                path.display()
+            ),
+            Error::InvalidAddressFile(path) => write!(
+                f,
+                "'{}' doesn't contain a contract address and a chain ID.",
+                path.display()
+            ),
+            Error::InvalidArtifact => write!(f, "SSPermissions.json has no deployed bytecode."),
+            Error::StaleArtifact => write!(
+                f,
+                "The bytecode of SSPermissions.json wasn't compiled from its source, run ./build-contract.sh."
+            ),
+            Error::Io(error) => write!(f, "Io: {}", error),
+            Error::Web3(error) => write!(f, "Web3: {:?}", error),
+        }
+    }
+}
+
+impl AclContract {
+    pub fn new(
+        account: Account,
+        web3: &Web3<RpcTransport>,
+        config: &ContractConfig,
+    ) -> Result<Self, failure::Error> {
+        let path = &config.address_file;
+        let contract = if let Some((address, chain_id)) = read_address_file(path)? {
+            info!(
+                "Use contract at address {:?} loaded from '{}'",
+                address,
+                path.display()
+            );
+            if config.skip_verification {
+                warn!("Contract verification is disabled, only do this on development chains");
+            } else {
+                verify_contract(web3, address, chain_id, config)?;
+            }
+            AclContract::at(web3, address)
         } else {
             /* TODO: this should be done when creating the network not here
             Deploy new contract if we don't have an address already */
-            println!("'contract-address.txt' doens't exsist, deploy new contract");
-            futures::executor::block_on(deploy_new_contract(account, web3))?
+            info!("'{}' doesn't exist, deploy new contract", path.display());
+            futures::executor::block_on(deploy_new_contract(account, web3, config))?
         };
-        println!("Use contract at address {:?}", contract.address());
         Ok(contract)
     }
 }
 
-async fn deploy_new_contract(
+/* The address file has the address of the contract on the first line and the chain ID it was
+ * deployed on on the second, files written before the chain ID was recorded only have the
+ * address. A missing or empty file means there's no contract yet */
+fn read_address_file(path: &Path) -> Result<Option<(H160, Option<u64>)>, Error> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error.into()),
+    };
+    let mut lines = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty());
+    let address = match lines.next() {
+        Some(address) => {
+            H160::from_str(address).map_err(|_| Error::InvalidAddressFile(path.into()))?
+        }
+        None => return Ok(None),
+    };
+    let chain_id = lines
+        .next()
+        .map(|id| {
+            id.parse()
+                .map_err(|_| Error::InvalidAddressFile(path.into()))
+        })
+        .transpose()?;
+    Ok(Some((address, chain_id)))
+}
+
+fn parse_chain_id(id: &serde_json::Value) -> Result<u64, Error> {
+    id.as_str()
+        .and_then(|id| u64::from_str_radix(id.trim_start_matches("0x"), 16).ok())
+        .ok_or_else(|| Error::Web3(web3::Error::InvalidResponse(id.to_string())))
+}
+
+/* Make sure the address points to the SSPermissions contract on the chain it was deployed
+ * on. The chain ID from the address file has to agree with the configured one */
+fn verify_contract(
+    web3: &Web3<RpcTransport>,
+    address: H160,
+    chain_id: Option<u64>,
+    config: &ContractConfig,
+) -> Result<(), Error> {
+    let expected = match (chain_id, config.chain_id) {
+        (Some(found), Some(expected)) if found != expected => {
+            return Err(Error::ChainIdMismatch { expected, found })
+        }
+        (Some(id), _) | (None, Some(id)) => id,
+        (None, None) => return Err(Error::UnknownChainId(config.address_file.clone())),
+    };
+    let found = parse_chain_id(&web3.transport().execute("eth_chainId", vec![]).wait()?)?;
+    if found != expected {
+        return Err(Error::ChainIdMismatch { expected, found });
+    }
+
+    let code = web3.eth().code(address, None).wait()?;
+    if code.0.is_empty() {
+        return Err(Error::NoCode(address));
+    }
+
+    if let Some(expected) = config.code_hash {
+        let found = H256::from(keccak256(&code.0));
+        if found != expected {
+            return Err(Error::CodeHashMismatch { expected, found });
+        }
+    } else if code.0 != deployed_bytecode(ARTIFACT)? {
+        return Err(Error::BytecodeMismatch(address));
+    }
+    Ok(())
+}
+
+/* The bytecode is only used if the metadata solc wrote next to it has the hash of the
+ * source, otherwise the contract would be missing functions of the source */
+fn deployed_bytecode(artifact: &str) -> Result<Vec<u8>, Error> {
+    let artifact: serde_json::Value =
+        serde_json::from_str(artifact).map_err(|_| Error::InvalidArtifact)?;
+    let metadata: serde_json::Value = artifact["metadata"]
+        .as_str()
+        .and_then(|metadata| serde_json::from_str(metadata).ok())
+        .ok_or(Error::InvalidArtifact)?;
+    let source = artifact["source"].as_str().ok_or(Error::InvalidArtifact)?;
+    let source_hash = format!("0x{}", hex::encode(keccak256(source.as_bytes())));
+    let compiled = metadata["sources"].as_object().map_or(false, |sources| {
+        sources
+            .values()
+            .any(|source| source["keccak256"].as_str() == Some(source_hash.as_str()))
+    });
+    if !compiled {
+        return Err(Error::StaleArtifact);
+    }
+
+    artifact["deployedBytecode"]
+        .as_str()
+        .and_then(|code| hex::decode(code.trim_start_matches("0x")).ok())
+        .ok_or(Error::InvalidArtifact)
+}
+
+pub async fn deploy_new_contract(
     account: Account,
-    web3: &Web3<Http>,
+    web3: &Web3<RpcTransport>,
+    config: &ContractConfig,
 ) -> Result<AclContract, failure::Error> {
+    deployed_bytecode(ARTIFACT)?;
+    let chain_id = web3
+        .transport()
+        .execute("eth_chainId", vec![])
+        .compat()
+        .await
+        .map_err(Error::from)?;
+    let chain_id = parse_chain_id(&chain_id)?;
     let contract = AclContract::builder(web3).from(account).deploy().await?;
-    let contract_address = format!("{:x}", contract.address());
-    let file = File::create("./contract-address.txt");
-    let _ = file.and_then(|mut file| file.write_all(contract_address.as_bytes()));
+    info!(
+        "Deployed contract at address {:?} on chain {}",
+        contract.address(),
+        chain_id
+    );
+    let contents = format!("{:x}\n{}\n", contract.address(), chain_id);
+    if let Err(error) = fs::write(&config.address_file, contents) {
+        warn!(
+            "Can't write '{}', save the address yourself: {}",
+            config.address_file.display(),
+            error
+        );
+    }
 
     Ok(contract)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn artifact(source: &str, compiled_from: &str) -> String {
+        let metadata = json!({
+            "sources": {
+                "SSPermissions.sol": {
+                    "keccak256": format!("0x{}", hex::encode(keccak256(compiled_from.as_bytes())))
+                }
+            }
+        });
+        json!({
+            "source": source,
+            "metadata": metadata.to_string(),
+            "deployedBytecode": "0x6080",
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn address_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("contract-address.txt");
+        assert!(read_address_file(&path).unwrap().is_none());
+
+        fs::write(&path, "").unwrap();
+        assert!(read_address_file(&path).unwrap().is_none());
+
+        let address = H160::from_low_u64_be(0xadb8);
+        fs::write(&path, format!("{:x}\n", address)).unwrap();
+        assert_eq!(read_address_file(&path).unwrap(), Some((address, None)));
+
+        fs::write(&path, format!("{:x}\n17\n", address)).unwrap();
+        assert_eq!(read_address_file(&path).unwrap(), Some((address, Some(17))));
+
+        fs::write(&path, "not an address\n").unwrap();
+        match read_address_file(&path) {
+            Err(Error::InvalidAddressFile(_)) => {}
+            other => panic!("Expected an invalid address file, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn stale_artifact() {
+        let source = "contract SSPermissions {}";
+        assert_eq!(
+            deployed_bytecode(&artifact(source, source)).unwrap(),
+            vec![0x60, 0x80]
+        );
+        match deployed_bytecode(&artifact(source, "contract Old {}")) {
+            Err(Error::StaleArtifact) => {}
+            other => panic!("Expected a stale artifact, got {:?}", other),
+        }
+    }
+
+    /* Fails until ./build-contract.sh was run after the last change of SSPermissions.sol */
+    #[test]
+    fn artifact_is_compiled() {
+        if let Err(error) = deployed_bytecode(ARTIFACT) {
+            panic!("{}", error);
+        }
+    }
+}
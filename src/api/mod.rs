@@ -1,3 +1,3 @@
 pub mod contract;
-pub mod secretstore;
 pub mod shadow;
+pub mod transport;
@@ -2,19 +2,249 @@ use crate::blockchain::DecryptionKeys;
 use crate::Data;
 use primitive_types::H512;
 use reqwest::{Client, IntoUrl, Url};
+use serde_derive::{Deserialize, Serialize};
 use serde_json;
 
-#[derive(Debug)]
-pub enum Error {
+/// Errors returned by the Secret Store, see `secret-store/src/types/error.rs` of OpenEthereum.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
     KeyAlreadyGenerated,
     KeyAlreadyStored,
     KeyNotFound,
-    Unknown(String),
+    DocumentKeyNotFound,
+    /// The ACL contract doesn't give the requester access to the document
+    AccessDenied,
+    /// Not enough nodes agree to run the session, e.g. because of the ACL contract
+    ConsensusUnreachable,
+    /// Too few nodes are online right now, the request can be retried
+    ConsensusTemporaryUnreachable,
+    /// The signature of the document ID doesn't recover to a requester
+    BadSignature,
+    /// The nodes of the server key don't match the current server set
+    ServerSetMismatch,
+    NotEnoughNodesForThreshold,
+    InvalidThreshold,
+    /// A node needed by the session is disconnected, the request can be retried
+    NodeDisconnected,
+    /// Another session for the same key is running, the request can be retried
+    SessionBusy,
+    InvalidRequest,
+    Internal,
+    /// A message this crate doesn't know, see the message of the error
+    Unknown,
+}
+
+/* Messages of the Secret Store with the status its HTTP listener sends them with. Messages
+ * ending with a space have parameters and are matched by prefix, the others exactly */
+const MESSAGES: &[(u16, &str, ErrorKind)] = &[
+    (
+        400,
+        "Server key with this ID is already generated",
+        ErrorKind::KeyAlreadyGenerated,
+    ),
+    (
+        400,
+        "Document key with this ID is already stored",
+        ErrorKind::KeyAlreadyStored,
+    ),
+    (
+        404,
+        "Server key with this ID is not found",
+        ErrorKind::KeyNotFound,
+    ),
+    (
+        404,
+        "Document key with this ID is not found",
+        ErrorKind::DocumentKeyNotFound,
+    ),
+    /* Sic, that's how the Secret Store spells it */
+    (403, "Access dened", ErrorKind::AccessDenied),
+    (
+        403,
+        "Consensus unreachable",
+        ErrorKind::ConsensusUnreachable,
+    ),
+    (
+        403,
+        "Consensus temporary unreachable",
+        ErrorKind::ConsensusTemporaryUnreachable,
+    ),
+    (
+        400,
+        "Insufficient requester data: ",
+        ErrorKind::BadSignature,
+    ),
+    (400, "hyper error ", ErrorKind::InvalidRequest),
+    (400, "serde error ", ErrorKind::InvalidRequest),
+    (
+        500,
+        "invalid nodes configuration",
+        ErrorKind::ServerSetMismatch,
+    ),
+    (500, "invalid nodes count", ErrorKind::ServerSetMismatch),
+    (
+        500,
+        "invalid node for this request",
+        ErrorKind::ServerSetMismatch,
+    ),
+    (
+        500,
+        "not enough nodes for passed threshold",
+        ErrorKind::NotEnoughNodesForThreshold,
+    ),
+    (
+        500,
+        "invalid threshold value has been passed",
+        ErrorKind::InvalidThreshold,
+    ),
+    (
+        500,
+        "node required for this operation is currently disconnected",
+        ErrorKind::NodeDisconnected,
+    ),
+    (
+        500,
+        "session with the same id is already registered",
+        ErrorKind::SessionBusy,
+    ),
+    (500, "Exclusive session active", ErrorKind::SessionBusy),
+    (
+        500,
+        "Unable to start exclusive session",
+        ErrorKind::SessionBusy,
+    ),
+    (
+        500,
+        "invalid message is received",
+        ErrorKind::InvalidRequest,
+    ),
+    (
+        500,
+        "unsupported message version",
+        ErrorKind::InvalidRequest,
+    ),
+    (500, "internal error: ", ErrorKind::Internal),
+];
+
+impl ErrorKind {
+    /* Status the Secret Store uses for this error */
+    pub fn status(self) -> u16 {
+        match self {
+            ErrorKind::AccessDenied
+            | ErrorKind::ConsensusUnreachable
+            | ErrorKind::ConsensusTemporaryUnreachable => 403,
+            ErrorKind::KeyNotFound | ErrorKind::DocumentKeyNotFound => 404,
+            ErrorKind::KeyAlreadyGenerated
+            | ErrorKind::KeyAlreadyStored
+            | ErrorKind::BadSignature
+            | ErrorKind::InvalidRequest => 400,
+            _ => 500,
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            ErrorKind::KeyAlreadyGenerated => "Server key with this ID is already generated",
+            ErrorKind::KeyAlreadyStored => "Document key with this ID is already stored",
+            ErrorKind::KeyNotFound => "Server key with this ID is not found",
+            ErrorKind::DocumentKeyNotFound => "Document key with this ID is not found",
+            ErrorKind::AccessDenied => "Access dened",
+            ErrorKind::ConsensusUnreachable => "Consensus unreachable",
+            ErrorKind::ConsensusTemporaryUnreachable => "Consensus temporary unreachable",
+            ErrorKind::BadSignature => "Insufficient requester data: bad signature",
+            ErrorKind::ServerSetMismatch => "invalid nodes configuration",
+            ErrorKind::NotEnoughNodesForThreshold => "not enough nodes for passed threshold",
+            ErrorKind::InvalidThreshold => "invalid threshold value has been passed",
+            ErrorKind::NodeDisconnected => {
+                "node required for this operation is currently disconnected"
+            }
+            ErrorKind::SessionBusy => "session with the same id is already registered",
+            ErrorKind::InvalidRequest => "invalid message is received",
+            ErrorKind::Internal => "internal error: unexpected response",
+            ErrorKind::Unknown => "Unknown error",
+        }
+    }
+
+    /* Errors which can go away without changing the request */
+    pub fn is_temporary(self) -> bool {
+        match self {
+            ErrorKind::ConsensusTemporaryUnreachable
+            | ErrorKind::NodeDisconnected
+            | ErrorKind::SessionBusy => true,
+            _ => false,
+        }
+    }
+
+    /* Only messages the Secret Store sends with this status are considered, anything else
+     * is Unknown instead of a guess */
+    fn classify(status: u16, message: &str) -> ErrorKind {
+        MESSAGES
+            .iter()
+            .find(|(expected_status, expected, _)| {
+                *expected_status == status
+                    && (message == *expected
+                        || (expected.ends_with(' ') && message.starts_with(expected)))
+            })
+            .map_or(ErrorKind::Unknown, |(_, _, kind)| *kind)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Error response of the Secret Store with its HTTP status and message
+    Response {
+        kind: ErrorKind,
+        status: u16,
+        message: String,
+    },
     Reqwest(reqwest::Error),
     UrlParse(url::ParseError),
     HashParse(serde_json::Error),
 }
 
+impl Error {
+    /* Error as the Secret Store would send it */
+    pub fn new(kind: ErrorKind) -> Error {
+        Error::with_message(kind, kind.message())
+    }
+
+    pub fn with_message<M: Into<String>>(kind: ErrorKind, message: M) -> Error {
+        Error::Response {
+            kind,
+            status: kind.status(),
+            message: message.into(),
+        }
+    }
+
+    /* The body is the message as JSON string */
+    pub fn from_response(status: u16, body: &str) -> Error {
+        let message = serde_json::from_str::<String>(body)
+            .unwrap_or_else(|_| body.trim_matches(CHARS_TO_TRIM).into());
+        Error::Response {
+            kind: ErrorKind::classify(status, &message),
+            status,
+            message,
+        }
+    }
+
+    /* None for errors which aren't responses of the Secret Store */
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Error::Response { kind, .. } => Some(*kind),
+            _ => None,
+        }
+    }
+
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Error::Response { status, .. } => Some(*status),
+            Error::Reqwest(error) => error.status().map(|status| status.as_u16()),
+            _ => None,
+        }
+    }
+}
+
 impl std::error::Error for Error {}
 
 impl From<url::ParseError> for Error {
@@ -38,12 +268,9 @@ impl From<serde_json::Error> for Error {
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            Error::KeyAlreadyGenerated => {
-                write!(f, "Server key with this ID is already generated.")
-            }
-            Error::KeyNotFound => write!(f, "Server key with this ID is not found."),
-            Error::KeyAlreadyStored => write!(f, "Document key with this ID is already stored."),
-            Error::Unknown(text) => write!(f, "UnknownError: {}", text),
+            Error::Response {
+                status, message, ..
+            } => write!(f, "Secret Store error {}: {}", status, message),
             Error::Reqwest(error) => write!(f, "Reqwest: {}", error),
             Error::UrlParse(error) => write!(f, "UrlParse: {}", error),
             Error::HashParse(error) => write!(f, "HashParse: {}", error),
@@ -52,6 +279,7 @@ impl std::fmt::Display for Error {
 }
 
 pub struct Shadow {
+    base_url: Url,
     url: Url,
     client: Client,
 }
@@ -60,9 +288,18 @@ const CHARS_TO_TRIM: &[char] = &['"', '\\'];
 
 impl Shadow {
     pub fn new<T: IntoUrl>(url: T) -> Self {
-        let url: Url = url.into_url().unwrap().join("shadow/").unwrap();
-        let client = Client::new();
-        Shadow { url, client }
+        Shadow::with_client(url, Client::new())
+    }
+
+    /* Client with the settings of the transport config */
+    pub fn with_client<T: IntoUrl>(url: T, client: Client) -> Self {
+        let base_url: Url = url.into_url().unwrap();
+        let url = base_url.join("shadow/").unwrap();
+        Shadow {
+            base_url,
+            url,
+            client,
+        }
     }
 
     pub async fn generate_server_key(
@@ -121,10 +358,60 @@ impl Shadow {
         if result == "" {
             Ok(())
         } else {
-            Err(Error::Unknown(result))
+            Err(Error::with_message(ErrorKind::Internal, result))
         }
     }
 
+    /* Public key of an already generated server key */
+    pub async fn get_server_key(
+        &self,
+        document_key_id: &str,
+        signed_document_key_id: &Data,
+    ) -> Result<Data, Error> {
+        let query = format!(
+            "server/{}/{}",
+            document_key_id,
+            signed_document_key_id
+                .trim_matches(CHARS_TO_TRIM)
+                .trim_start_matches("0x")
+        );
+
+        let url = self.base_url.join(&query)?;
+        parse_response(
+            self.client
+                .get(url)
+                .send()
+                .await
+                .map_err(|err| Error::Reqwest(err))?,
+        )
+        .await
+    }
+
+    /* Stored document key, encrypted with the public key of the requester */
+    pub async fn get_encrypted_document_key(
+        &self,
+        document_key_id: &str,
+        signed_document_key_id: &Data,
+    ) -> Result<Data, Error> {
+        let query = format!(
+            "{}/{}",
+            document_key_id,
+            signed_document_key_id
+                .trim_matches(CHARS_TO_TRIM)
+                .trim_start_matches("0x")
+        );
+
+        let url = self.base_url.join(&query)?;
+        parse_response(
+            self.client
+                .get(url)
+                .send()
+                .await
+                .map_err(|err| Error::Reqwest(err))?,
+        )
+        .await
+    }
+
     pub async fn get_document_key(
         &self,
         document_key_id: &str,
@@ -152,20 +439,129 @@ impl Shadow {
 }
 
 async fn parse_response(response: reqwest::Response) -> Result<Data, Error> {
-    if !response.status().is_success() {
-        let body = response.text().await?;
-
-        if body.contains("Server key with this ID is already generated") {
-            return Err(Error::KeyAlreadyGenerated.into());
-        } else if body.contains("Document key with this ID is already stored") {
-            return Err(Error::KeyAlreadyStored.into());
-        } else if body.contains("Server key with this ID is not found") {
-            return Err(Error::KeyNotFound.into());
-        } else {
-            return Err(Error::Unknown(body).into());
+    let status = response.status();
+    let text = response.text().await?;
+    if !status.is_success() {
+        return Err(Error::from_response(status.as_u16(), &text));
+    }
+    Ok(text.trim_matches(CHARS_TO_TRIM).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use primitive_types::H256;
+    use secp256k1::{Message, SecretKey};
+
+    #[derive(Serialize, Deserialize)]
+    struct Fixture {
+        status: u16,
+        body: String,
+        kind: ErrorKind,
+    }
+
+    #[test]
+    fn recorded_errors() {
+        let fixtures: Vec<Fixture> =
+            serde_json::from_str(include_str!("fixtures/shadow_errors.json")).unwrap();
+        for fixture in fixtures {
+            let error = Error::from_response(fixture.status, &fixture.body);
+            assert_eq!(error.kind(), Some(fixture.kind), "{}", fixture.body);
+            assert_eq!(error.status(), Some(fixture.status));
+            match error {
+                Error::Response { message, .. } => {
+                    assert_eq!(serde_json::to_string(&message).unwrap(), fixture.body)
+                }
+                _ => panic!("Not a Secret Store response"),
+            }
         }
     }
 
-    let text = response.text().await?;
-    Ok(text.trim_matches(CHARS_TO_TRIM).into())
+    /* Replaces the fixtures of the errors which can be provoked with the responses of a
+     * running Secret Store, e.g. the one of ss-network, at the shadow_url of the default
+     * config. Run with `cargo test record_errors -- --ignored` */
+    #[test]
+    #[ignore]
+    fn record_errors() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let base_url = Url::parse(&Config::default().shadow_url).unwrap();
+        let client = Client::new();
+        let secret = SecretKey::parse(&rand::random()).unwrap();
+        let sign = |id: H256| {
+            let (signature, recovery) =
+                secp256k1::sign(&Message::parse(id.as_fixed_bytes()), &secret).unwrap();
+            let mut bytes = signature.serialize().to_vec();
+            bytes.push(recovery.serialize());
+            hex::encode(bytes)
+        };
+        let mut send = |request: reqwest::RequestBuilder| {
+            rt.block_on(async {
+                let response = request.send().await.unwrap();
+                (response.status().as_u16(), response.text().await.unwrap())
+            })
+        };
+        let url = |path: String| base_url.join(&path).unwrap();
+
+        let id = H256::random();
+        let mut recorded = vec![
+            (
+                send(client.get(url(format!("shadow/{:x}/{}", id, sign(id))))),
+                ErrorKind::KeyNotFound,
+            ),
+            (
+                send(client.get(url(format!("shadow/{:x}/{}", id, "00".repeat(65))))),
+                ErrorKind::BadSignature,
+            ),
+        ];
+        let generate = url(format!("shadow/{:x}/{}/1", id, sign(id)));
+        let (status, _) = send(client.post(generate.clone()));
+        assert_eq!(status, 200, "Generating a server key failed");
+        recorded.push((send(client.post(generate)), ErrorKind::KeyAlreadyGenerated));
+        recorded.push((
+            send(client.get(url(format!("shadow/{:x}/{}", id, sign(id))))),
+            ErrorKind::DocumentKeyNotFound,
+        ));
+
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/api/fixtures/shadow_errors.json"
+        );
+        let mut fixtures: Vec<Fixture> =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        for ((status, body), kind) in recorded {
+            fixtures.retain(|fixture| fixture.kind != kind);
+            fixtures.push(Fixture { status, body, kind });
+        }
+        std::fs::write(
+            path,
+            serde_json::to_string_pretty(&fixtures).unwrap() + "\n",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn canonical_errors() {
+        let error = Error::new(ErrorKind::AccessDenied);
+        assert_eq!(error.status(), Some(403));
+        assert_eq!(
+            ErrorKind::classify(403, ErrorKind::AccessDenied.message()),
+            ErrorKind::AccessDenied
+        );
+        /* Same message with another status, and substrings of known messages */
+        assert_eq!(
+            ErrorKind::classify(500, ErrorKind::AccessDenied.message()),
+            ErrorKind::Unknown
+        );
+        assert_eq!(
+            ErrorKind::classify(403, "Upstream: Access dened by proxy"),
+            ErrorKind::Unknown
+        );
+        assert_eq!(
+            ErrorKind::classify(500, "internal error: database is corrupted"),
+            ErrorKind::Internal
+        );
+        assert!(ErrorKind::ConsensusTemporaryUnreachable.is_temporary());
+        assert!(!ErrorKind::ConsensusUnreachable.is_temporary());
+    }
 }
@@ -0,0 +1,431 @@
+/* HTTP clients built from the transport config, so timeouts, CA roots, the client
+ * certificate and the proxy apply to the Secret Store HTTP API, the Secret Store JSON-RPC
+ * API and the contract calls alike */
+use crate::config::TransportConfig;
+use futures::compat::Compat;
+use futures::future::TryFutureExt;
+use lazy_static::lazy_static;
+use reqwest::{Certificate, Client, Identity, IntoUrl, Proxy, Url};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fs;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::runtime::{Builder, Handle};
+use web3::futures::Future as Future01;
+use web3::helpers;
+use web3::rpc::{self, Value};
+use web3::{RequestId, Transport};
+
+#[derive(Debug)]
+pub enum Error {
+    Reqwest(reqwest::Error),
+    Io(std::io::Error),
+    /// Error returned by the node for a call
+    Rpc {
+        code: i64,
+        message: String,
+    },
+    InvalidResponse(String),
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        Error::Reqwest(error)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<rpc::Error> for Error {
+    fn from(error: rpc::Error) -> Self {
+        Error::Rpc {
+            code: error.code.code(),
+            message: error.message,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Reqwest(error) => write!(f, "Reqwest: {}", error),
+            Error::Io(error) => write!(f, "Io: {}", error),
+            Error::Rpc { code, message } => write!(f, "RPC error {}: {}", code, message),
+            Error::InvalidResponse(body) => write!(f, "Invalid JSON-RPC response: {}", body),
+        }
+    }
+}
+
+pub fn http_client(config: &TransportConfig) -> Result<Client, Error> {
+    let mut builder = Client::builder();
+    if let Some(timeout) = config.timeout {
+        builder = builder.timeout(Duration::from_secs(timeout));
+    }
+    if let Some(timeout) = config.connect_timeout {
+        builder = builder.connect_timeout(Duration::from_secs(timeout));
+    }
+    for path in &config.ca_certificates {
+        builder = builder.add_root_certificate(Certificate::from_pem(&fs::read(path)?)?);
+    }
+    if let Some(path) = &config.client_identity {
+        let password = config.client_identity_password.as_deref().unwrap_or("");
+        builder = builder.identity(Identity::from_pkcs12_der(&fs::read(path)?, password)?);
+    }
+    if let Some(proxy) = &config.proxy {
+        builder = builder.proxy(Proxy::all(proxy.as_str())?);
+    }
+    Ok(builder.build()?)
+}
+
+lazy_static! {
+    /* One connection pool per transport config, shared by all stores of the process */
+    static ref CLIENTS: Mutex<HashMap<TransportConfig, Client>> = Mutex::new(HashMap::new());
+    /* Used by the blocking API outside of a runtime, see Executor::current. It is never shut
+     * down, so only its handle is kept */
+    static ref SHARED: Handle = {
+        let runtime = Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .thread_name("secretstore")
+            .build()
+            .expect("Failed to start the shared runtime");
+        let handle = runtime.handle().clone();
+        std::mem::forget(runtime);
+        handle
+    };
+}
+
+/* Pooled client for config, clients share their connections */
+pub fn shared_client(config: &TransportConfig) -> Result<Client, Error> {
+    let mut clients = CLIENTS.lock().unwrap_or_else(|error| error.into_inner());
+    if let Some(client) = clients.get(config) {
+        return Ok(client.clone());
+    }
+    let client = http_client(config)?;
+    clients.insert(config.clone(), client.clone());
+    Ok(client)
+}
+
+/// Runtime on which the blocking API of a store runs its requests.
+#[derive(Debug, Clone)]
+pub struct Executor {
+    handle: Handle,
+}
+
+impl Executor {
+    /* The runtime of the caller, or the runtime shared by all stores when called outside of
+     * one */
+    pub fn current() -> Executor {
+        Executor::new(Handle::try_current().unwrap_or_else(|_| SHARED.clone()))
+    }
+
+    pub fn new(handle: Handle) -> Executor {
+        Executor { handle }
+    }
+
+    /* Run blocking code which waits on requests */
+    pub fn enter<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        self.handle.enter(f)
+    }
+
+    /* On a worker of a threaded runtime the other tasks of the worker move to another thread
+     * while it waits. The basic scheduler can't do that and panics, call the blocking API
+     * through spawn_blocking there */
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        tokio::task::block_in_place(|| self.handle.block_on(future))
+    }
+}
+
+type RpcFuture<T> = Compat<Pin<Box<dyn Future<Output = Result<T, Error>> + Send>>>;
+
+/// JSON-RPC over HTTP, used for the web3 contract calls and the Secret Store RPC API.
+///
+/// Requests run on the runtime they are awaited on, the client is usually a shared one.
+#[derive(Debug, Clone)]
+pub struct RpcTransport {
+    client: Client,
+    url: Url,
+    next_id: Arc<AtomicUsize>,
+}
+
+impl RpcTransport {
+    pub fn new<T: IntoUrl>(url: T, client: Client) -> Result<RpcTransport, Error> {
+        Ok(RpcTransport {
+            client,
+            url: url.into_url()?,
+            next_id: Arc::new(AtomicUsize::new(1)),
+        })
+    }
+
+    /* Send a JSON-RPC request or batch as it is, the response body is returned */
+    fn post(&self, body: Vec<u8>) -> impl Future<Output = Result<Vec<u8>, Error>> + Send {
+        let request = self
+            .client
+            .post(self.url.clone())
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send();
+        async move { Ok(request.await?.bytes().await?.to_vec()) }
+    }
+
+    fn post01(&self, body: Vec<u8>) -> RpcFuture<Vec<u8>> {
+        let response: Pin<Box<dyn Future<Output = Result<Vec<u8>, Error>> + Send>> =
+            Box::pin(self.post(body));
+        response.compat()
+    }
+
+    /* Single JSON-RPC call, the result is deserialized into T */
+    pub async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Vec<Value>,
+    ) -> Result<T, Error> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let request = helpers::build_request(id, method, params);
+        let body = helpers::to_string(&rpc::Request::Single(request)).into_bytes();
+        let body = self.post(body).await?;
+        match serde_json::from_slice::<rpc::Response>(&body) {
+            Ok(rpc::Response::Single(rpc::Output::Success(success))) => {
+                serde_json::from_value(success.result)
+                    .map_err(|error| Error::InvalidResponse(error.to_string()))
+            }
+            Ok(rpc::Response::Single(rpc::Output::Failure(failure))) => Err(failure.error.into()),
+            _ => Err(Error::InvalidResponse(
+                String::from_utf8_lossy(&body).into(),
+            )),
+        }
+    }
+
+    /* JSON-RPC 2.0 batch, the results are in the order of the calls. A failed call doesn't
+     * fail the others */
+    pub async fn batch(
+        &self,
+        calls: Vec<(&str, Vec<Value>)>,
+    ) -> Result<Vec<Result<Value, Error>>, Error> {
+        if calls.is_empty() {
+            return Ok(vec![]);
+        }
+        let (ids, requests): (Vec<_>, Vec<_>) = calls
+            .into_iter()
+            .map(|(method, params)| {
+                let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+                (id as u64, helpers::build_request(id, method, params))
+            })
+            .unzip();
+        let body = helpers::to_string(&rpc::Request::Batch(requests)).into_bytes();
+        let body = self.post(body).await?;
+
+        /* A node without batch support answers with a single error */
+        let outputs = match serde_json::from_slice::<rpc::Response>(&body) {
+            Ok(rpc::Response::Batch(outputs)) => outputs,
+            Ok(rpc::Response::Single(rpc::Output::Failure(failure))) => {
+                return Err(failure.error.into())
+            }
+            _ => {
+                return Err(Error::InvalidResponse(
+                    String::from_utf8_lossy(&body).into(),
+                ))
+            }
+        };
+
+        let mut results: HashMap<u64, Result<Value, Error>> = outputs
+            .into_iter()
+            .filter_map(|output| match output {
+                rpc::Output::Success(success) => match success.id {
+                    rpc::Id::Num(id) => Some((id, Ok(success.result))),
+                    _ => None,
+                },
+                rpc::Output::Failure(failure) => match failure.id {
+                    rpc::Id::Num(id) => Some((id, Err(failure.error.into()))),
+                    _ => None,
+                },
+            })
+            .collect();
+        Ok(ids
+            .iter()
+            .map(|id| {
+                results.remove(id).unwrap_or_else(|| {
+                    Err(Error::InvalidResponse(format!("No response for {}", id)))
+                })
+            })
+            .collect())
+    }
+}
+
+impl Transport for RpcTransport {
+    type Out = Box<dyn Future01<Item = Value, Error = web3::Error> + Send>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, rpc::Call) {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        (id, helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: rpc::Call) -> Self::Out {
+        let body = helpers::to_string(&rpc::Request::Single(request)).into_bytes();
+        Box::new(
+            self.post01(body)
+                .map_err(|error| web3::Error::Transport(error.to_string()))
+                .and_then(
+                    |body| match serde_json::from_slice::<rpc::Response>(&body) {
+                        Ok(rpc::Response::Single(output)) => helpers::to_result_from_output(output),
+                        _ => Err(web3::Error::InvalidResponse(
+                            String::from_utf8_lossy(&body).into(),
+                        )),
+                    },
+                ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use tempfile::NamedTempFile;
+
+    fn get(client: &Client, url: &str) -> Result<reqwest::Response, reqwest::Error> {
+        Executor::current().block_on(client.get(url).send())
+    }
+
+    #[test]
+    fn timeout() {
+        /* Connections are accepted by the kernel but never answered */
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = http_client(&TransportConfig {
+            timeout: Some(1),
+            ..TransportConfig::default()
+        })
+        .unwrap();
+        let error = get(
+            &client,
+            &format!("http://{}/", listener.local_addr().unwrap()),
+        )
+        .unwrap_err();
+        assert!(error.is_timeout(), "{}", error);
+    }
+
+    #[test]
+    fn invalid_certificates() {
+        let missing = TransportConfig {
+            ca_certificates: vec!["/nonexistent/ca.pem".into()],
+            ..TransportConfig::default()
+        };
+        match http_client(&missing) {
+            Err(Error::Io(_)) => {}
+            other => panic!("Expected an IO error, got {:?}", other.map(|_| ())),
+        }
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"not a certificate").unwrap();
+        let invalid_ca = TransportConfig {
+            ca_certificates: vec![file.path().into()],
+            ..TransportConfig::default()
+        };
+        match http_client(&invalid_ca) {
+            Err(Error::Reqwest(_)) => {}
+            other => panic!("Expected a reqwest error, got {:?}", other.map(|_| ())),
+        }
+        let invalid_identity = TransportConfig {
+            client_identity: Some(file.path().into()),
+            client_identity_password: Some("password".into()),
+            ..TransportConfig::default()
+        };
+        match http_client(&invalid_identity) {
+            Err(Error::Reqwest(_)) => {}
+            other => panic!("Expected a reqwest error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn proxy() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let client = http_client(&TransportConfig {
+            proxy: Some(proxy),
+            ..TransportConfig::default()
+        })
+        .unwrap();
+        let response = get(&client, "http://secretstore.invalid/shadow").unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(server
+            .join()
+            .unwrap()
+            .starts_with("GET http://secretstore.invalid/shadow HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn shared_clients() {
+        let config = TransportConfig {
+            proxy: Some("http://127.0.0.1:3128".into()),
+            ..TransportConfig::default()
+        };
+        shared_client(&config).unwrap();
+        assert!(CLIENTS.lock().unwrap().contains_key(&config));
+
+        let invalid = TransportConfig {
+            ca_certificates: vec!["/nonexistent/ca.pem".into()],
+            ..TransportConfig::default()
+        };
+        assert!(shared_client(&invalid).is_err());
+        assert!(!CLIENTS.lock().unwrap().contains_key(&invalid));
+    }
+
+    #[test]
+    fn block_on_in_threaded_runtime() {
+        let mut rt = Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            Executor::current().block_on(tokio::time::delay_for(Duration::from_millis(10)));
+        });
+        rt.block_on(tokio::spawn(async {
+            Executor::current().block_on(tokio::time::delay_for(Duration::from_millis(10)));
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn spawn_blocking_in_basic_scheduler() {
+        let mut rt = Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            /* The timer is driven by the scheduler thread while the blocking thread waits */
+            tokio::task::spawn_blocking(|| {
+                Executor::current().block_on(tokio::time::delay_for(Duration::from_millis(10)))
+            })
+            .await
+            .unwrap();
+        });
+    }
+}
@@ -0,0 +1,223 @@
+use crypto_secretstore::{
+    deploy_contract, generate_id, Config, CryptoSecretStore, PasswordProvider, PromptPassword,
+    SecretString,
+};
+use failure::{format_err, Error};
+use primitive_types::H160;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(
+    name = "secretstore-cli",
+    about = "Encrypt, decrypt and manage access to documents in the Secret Store"
+)]
+struct Opt {
+    /// Config file with the Secret Store and node URLs
+    #[structopt(short, long, parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// Address of the account, if no keystore file is given
+    #[structopt(short, long)]
+    address: Option<String>,
+    /// Keystore file of the account, the address is read from it
+    #[structopt(short, long, parse(from_os_str))]
+    keystore: Option<PathBuf>,
+    /// File containing the password of the account
+    #[structopt(short, long, parse(from_os_str))]
+    password_file: Option<PathBuf>,
+    #[structopt(subcommand)]
+    cmd: Command,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Encrypt a document and print the ciphertext
+    Encrypt {
+        /// Document ID, generated from the document if omitted
+        #[structopt(long)]
+        id: Option<String>,
+        #[structopt(short, long, default_value = "1")]
+        threshold: u32,
+        /// Input file, stdin if omitted
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+    },
+    /// Decrypt a ciphertext and print the document
+    Decrypt {
+        #[structopt(long)]
+        id: String,
+        /// Input file, stdin if omitted
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+    },
+    /// Allow addresses to decrypt a document
+    Grant {
+        #[structopt(long)]
+        id: String,
+        /// Unix timestamp from which the grant is valid
+        #[structopt(long)]
+        from: Option<u64>,
+        /// Unix timestamp at which the grant runs out
+        #[structopt(long)]
+        until: Option<u64>,
+        #[structopt(required = true)]
+        addresses: Vec<String>,
+    },
+    /// Remove addresses from the allowed readers of a document
+    Revoke {
+        #[structopt(long)]
+        id: String,
+        #[structopt(required = true)]
+        addresses: Vec<String>,
+    },
+    /// Check whether an address can decrypt a document
+    Check {
+        #[structopt(long)]
+        id: String,
+        address: String,
+    },
+    /// List the owner and the members of a document
+    Members {
+        #[structopt(long)]
+        id: String,
+    },
+    /// Deploy a new ACL contract
+    DeployContract,
+    /// Print the document ID of a document
+    Id {
+        /// Input file, stdin if omitted
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+    },
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    if let Err(error) = run(opt) {
+        eprintln!("Error: {}", error);
+        std::process::exit(1);
+    }
+}
+
+fn run(opt: Opt) -> Result<(), Error> {
+    let config = match &opt.config {
+        Some(path) => Config::from_file(path)?,
+        None => Config::default(),
+    };
+
+    match &opt.cmd {
+        Command::Id { input } => {
+            println!("{}", generate_id(&read_input(input)?));
+            return Ok(());
+        }
+        Command::DeployContract => {
+            let (address, password) = credentials(&opt)?;
+            let contract = deploy_contract(&address, password.expose(), &config)?;
+            println!("{:x}", contract);
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let (address, password) = credentials(&opt)?;
+    let store = CryptoSecretStore::with_config(&address, password.expose(), &config)?;
+
+    match opt.cmd {
+        Command::Encrypt {
+            id,
+            threshold,
+            input,
+        } => {
+            let document = read_input(&input)?;
+            let id = id.unwrap_or_else(|| generate_id(&document));
+            let ciphertext = store.encrypt(&id, &document, threshold)?;
+            eprintln!("Document ID: {}", id);
+            println!("{}", ciphertext);
+        }
+        Command::Decrypt { id, input } => {
+            let ciphertext = read_input(&input)?;
+            let document = store.decrypt(&id, ciphertext.trim())?;
+            io::stdout().write_all(document.as_bytes())?;
+        }
+        Command::Grant {
+            id,
+            from,
+            until,
+            addresses,
+        } => {
+            let addresses = parse_addresses(&addresses)?;
+            let result = if from.is_some() || until.is_some() {
+                store.allow_access_until(&id, &addresses, from, until)?
+            } else {
+                store.allow_access(&id, &addresses)?
+            };
+            println!("{:?}", result.hash());
+        }
+        Command::Revoke { id, addresses } => {
+            let result = store.revoke_access(&id, &parse_addresses(&addresses)?)?;
+            println!("{:?}", result.hash());
+        }
+        Command::Check { id, address } => {
+            let grant = store.check_permissions(parse_address(&address)?, &id)?;
+            match grant.valid_until {
+                Some(until) => println!("{} (until {})", grant.is_allowed(), until),
+                None => println!("{}", grant.is_allowed()),
+            }
+        }
+        Command::Members { id } => {
+            println!("owner {:?}", store.owner_of(&id)?);
+            for member in store.members_of(&id)? {
+                println!("{:?}", member);
+            }
+        }
+        Command::DeployContract | Command::Id { .. } => unreachable!(),
+    }
+    Ok(())
+}
+
+/* Reads the account from the keystore file or --address and the password from --password-file,
+ * without a password file the password is asked for on the terminal */
+fn credentials(opt: &Opt) -> Result<(String, SecretString), Error> {
+    let address = match (&opt.keystore, &opt.address) {
+        (Some(keystore), _) => {
+            let keystore: serde_json::Value = serde_json::from_str(&fs::read_to_string(keystore)?)?;
+            keystore["address"]
+                .as_str()
+                .ok_or_else(|| format_err!("Keystore file doesn't contain an address"))?
+                .to_string()
+        }
+        (None, Some(address)) => address.clone(),
+        (None, None) => return Err(format_err!("Either --keystore or --address is required")),
+    };
+    let address = address.trim_start_matches("0x").to_string();
+    let password = match &opt.password_file {
+        Some(path) => SecretString::from(fs::read_to_string(path)?.trim_end()),
+        None => PromptPassword.password(H160::from_str(&address)?)?,
+    };
+    Ok((address, password))
+}
+
+fn read_input(input: &Option<PathBuf>) -> Result<String, Error> {
+    let mut contents = String::new();
+    match input {
+        Some(path) => contents = fs::read_to_string(path)?,
+        None => {
+            io::stdin().read_to_string(&mut contents)?;
+        }
+    }
+    Ok(contents)
+}
+
+fn parse_address(address: &str) -> Result<H160, Error> {
+    Ok(H160::from_str(address.trim_start_matches("0x"))?)
+}
+
+fn parse_addresses(addresses: &[String]) -> Result<Vec<H160>, Error> {
+    addresses
+        .iter()
+        .map(|address| parse_address(address))
+        .collect()
+}
@@ -0,0 +1,56 @@
+use crypto_secretstore::daemon::{self, TokensFile};
+use crypto_secretstore::Config;
+use failure::Error;
+use log::error;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(
+    name = "secretstore-daemon",
+    about = "Serve encrypt, decrypt and grants of the Secret Store over a local HTTP API"
+)]
+struct Opt {
+    /// Config file with the Secret Store and node URLs
+    #[structopt(short, long, parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// TOML file mapping API tokens to accounts, see the daemon module
+    #[structopt(short, long, parse(from_os_str))]
+    tokens: PathBuf,
+    /// Address to listen on, only loopback addresses are allowed
+    #[structopt(short, long, default_value = "127.0.0.1:8600")]
+    listen: SocketAddr,
+    /// Listen on this Unix socket instead of a TCP port
+    #[structopt(short, long, parse(from_os_str))]
+    socket: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() {
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "info");
+    }
+    env_logger::init();
+
+    let opt = Opt::from_args();
+    if let Err(err) = run(opt).await {
+        error!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+async fn run(opt: Opt) -> Result<(), Error> {
+    let config = match &opt.config {
+        Some(path) => Config::from_file(path)?,
+        None => Config::default(),
+    };
+    let tokens: TokensFile = toml::from_str(&fs::read_to_string(&opt.tokens)?)?;
+    let state = tokio::task::spawn_blocking(move || daemon::load(tokens, &config)).await??;
+
+    match &opt.socket {
+        Some(path) => daemon::serve_socket(state, path).await,
+        None => daemon::serve_tcp(state, opt.listen).await,
+    }
+}
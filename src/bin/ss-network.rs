@@ -0,0 +1,191 @@
+use crypto_secretstore::network::{Layout, NetworkSpec};
+use failure::{format_err, Error};
+use primitive_types::H160;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = "ss-network", about = "Create and run a Secret Store network")]
+struct Opt {
+    /// Directory containing the node configs
+    #[structopt(short, long, parse(from_os_str), default_value = "test_network")]
+    dir: PathBuf,
+    /// parity binary, it has to be built with the Secret Store feature
+    #[structopt(long, default_value = "parity")]
+    parity: String,
+    #[structopt(subcommand)]
+    cmd: Cmd,
+}
+
+#[derive(StructOpt)]
+enum Cmd {
+    /// Generate keys and render the configs for a new network
+    Setup {
+        #[structopt(short, long)]
+        nodes: usize,
+        /// File with one host per line, the nodes run on these hosts instead of locally
+        #[structopt(long, parse(from_os_str))]
+        hosts: Option<PathBuf>,
+        /// Also create an account for every node with `parity account new`
+        #[structopt(long)]
+        accounts: bool,
+        /// Replace the directory even if it isn't empty
+        #[structopt(long)]
+        force: bool,
+    },
+    /// Set the ACL contract of all nodes
+    SetContract { address: String },
+    /// Start all local nodes and wait until they exit
+    Start {
+        /// Start the nodes on remote hosts via ssh as this user
+        #[structopt(long)]
+        ssh_user: Option<String>,
+    },
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    if let Err(error) = run(opt) {
+        eprintln!("Error: {}", error);
+        std::process::exit(1);
+    }
+}
+
+fn run(opt: Opt) -> Result<(), Error> {
+    match opt.cmd {
+        Cmd::Setup {
+            nodes,
+            hosts,
+            accounts,
+            force,
+        } => {
+            let layout = match hosts {
+                Some(path) => Layout::Remote(
+                    fs::read_to_string(path)?
+                        .lines()
+                        .map(str::trim)
+                        .filter(|line| !line.is_empty())
+                        .map(String::from)
+                        .collect(),
+                ),
+                None => Layout::Local,
+            };
+            if opt.dir.exists() {
+                /* Don't throw away keys of an existing network by accident */
+                if fs::read_dir(&opt.dir)?.next().is_some() && !force {
+                    return Err(format_err!(
+                        "{} isn't empty, use --force to replace it",
+                        opt.dir.display()
+                    ));
+                }
+                fs::remove_dir_all(&opt.dir)?;
+            }
+            let spec = NetworkSpec::generate(nodes, &layout)?;
+            spec.write(&opt.dir)?;
+            if accounts {
+                create_accounts(&opt.parity, &opt.dir, &spec)?;
+            }
+
+            println!("SecretStore nodes:");
+            for node in &spec.nodes {
+                println!("  {}", node.ss_node()?);
+            }
+            println!("Bootnodes:");
+            for node in &spec.nodes {
+                println!("  {}", node.enode()?);
+            }
+        }
+        Cmd::SetContract { address } => {
+            let mut spec = NetworkSpec::load(&opt.dir)?;
+            spec.acl_contract = Some(H160::from_str(address.trim_start_matches("0x"))?);
+            spec.write(&opt.dir)?;
+        }
+        Cmd::Start { ssh_user } => {
+            let spec = NetworkSpec::load(&opt.dir)?;
+            let mut children = vec![];
+            for node in &spec.nodes {
+                let child = match (&ssh_user, node.host.as_str()) {
+                    (None, _) | (Some(_), "127.0.0.1") => {
+                        start_local(&opt.parity, &opt.dir, &node.name)?
+                    }
+                    (Some(user), host) => {
+                        start_remote(&opt.parity, &opt.dir, user, host, &node.name)?
+                    }
+                };
+                println!("Started node {}", node.name);
+                children.push(child);
+            }
+            /* The nodes get ctrl+c from the terminal as well, so just wait for all of them */
+            for mut child in children {
+                child.wait()?;
+            }
+            println!("Stopped all nodes.");
+        }
+    }
+    Ok(())
+}
+
+fn create_accounts(parity: &str, dir: &Path, spec: &NetworkSpec) -> Result<(), Error> {
+    for node in &spec.nodes {
+        let output = Command::new(parity)
+            .current_dir(dir)
+            .args(&["--config", &format!("{}.toml", node.name), "account", "new"])
+            .stderr(Stdio::inherit())
+            .output()?;
+        if !output.status.success() {
+            return Err(format_err!("Creating account for {} failed", node.name));
+        }
+        println!(
+            "Created account {} for {}",
+            String::from_utf8_lossy(&output.stdout).trim(),
+            node.name
+        );
+    }
+    Ok(())
+}
+
+fn start_local(parity: &str, dir: &Path, name: &str) -> Result<Child, Error> {
+    Ok(Command::new(parity)
+        .current_dir(dir)
+        .args(&["--config", &format!("{}.toml", name)])
+        .spawn()?)
+}
+
+fn start_remote(
+    parity: &str,
+    dir: &Path,
+    user: &str,
+    host: &str,
+    name: &str,
+) -> Result<Child, Error> {
+    let remote_dir = dir
+        .file_name()
+        .ok_or_else(|| format_err!("Invalid network directory"))?
+        .to_string_lossy();
+    let status = Command::new("rsync")
+        .args(&["-az", "--delete"])
+        .arg(dir)
+        .arg(format!("{}@{}:~/", user, host))
+        .status()?;
+    if !status.success() {
+        return Err(format_err!("Copying the network to {} failed", host));
+    }
+    /* The host keys have to be known already, ssh runs the command through the remote shell */
+    Ok(Command::new("ssh")
+        .args(&["-l", user, "--", host])
+        .arg(format!(
+            "cd {} && {} --config {}",
+            quote(&remote_dir),
+            quote(parity),
+            quote(&format!("{}.toml", name))
+        ))
+        .spawn()?)
+}
+
+/* Single quotes for the remote shell, a quote inside is closed, escaped and reopened */
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
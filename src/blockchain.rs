@@ -1,15 +1,23 @@
-pub use crate::api::contract::AclContract;
-pub use crate::api::secretstore::SecretStore;
+use crate::acl::{AccessControl, EthereumAcl};
+pub use crate::api::shadow::Error as ShadowError;
+pub use crate::api::shadow::ErrorKind as ShadowErrorKind;
 pub use crate::api::shadow::Shadow;
+use crate::api::transport::{self, RpcTransport};
+use crate::config::Config;
+use crate::grant::{AccessEvent, Grant};
+use crate::keyserver::KeyServer;
+use crate::node::{Node, RpcNode};
+use crate::permit::{Permit, SignedPermit};
+use crate::threshold::ThresholdError;
 use crate::Data;
 use crate::Password;
-use ethcontract::transaction::Account;
 use ethcontract::transaction::TransactionResult;
-use failure::{Error, SyncFailure};
-use primitive_types::{H160, H256, H512};
+use failure::{format_err, Error};
+use futures::stream::{self, StreamExt};
+use primitive_types::{H160, H256, H512, U256};
 use serde_derive::{Deserialize, Serialize};
+use std::ops::Range;
 use std::str::FromStr;
-use web3::transports::EventLoopHandle;
 
 #[derive(Default, Debug, Serialize, Deserialize, PartialEq)]
 pub struct EncryptedDocumentKey {
@@ -25,84 +33,305 @@ pub struct DecryptionKeys {
     pub decrypt_shadows: Vec<Data>,
 }
 
-pub struct Blockchain {
-    ss_client: SecretStore<jsonrpc_client_http::HttpHandle>,
-    shadow_client: Shadow,
-    contract: AclContract,
-    /* Dropping the event loop will break the web3 connection */
-    #[allow(dead_code)]
-    eloop: EventLoopHandle,
+/// What to do when a document key is already stored for a document ID
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyReuse {
+    Deny,
+    Allow,
 }
 
-impl Blockchain {
+/// Transaction for a part of the documents of a batch grant or revocation.
+#[derive(Debug)]
+pub struct ChunkResult {
+    /// Positions of the documents in the batch
+    pub documents: Range<usize>,
+    pub result: Result<TransactionResult, Error>,
+}
+
+fn parse_ids(document_ids: &[&str]) -> Result<Vec<H256>, Error> {
+    document_ids
+        .iter()
+        .map(|id| Ok(H256::from_str(id)?))
+        .collect()
+}
+
+/* Document key IDs of (document ID, document) pairs to sign */
+fn hashes(documents: &[(&str, &str)]) -> Vec<Result<H256, Error>> {
+    documents
+        .iter()
+        .map(|(id, _)| Ok(H256::from_str(id)?))
+        .collect()
+}
+
+/* Key to encrypt a new document with */
+enum ServerKey {
+    /* Public server key, the document key still has to be generated */
+    Public(Data),
+    /* Document key which is already stored, encrypted for the requester */
+    Stored(Data),
+}
+
+/* A server key without document key (e.g. after a crash between the two requests) is
+ * reused if it has the requested threshold. The Secret Store HTTP API can't tell the
+ * threshold of a server key, then the key is reused anyway and the document gets the
+ * threshold of the existing key */
+async fn server_key<K: KeyServer>(
+    key_server: &K,
+    document_id: &str,
+    signed_document_key_id: &Data,
+    threshold: u32,
+) -> Result<ServerKey, Error> {
+    match key_server
+        .generate_server_key(document_id, signed_document_key_id, threshold)
+        .await
+    {
+        Ok(key) => return Ok(ServerKey::Public(key)),
+        Err(ShadowError::Response {
+            kind: ShadowErrorKind::KeyAlreadyGenerated,
+            ..
+        }) => (),
+        Err(error) => return Err(error.into()),
+    }
+
+    match key_server
+        .get_encrypted_document_key(document_id, signed_document_key_id)
+        .await
+    {
+        Ok(encrypted_key) => return Ok(ServerKey::Stored(encrypted_key)),
+        Err(ShadowError::Response {
+            kind: ShadowErrorKind::DocumentKeyNotFound,
+            ..
+        }) => (),
+        Err(error) => return Err(error.into()),
+    }
+
+    if let Some(stored) = key_server
+        .server_key_threshold(document_id, signed_document_key_id)
+        .await?
+    {
+        if stored != threshold {
+            return Err(ThresholdError::Mismatch {
+                stored,
+                requested: threshold,
+            }
+            .into());
+        }
+    }
+    Ok(ServerKey::Public(
+        key_server
+            .get_server_key(document_id, signed_document_key_id)
+            .await?,
+    ))
+}
+
+pub struct Blockchain<A: AccessControl = EthereumAcl, K: KeyServer = Shadow, N: Node = RpcNode> {
+    node: N,
+    key_server: K,
+    acl: A,
+    cluster_size: Option<usize>,
+}
+
+impl Blockchain<EthereumAcl> {
     /* Address and password of the user who creates the initial contract
      * TODO: shouldn't be required since we create the contract on the setup of the network */
-    pub fn new(
+    pub fn new(address: H160, password: &Password, config: &Config) -> Result<Blockchain, Error> {
+        let acl = EthereumAcl::new(address, password, config)?;
+        let mut blockchain = Blockchain::with_access_control(acl, config)?;
+        /* Ask the KeyServerSet contract if the config doesn't list the nodes */
+        if let (None, Some(contract)) = (blockchain.cluster_size, config.server_set_contract) {
+            blockchain.cluster_size = Some(blockchain.acl.server_set_size(contract)?);
+        }
+        Ok(blockchain)
+    }
+
+    /* Deploy a new ACL contract and write its address to the configured address file */
+    pub fn deploy_contract(
+        address: H160,
+        password: &Password,
+        config: &Config,
+    ) -> Result<H160, Error> {
+        EthereumAcl::deploy(address, password, config)
+    }
+
+    /* Remove the grants of addresses which ran out, this emits AccessExpired events */
+    pub async fn expire(
+        &self,
         address: H160,
         password: &Password,
-        shadow: &str,
-        jsonrpc: &str,
-    ) -> Result<Blockchain, Error> {
-        let shadow_client = Shadow::new(shadow);
-        let ss_client = SecretStore::create(jsonrpc)?;
+        document_id: &str,
+        addresses: &[H160],
+    ) -> Result<TransactionResult, Error> {
+        let document_id = H256::from_str(document_id)?;
+        self.acl
+            .expire(address, password, document_id, addresses)
+            .await
+    }
+
+    pub async fn access_events(
+        &self,
+        from_block: u64,
+        document_id: Option<&str>,
+    ) -> Result<Vec<(u64, AccessEvent)>, Error> {
+        let document_id = document_id.map(H256::from_str).transpose()?;
+        self.acl.access_events(from_block, document_id).await
+    }
+
+    /* Sign a grant with the owner's account, the permit can be submitted by anybody */
+    pub async fn sign_permit(
+        &self,
+        address: H160,
+        password: &Password,
+        document_id: &str,
+        addresses: &[H160],
+        deadline: U256,
+    ) -> Result<SignedPermit, Error> {
+        let document_id = H256::from_str(document_id)?;
+        let (nonce, domain_separator) = self.acl.permit_domain(address).await?;
+
+        let permit = Permit {
+            document_id,
+            users: addresses.into(),
+            owner: address,
+            nonce,
+            deadline,
+        };
+
+        let signature = self
+            .node
+            .sign_raw_hash(address, password, permit.digest(domain_separator))
+            .await?;
+
+        SignedPermit::from_signature(permit, &signature)
+    }
+
+    /* Submit a permit signed by the document owner, the relayer pays for the gas */
+    pub async fn submit_permit(
+        &self,
+        relayer: H160,
+        password: &Password,
+        signed_permit: &SignedPermit,
+    ) -> Result<TransactionResult, Error> {
+        self.acl
+            .submit_permit(relayer, password, signed_permit)
+            .await
+    }
+}
 
-        let (eloop, transport) = web3::transports::Http::new(jsonrpc)?;
-        let web3 = web3::Web3::new(transport);
-        let account = Account::Locked(address, password.into(), None);
+impl<A: AccessControl> Blockchain<A> {
+    pub fn with_access_control(acl: A, config: &Config) -> Result<Blockchain<A>, Error> {
+        let client = transport::shared_client(&config.transport)?;
+        let key_server = Shadow::with_client(config.shadow_url.as_str(), client);
+        Blockchain::with_key_server(acl, key_server, config)
+    }
+}
 
-        let contract = AclContract::new(account, &web3)?;
+impl<A: AccessControl, K: KeyServer> Blockchain<A, K> {
+    /* The Secret Store node is still needed to sign and to encrypt and decrypt */
+    pub fn with_key_server(
+        acl: A,
+        key_server: K,
+        config: &Config,
+    ) -> Result<Blockchain<A, K>, Error> {
+        let rpc = RpcTransport::new(
+            config.rpc_url.as_str(),
+            transport::shared_client(&config.transport)?,
+        )?;
+        Ok(Blockchain::with_node(
+            acl,
+            key_server,
+            RpcNode::new(rpc),
+            config,
+        ))
+    }
+}
+
+impl<A: AccessControl, K: KeyServer, N: Node> Blockchain<A, K, N> {
+    /* Another node than the one in the config, e.g. LocalNode for tests */
+    pub fn with_node(acl: A, key_server: K, node: N, config: &Config) -> Blockchain<A, K, N> {
+        let cluster_size = if config.nodes.is_empty() {
+            None
+        } else {
+            Some(config.nodes.len())
+        };
+
+        Blockchain {
+            node,
+            key_server,
+            acl,
+            cluster_size,
+        }
+    }
 
-        Ok(Blockchain {
-            ss_client,
-            shadow_client,
-            contract,
-            eloop,
-        })
+    pub fn access_control(&self) -> &A {
+        &self.acl
     }
 
-    /* Todo: make self not mut, but SecretStore requires it */
+    pub fn key_server(&self) -> &K {
+        &self.key_server
+    }
+
+    /* Number of Secret Store nodes, None if it isn't known */
+    pub fn cluster_size(&self) -> Option<usize> {
+        self.cluster_size
+    }
+
+    /* Make sure the password belongs to the address by letting the node sign with it */
+    pub async fn verify_credentials(
+        &self,
+        address: H160,
+        password: &Password,
+    ) -> Result<(), Error> {
+        self.node.verify_credentials(address, password).await
+    }
+
+    /* If the document key is already stored the document is encrypted with it only if
+     * reuse is allowed, this requires access to the document. A server key with another
+     * threshold is never reused */
     pub async fn encrypt(
-        &mut self,
+        &self,
         address: H160,
         password: &Password,
         document_id: &str,
         document: &str,
         threshold: u32,
+        reuse: KeyReuse,
     ) -> Result<Data, Error> {
         // Sign the document key id
         let signed_document_key_id = self
-            .ss_client
-            .secretstore_signRawHash(address, password, H256::from_str(document_id)?)
-            .call()
-            .map_err(SyncFailure::new)?;
-
-        let public_server_key = self
-            .shadow_client
-            .generate_server_key(document_id, &signed_document_key_id, threshold)
+            .node
+            .sign_raw_hash(address, password, H256::from_str(document_id)?)
             .await?;
 
+        let public_server_key = match server_key(
+            &self.key_server,
+            document_id,
+            &signed_document_key_id,
+            threshold,
+        )
+        .await?
+        {
+            ServerKey::Public(key) => key,
+            ServerKey::Stored(encrypted_key) => {
+                if reuse == KeyReuse::Deny {
+                    return Err(ShadowError::new(ShadowErrorKind::KeyAlreadyStored).into());
+                }
+                return self
+                    .encrypt_with_key(address, password, encrypted_key, document)
+                    .await;
+            }
+        };
+
         let encrypted_key = self
-            .ss_client
-            .secretstore_generateDocumentKey(
+            .node
+            .generate_document_key(
                 address,
                 password,
                 H512::from_str(&public_server_key.trim_start_matches("0x"))?,
             )
-            .call()
-            .map_err(SyncFailure::new)?;
-
-        let encrypted_document = self
-            .ss_client
-            .secretstore_encrypt(
-                address,
-                password,
-                encrypted_key.encrypted_key,
-                format!("0x{}", hex::encode(document)),
-            )
-            .call()
-            .map_err(SyncFailure::new)?;
+            .await?;
 
-        self.shadow_client
+        /* Store the key first, so a failure afterwards leaves a key which can be reused */
+        self.key_server
             .store_document_key(
                 document_id,
                 &signed_document_key_id,
@@ -110,89 +339,418 @@ impl Blockchain {
                 encrypted_key.encrypted_point,
             )
             .await?;
-        Ok(encrypted_document.into())
+
+        self.encrypt_with_key(address, password, encrypted_key.encrypted_key, document)
+            .await
+    }
+
+    async fn encrypt_with_key(
+        &self,
+        address: H160,
+        password: &Password,
+        encrypted_key: Data,
+        document: &str,
+    ) -> Result<Data, Error> {
+        self.node
+            .encrypt(
+                address,
+                password,
+                encrypted_key,
+                format!("0x{}", hex::encode(document)),
+            )
+            .await
     }
 
-    /* Todo: make self not mut, but SecretStore requires it */
     pub async fn decrypt(
-        &mut self,
+        &self,
         address: H160,
         password: &Password,
         document_id: &str,
         encrypted_document: &Data,
     ) -> Result<Data, Error> {
         let signed_document_key_id = self
-            .ss_client
-            .secretstore_signRawHash(address, password, H256::from_str(document_id)?)
-            .call()
-            .map_err(SyncFailure::new)?;
+            .node
+            .sign_raw_hash(address, password, H256::from_str(document_id)?)
+            .await?;
 
         let key = self
-            .shadow_client
+            .key_server
             .get_document_key(document_id, &signed_document_key_id)
             .await?;
 
         let hashed_document = self
-            .ss_client
-            .secretstore_shadowDecrypt(
-                address,
-                password,
-                key.decrypted_secret,
-                key.common_point,
-                key.decrypt_shadows,
-                &encrypted_document,
-            )
-            .call()
-            .map_err(SyncFailure::new)?;
+            .node
+            .shadow_decrypt(address, password, key, encrypted_document)
+            .await?;
 
         Ok(Data::from_utf8(hex::decode(
             hashed_document.trim_start_matches("0x"),
         )?)?)
     }
 
+    /* Encrypt documents given as (document ID, document). Every phase is one JSON-RPC
+     * batch for all documents, the Secret Store requests run with at most parallelism
+     * requests at a time. A document which fails doesn't fail the others, existing
+     * document keys aren't reused */
+    pub async fn encrypt_many(
+        &self,
+        address: H160,
+        password: &Password,
+        documents: &[(&str, &str)],
+        threshold: u32,
+        parallelism: usize,
+    ) -> Result<Vec<Result<Data, Error>>, Error> {
+        let signatures = self
+            .node
+            .sign_raw_hashes(address, password, hashes(documents))
+            .await?;
+
+        let key_server = &self.key_server;
+        let server_keys: Vec<Result<(Data, Data), Error>> =
+            stream::iter(documents.iter().zip(signatures))
+                .map(|((id, _), signature)| async move {
+                    let signature = signature?;
+                    match server_key(key_server, id, &signature, threshold).await? {
+                        ServerKey::Public(key) => Ok::<_, Error>((signature, key)),
+                        ServerKey::Stored(_) => {
+                            Err(ShadowError::new(ShadowErrorKind::KeyAlreadyStored).into())
+                        }
+                    }
+                })
+                .buffered(parallelism.max(1))
+                .collect()
+                .await;
+
+        let mut signatures = Vec::new();
+        let mut public_keys = Vec::new();
+        for server_key in server_keys {
+            match server_key {
+                Ok((signature, key)) => {
+                    signatures.push(Some(signature));
+                    public_keys
+                        .push(H512::from_str(key.trim_start_matches("0x")).map_err(Error::from));
+                }
+                Err(error) => {
+                    signatures.push(None);
+                    public_keys.push(Err(error));
+                }
+            }
+        }
+        let document_keys = self
+            .node
+            .generate_document_keys(address, password, public_keys)
+            .await?;
+
+        /* Store the keys first, so a failure afterwards leaves keys which can be reused */
+        let key_server = &self.key_server;
+        let stored: Vec<Result<Data, Error>> =
+            stream::iter(documents.iter().zip(signatures).zip(document_keys))
+                .map(|(((id, _), signature), key)| async move {
+                    let key = key?;
+                    let signature = signature.ok_or_else(|| format_err!("Missing signature"))?;
+                    key_server
+                        .store_document_key(id, &signature, key.common_point, key.encrypted_point)
+                        .await?;
+                    Ok::<_, Error>(key.encrypted_key)
+                })
+                .buffered(parallelism.max(1))
+                .collect()
+                .await;
+
+        self.node
+            .encrypt_many(
+                address,
+                password,
+                documents
+                    .iter()
+                    .zip(stored)
+                    .map(|((_, document), key)| Ok((key?, format!("0x{}", hex::encode(document)))))
+                    .collect(),
+            )
+            .await
+    }
+
+    /* Decrypt documents given as (document ID, encrypted document), batched like
+     * encrypt_many */
+    pub async fn decrypt_many(
+        &self,
+        address: H160,
+        password: &Password,
+        documents: &[(&str, &str)],
+        parallelism: usize,
+    ) -> Result<Vec<Result<Data, Error>>, Error> {
+        let signatures = self
+            .node
+            .sign_raw_hashes(address, password, hashes(documents))
+            .await?;
+
+        let key_server = &self.key_server;
+        let keys: Vec<Result<DecryptionKeys, Error>> =
+            stream::iter(documents.iter().zip(signatures))
+                .map(|((id, _), signature)| async move {
+                    Ok::<_, Error>(key_server.get_document_key(id, &signature?).await?)
+                })
+                .buffered(parallelism.max(1))
+                .collect()
+                .await;
+
+        let decrypted = self
+            .node
+            .shadow_decrypt_many(
+                address,
+                password,
+                documents
+                    .iter()
+                    .zip(keys)
+                    .map(|((_, encrypted_document), key)| {
+                        Ok((key?, encrypted_document.to_string()))
+                    })
+                    .collect(),
+            )
+            .await?;
+
+        Ok(decrypted
+            .into_iter()
+            .map(|document| {
+                let document = hex::decode(document?.trim_start_matches("0x"))?;
+                Ok(Data::from_utf8(document)?)
+            })
+            .collect())
+    }
+
+    pub async fn is_reachable(&self) -> bool {
+        self.acl.is_reachable().await
+    }
+
+    pub async fn receipt_status(&self, transaction: H256) -> Result<Option<bool>, Error> {
+        self.acl.transaction_status(transaction).await
+    }
+
     pub async fn allow_access(
-        &mut self,
+        &self,
+        address: H160,
+        password: &Password,
+        document_id: &str,
+        addresses: &[H160],
+    ) -> Result<TransactionResult, Error> {
+        let document_id = H256::from_str(document_id)?;
+        self.acl
+            .grant(address, password, document_id, addresses, None, None)
+            .await
+    }
+
+    /* Grant access between two block timestamps, None means no limit */
+    pub async fn allow_access_until(
+        &self,
         address: H160,
         password: &Password,
         document_id: &str,
         addresses: &[H160],
+        valid_from: Option<u64>,
+        valid_until: Option<u64>,
     ) -> Result<TransactionResult, Error> {
-        let document_id = H256::from_str(document_id).unwrap();
-        let account = Account::Locked(address, password.into(), None);
-        let result = self
-            .contract
-            .allow_access(document_id.into(), addresses.into())
-            .from(account.clone())
-            .into_inner()
-            .estimate_gas()
+        let document_id = H256::from_str(document_id)?;
+        self.acl
+            .grant(
+                address,
+                password,
+                document_id,
+                addresses,
+                valid_from,
+                valid_until,
+            )
             .await
-            .map_err(SyncFailure::new)?;
-        println!("Gas price {:?}", result);
-
-        let result = self
-            .contract
-            .allow_access(document_id.into(), addresses.into())
-            .from(account)
-            .gas(8000000.into())
-            .send()
+    }
+
+    pub async fn revoke_access(
+        &self,
+        address: H160,
+        password: &Password,
+        document_id: &str,
+        addresses: &[H160],
+    ) -> Result<TransactionResult, Error> {
+        let document_id = H256::from_str(document_id)?;
+        self.acl
+            .revoke(address, password, document_id, addresses)
             .await
-            .map_err(SyncFailure::new)?;
-        Ok(result)
     }
 
+    /* Grant access to many documents, split into as few transactions as fit under the
+     * block gas limit. Returns the result of every transaction */
+    pub async fn allow_access_batch(
+        &self,
+        address: H160,
+        password: &Password,
+        document_ids: &[&str],
+        addresses: &[H160],
+    ) -> Result<Vec<ChunkResult>, Error> {
+        let document_ids = parse_ids(document_ids)?;
+        let mut results = Vec::new();
+        for documents in self.chunks(address, &document_ids, addresses).await? {
+            let result = self
+                .acl
+                .grant_many(
+                    address,
+                    password,
+                    &document_ids[documents.clone()],
+                    addresses,
+                )
+                .await;
+            results.push(ChunkResult { documents, result });
+        }
+        Ok(results)
+    }
+
+    pub async fn revoke_access_batch(
+        &self,
+        address: H160,
+        password: &Password,
+        document_ids: &[&str],
+        addresses: &[H160],
+    ) -> Result<Vec<ChunkResult>, Error> {
+        let document_ids = parse_ids(document_ids)?;
+        let mut results = Vec::new();
+        for documents in self.chunks(address, &document_ids, addresses).await? {
+            let result = self
+                .acl
+                .revoke_many(
+                    address,
+                    password,
+                    &document_ids[documents.clone()],
+                    addresses,
+                )
+                .await;
+            results.push(ChunkResult { documents, result });
+        }
+        Ok(results)
+    }
+
+    async fn chunks(
+        &self,
+        address: H160,
+        document_ids: &[H256],
+        addresses: &[H160],
+    ) -> Result<Vec<Range<usize>>, Error> {
+        if document_ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let size = self
+            .acl
+            .documents_per_transaction(address, addresses)
+            .await?
+            .max(1);
+        Ok((0..document_ids.len())
+            .step_by(size)
+            .map(|start| start..(start.saturating_add(size)).min(document_ids.len()))
+            .collect())
+    }
+
+    /* Whether the user has access right now, together with the validity window of the grant */
     pub async fn check_permissions(
-        &mut self,
+        &self,
         address: H160,
         document_id: &str,
-    ) -> Result<bool, Error> {
-        let document_id = H256::from_str(document_id).unwrap();
-        let result = self
-            .contract
-            .check_permissions(address, document_id.into())
-            .from(address)
-            .call()
-            .await
-            .map_err(SyncFailure::new)?;
-        Ok(result)
+    ) -> Result<Grant, Error> {
+        let document_id = H256::from_str(document_id)?;
+        self.acl.check(address, document_id).await
+    }
+
+    pub async fn owner_of(&self, document_id: &str) -> Result<H160, Error> {
+        let document_id = H256::from_str(document_id)?;
+        self.acl.owner(document_id).await
+    }
+
+    /* Addresses with a grant for the document, the owner isn't included */
+    pub async fn members_of(&self, document_id: &str) -> Result<Vec<H160>, Error> {
+        let document_id = H256::from_str(document_id)?;
+        self.acl.members(document_id).await
+    }
+
+    pub async fn member_count(&self, document_id: &str) -> Result<u64, Error> {
+        let document_id = H256::from_str(document_id)?;
+        self.acl.member_count(document_id).await
+    }
+
+    /* At most count members starting at start */
+    pub async fn members_page(
+        &self,
+        document_id: &str,
+        start: u64,
+        count: u64,
+    ) -> Result<Vec<H160>, Error> {
+        let document_id = H256::from_str(document_id)?;
+        self.acl.members_page(document_id, start, count).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::transport::Executor;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /* Recorded Secret Store error of kind */
+    fn fixture(kind: &str) -> (u16, String) {
+        let fixtures: Vec<serde_json::Value> =
+            serde_json::from_str(include_str!("api/fixtures/shadow_errors.json")).unwrap();
+        let fixture = fixtures
+            .into_iter()
+            .find(|fixture| fixture["kind"] == kind)
+            .unwrap();
+        (
+            fixture["status"].as_u64().unwrap() as u16,
+            fixture["body"].as_str().unwrap().into(),
+        )
+    }
+
+    /* Secret Store answering every request with the response for its method and path */
+    fn shadow(responses: Vec<(&'static str, (u16, String))>) -> (Shadow, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            for _ in 0..responses.len() {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buffer).unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                let request = String::from_utf8(request).unwrap();
+                let (_, (status, body)) = responses
+                    .iter()
+                    .find(|(prefix, _)| request.starts_with(prefix))
+                    .unwrap_or_else(|| panic!("Unexpected request {}", request));
+                let response = format!(
+                    "HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (Shadow::new(url.as_str()), server)
+    }
+
+    #[test]
+    fn reuse_server_key() {
+        let id = format!("{:x}", H256::repeat_byte(1));
+        let public = format!("0x{:x}", H512::repeat_byte(2));
+        let (shadow, server) = shadow(vec![
+            ("POST /shadow/", fixture("key_already_generated")),
+            ("GET /server/", (200, format!("\"{}\"", public))),
+            ("GET /", fixture("document_key_not_found")),
+        ]);
+        let key = Executor::current()
+            .block_on(server_key(&shadow, &id, &"0x00".to_string(), 1))
+            .unwrap();
+        match key {
+            ServerKey::Public(key) => assert_eq!(key, public),
+            ServerKey::Stored(_) => panic!("Expected the public server key"),
+        }
+        server.join().unwrap();
     }
 }
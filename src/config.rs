@@ -0,0 +1,111 @@
+use failure::Error;
+use primitive_types::{H160, H256};
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Settings needed to connect to the Secret Store and the user's node.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Config {
+    /// Secret Store HTTP API
+    pub shadow_url: String,
+    /// JSON-RPC API of the user's node
+    pub rpc_url: String,
+    /// Secret Store nodes of the network as `public_key@host:port`
+    pub nodes: Vec<String>,
+    /// KeyServerSet contract of the Secret Store, used to find the number of nodes if
+    /// `nodes` is empty
+    pub server_set_contract: Option<H160>,
+    /// Seconds the account stays unlocked on the user's node for contract transactions,
+    /// 0 sends the password with every transaction
+    pub unlock_duration: u16,
+    /// File in which grants and revocations are kept until the node confirms them,
+    /// they are only kept in memory if it isn't set
+    pub outbox_file: Option<PathBuf>,
+    /// Requests to the Secret Store HTTP API which run at the same time in encrypt_many
+    /// and decrypt_many
+    pub parallel_requests: usize,
+    pub contract: ContractConfig,
+    pub transport: TransportConfig,
+}
+
+/// Where the ACL contract is found and how it is checked before it's used.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ContractConfig {
+    /// File containing the address of the deployed SSPermissions contract
+    pub address_file: PathBuf,
+    /// Expected keccak256 hash of the deployed code, by default the code has to match
+    /// the `deployedBytecode` of SSPermissions.json
+    pub code_hash: Option<H256>,
+    /// Expected chain ID of the user's node, needed if the address file doesn't record one
+    pub chain_id: Option<u64>,
+    /// Trust the contract address without any checks, only use this on development chains
+    pub skip_verification: bool,
+}
+
+/// HTTP settings used for the Secret Store and the user's node.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(default)]
+pub struct TransportConfig {
+    /// Seconds a request may take, including reading the response
+    pub timeout: Option<u64>,
+    /// Seconds to wait for the connection
+    pub connect_timeout: Option<u64>,
+    /// PEM files of additional trusted root certificates, e.g. of the reverse proxy
+    pub ca_certificates: Vec<PathBuf>,
+    /// PKCS#12 file with the client certificate and key for mutual TLS
+    pub client_identity: Option<PathBuf>,
+    pub client_identity_password: Option<String>,
+    /// Proxy for all requests, e.g. `http://proxy.example.com:3128`
+    pub proxy: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            shadow_url: "http://127.0.0.1:8010".into(),
+            rpc_url: "http://127.0.0.1:8545".into(),
+            nodes: vec![],
+            server_set_contract: None,
+            unlock_duration: 60,
+            outbox_file: None,
+            parallel_requests: 8,
+            contract: ContractConfig::default(),
+            transport: TransportConfig::default(),
+        }
+    }
+}
+
+impl Default for ContractConfig {
+    fn default() -> Self {
+        ContractConfig {
+            address_file: "./contract-address.txt".into(),
+            code_hash: None,
+            chain_id: None,
+            skip_verification: false,
+        }
+    }
+}
+
+/* A hung node shouldn't block forever, but generating a server key takes a while */
+impl Default for TransportConfig {
+    fn default() -> Self {
+        TransportConfig {
+            timeout: Some(120),
+            connect_timeout: Some(10),
+            ca_certificates: vec![],
+            client_identity: None,
+            client_identity_password: None,
+            proxy: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Config, Error> {
+        let contents = fs::read_to_string(path)?;
+        Ok(toml::from_str(&contents)?)
+    }
+}
@@ -0,0 +1,681 @@
+/* Local HTTP API of secretstore-daemon: token auth, routing and the mapping of errors to
+ * HTTP statuses. The binary only parses its options and calls load and serve_tcp or
+ * serve_socket. */
+use crate::acl::{AccessControl, EthereumAcl};
+use crate::blockchain::ShadowError;
+use crate::config::Config;
+use crate::error::ErrorClass;
+use crate::id::DocumentId;
+use crate::keyserver::{KeyServer, Shadow};
+use crate::node::{Node, RpcNode};
+use crate::secret::{SecretString, StaticPassword};
+use crate::{generate_id, CryptoSecretStore};
+use failure::{format_err, Error};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::info;
+use primitive_types::H160;
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fs;
+use std::net::SocketAddr;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::net::UnixListener;
+
+/* One entry per caller, several tokens may use the same account:
+ *
+ * [[tokens]]
+ * name = "push-gateway"
+ * token = "..."
+ * address = "27d39a0fe767025e7ea0f78dccd4665929e3a8f2"
+ * password_file = "network/alice.pwd"
+ */
+#[derive(Deserialize)]
+pub struct TokensFile {
+    tokens: Vec<TokenEntry>,
+}
+
+#[derive(Deserialize)]
+struct TokenEntry {
+    /// Shown in the request log instead of the token
+    name: String,
+    token: String,
+    address: String,
+    password_file: PathBuf,
+}
+
+struct Caller<A: AccessControl, K: KeyServer, N: Node> {
+    name: String,
+    store: Arc<CryptoSecretStore<A, K, N>>,
+}
+
+/// Callers of the API by the hash of their token, so looking a token up doesn't leak how
+/// much of it matched.
+pub struct State<A: AccessControl = EthereumAcl, K: KeyServer = Shadow, N: Node = RpcNode> {
+    callers: HashMap<Vec<u8>, Arc<Caller<A, K, N>>>,
+}
+
+impl<A: AccessControl, K: KeyServer, N: Node> State<A, K, N> {
+    pub fn new() -> Self {
+        State {
+            callers: HashMap::new(),
+        }
+    }
+
+    /* Requests with token run as store, every token can only be used once */
+    pub fn add(
+        &mut self,
+        name: &str,
+        token: &str,
+        store: Arc<CryptoSecretStore<A, K, N>>,
+    ) -> Result<(), Error> {
+        let hash = token_hash(token);
+        if self.callers.contains_key(&hash) {
+            return Err(format_err!("Token of {} is used twice", name));
+        }
+        self.callers.insert(
+            hash,
+            Arc::new(Caller {
+                name: name.into(),
+                store,
+            }),
+        );
+        Ok(())
+    }
+}
+
+impl<A: AccessControl, K: KeyServer, N: Node> Default for State<A, K, N> {
+    fn default() -> Self {
+        State::new()
+    }
+}
+
+/* Every account gets one handle, all handles share the connections of the first one. The
+ * credentials of every account are checked against the node before the daemon starts */
+pub fn load(tokens: TokensFile, config: &Config) -> Result<State, Error> {
+    let mut base: Option<CryptoSecretStore> = None;
+    let mut stores: HashMap<H160, Arc<CryptoSecretStore>> = HashMap::new();
+    let mut state = State::new();
+
+    for entry in tokens.tokens {
+        let address = parse_address(&entry.address)?;
+        let store = match stores.get(&address) {
+            Some(store) => store.clone(),
+            None => {
+                let password =
+                    SecretString::from(fs::read_to_string(&entry.password_file)?.trim_end());
+                let hex_address = format!("{:x}", address);
+                if base.is_none() {
+                    base = Some(CryptoSecretStore::with_credentials(
+                        &hex_address,
+                        StaticPassword::new(password.clone()),
+                        config,
+                    )?);
+                }
+                let store = base
+                    .as_ref()
+                    .unwrap()
+                    .as_identity(&hex_address, StaticPassword::new(password))?;
+                let store = Arc::new(store);
+                stores.insert(address, store.clone());
+                store
+            }
+        };
+        state.add(&entry.name, &entry.token, store)?;
+    }
+
+    if state.callers.is_empty() {
+        return Err(format_err!("No tokens configured"));
+    }
+    Ok(state)
+}
+
+/* The API must not be reachable from the network */
+pub fn check_listen(address: &SocketAddr) -> Result<(), Error> {
+    if !address.ip().is_loopback() {
+        return Err(format_err!(
+            "{} isn't a loopback address, the API must not be reachable from the network",
+            address
+        ));
+    }
+    Ok(())
+}
+
+/* Only a socket left by a previous run is replaced, never a file given by mistake. The socket
+ * is created accessible by the user only, there's no window in which others can connect */
+pub fn bind_socket(path: &Path) -> Result<UnixListener, Error> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(format_err!(
+                "{} exists and isn't a socket, refusing to replace it",
+                path.display()
+            ))
+        }
+        Err(_) => {}
+    }
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(umask) };
+    Ok(listener?)
+}
+
+pub async fn serve_tcp<A, K, N>(state: State<A, K, N>, address: SocketAddr) -> Result<(), Error>
+where
+    A: AccessControl + 'static,
+    K: KeyServer + 'static,
+    N: Node + 'static,
+    CryptoSecretStore<A, K, N>: Send + Sync,
+{
+    check_listen(&address)?;
+    let state = Arc::new(state);
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req))) }
+    });
+    info!("Listening on {}", address);
+    Server::try_bind(&address)?.serve(make_service).await?;
+    Ok(())
+}
+
+pub async fn serve_socket<A, K, N>(state: State<A, K, N>, path: &Path) -> Result<(), Error>
+where
+    A: AccessControl + 'static,
+    K: KeyServer + 'static,
+    N: Node + 'static,
+    CryptoSecretStore<A, K, N>: Send + Sync,
+{
+    let mut listener = bind_socket(path)?;
+    let state = Arc::new(state);
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req))) }
+    });
+    info!("Listening on {}", path.display());
+    let incoming = hyper::server::accept::from_stream(listener.incoming());
+    Server::builder(incoming).serve(make_service).await?;
+    Ok(())
+}
+
+#[derive(Debug)]
+struct ApiError {
+    status: StatusCode,
+    class: &'static str,
+    kind: Option<String>,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, class: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError {
+            status,
+            class,
+            kind: None,
+            message: message.into(),
+        }
+    }
+
+    fn bad_request(message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::BAD_REQUEST, "invalid_request", message)
+    }
+
+    fn response(&self) -> Response<Body> {
+        json_response(
+            self.status,
+            json!({
+                "error": {
+                    "class": self.class,
+                    "kind": self.kind,
+                    "message": self.message,
+                }
+            }),
+        )
+    }
+}
+
+/* The class decides the HTTP status, the kind is the ShadowErrorKind for errors of the
+ * Secret Store */
+impl From<Error> for ApiError {
+    fn from(error: Error) -> Self {
+        let (status, class) = match ErrorClass::of(&error) {
+            ErrorClass::AccessDenied => (StatusCode::FORBIDDEN, "access_denied"),
+            ErrorClass::KeyNotFound => (StatusCode::NOT_FOUND, "key_not_found"),
+            ErrorClass::KeyExists => (StatusCode::CONFLICT, "key_exists"),
+            ErrorClass::InvalidThreshold => (StatusCode::BAD_REQUEST, "invalid_threshold"),
+            ErrorClass::Unavailable => (StatusCode::SERVICE_UNAVAILABLE, "unavailable"),
+            ErrorClass::Other => (StatusCode::INTERNAL_SERVER_ERROR, "other"),
+        };
+        ApiError {
+            status,
+            class,
+            kind: ErrorClass::kind_name(&error),
+            message: match error.downcast_ref::<ShadowError>() {
+                Some(ShadowError::Response { message, .. }) => message.clone(),
+                _ => error.to_string(),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct IdRequest {
+    document: String,
+}
+
+#[derive(Deserialize)]
+struct EncryptRequest {
+    /// A random ID if omitted, so the ID doesn't reveal the document
+    id: Option<String>,
+    document: String,
+    #[serde(default = "default_threshold")]
+    threshold: u32,
+}
+
+fn default_threshold() -> u32 {
+    1
+}
+
+#[derive(Deserialize)]
+struct DecryptRequest {
+    id: String,
+    ciphertext: String,
+}
+
+#[derive(Deserialize)]
+struct GrantRequest {
+    id: String,
+    addresses: Vec<String>,
+    valid_from: Option<u64>,
+    valid_until: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct RevokeRequest {
+    id: String,
+    addresses: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct CheckRequest {
+    id: String,
+    address: String,
+}
+
+fn token_hash(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+fn parse_address(address: &str) -> Result<H160, Error> {
+    H160::from_str(address.trim_start_matches("0x"))
+        .map_err(|_| format_err!("Invalid address {}", address))
+}
+
+fn parse_addresses(addresses: &[String]) -> Result<Vec<H160>, ApiError> {
+    addresses
+        .iter()
+        .map(|address| parse_address(address).map_err(|err| ApiError::bad_request(err.to_string())))
+        .collect()
+}
+
+/* Logs every request with the caller, never with the token or the body */
+async fn handle<A, K, N>(
+    state: Arc<State<A, K, N>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible>
+where
+    A: AccessControl + 'static,
+    K: KeyServer + 'static,
+    N: Node + 'static,
+    CryptoSecretStore<A, K, N>: Send + Sync,
+{
+    let start = Instant::now();
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+
+    let caller = authorize(&state, &req);
+    let name = caller
+        .as_ref()
+        .map(|caller| caller.name.clone())
+        .unwrap_or_else(|_| "-".into());
+    let response = match caller {
+        Ok(caller) => route(caller.store.clone(), req).await,
+        Err(err) => Err(err),
+    };
+    let response = match response {
+        Ok(response) => response,
+        Err(err) => err.response(),
+    };
+
+    info!(
+        "{} {} {} {} {}ms",
+        name,
+        method,
+        path,
+        response.status().as_u16(),
+        start.elapsed().as_millis()
+    );
+    Ok(response)
+}
+
+fn authorize<A: AccessControl, K: KeyServer, N: Node>(
+    state: &State<A, K, N>,
+    req: &Request<Body>,
+) -> Result<Arc<Caller<A, K, N>>, ApiError> {
+    let unauthorized = || {
+        ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "Missing or unknown API token",
+        )
+    };
+    let token = req
+        .headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(unauthorized)?;
+    state
+        .callers
+        .get(&token_hash(token.trim()))
+        .cloned()
+        .ok_or_else(unauthorized)
+}
+
+async fn route<A, K, N>(
+    store: Arc<CryptoSecretStore<A, K, N>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, ApiError>
+where
+    A: AccessControl + 'static,
+    K: KeyServer + 'static,
+    N: Node + 'static,
+    CryptoSecretStore<A, K, N>: Send + Sync,
+{
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+
+    let result = match (method, path.as_str()) {
+        (Method::GET, "/v1/identity") => {
+            json!({ "address": format!("{:x}", store.address()) })
+        }
+        (Method::POST, "/v1/id") => {
+            let request: IdRequest = body(req).await?;
+            json!({ "id": generate_id(&request.document) })
+        }
+        (Method::POST, "/v1/encrypt") => {
+            let request: EncryptRequest = body(req).await?;
+            let id = request
+                .id
+                .unwrap_or_else(|| DocumentId::random().to_string());
+            let (id, ciphertext) = blocking(store, move |store| {
+                let ciphertext = store.encrypt(&id, &request.document, request.threshold)?;
+                Ok((id, ciphertext))
+            })
+            .await?;
+            json!({ "id": id, "ciphertext": ciphertext })
+        }
+        (Method::POST, "/v1/decrypt") => {
+            let request: DecryptRequest = body(req).await?;
+            let document = blocking(store, move |store| {
+                store.decrypt(&request.id, &request.ciphertext)
+            })
+            .await?;
+            json!({ "document": document })
+        }
+        (Method::POST, "/v1/grant") => {
+            let request: GrantRequest = body(req).await?;
+            let addresses = parse_addresses(&request.addresses)?;
+            let result = blocking(store, move |store| {
+                if request.valid_from.is_some() || request.valid_until.is_some() {
+                    store.allow_access_until(
+                        &request.id,
+                        &addresses,
+                        request.valid_from,
+                        request.valid_until,
+                    )
+                } else {
+                    store.allow_access(&request.id, &addresses)
+                }
+            })
+            .await?;
+            json!({ "transaction": format!("{:?}", result.hash()) })
+        }
+        (Method::POST, "/v1/revoke") => {
+            let request: RevokeRequest = body(req).await?;
+            let addresses = parse_addresses(&request.addresses)?;
+            let result = blocking(store, move |store| {
+                store.revoke_access(&request.id, &addresses)
+            })
+            .await?;
+            json!({ "transaction": format!("{:?}", result.hash()) })
+        }
+        (Method::POST, "/v1/check") => {
+            let request: CheckRequest = body(req).await?;
+            let address = parse_address(&request.address)
+                .map_err(|err| ApiError::bad_request(err.to_string()))?;
+            let grant = blocking(store, move |store| {
+                store.check_permissions(address, &request.id)
+            })
+            .await?;
+            json!({
+                "allowed": grant.is_allowed(),
+                "valid_from": grant.valid_from,
+                "valid_until": grant.valid_until,
+            })
+        }
+        _ => {
+            return Err(ApiError::new(
+                StatusCode::NOT_FOUND,
+                "not_found",
+                format!("No endpoint {}", path),
+            ))
+        }
+    };
+    Ok(json_response(StatusCode::OK, result))
+}
+
+async fn body<T: DeserializeOwned>(req: Request<Body>) -> Result<T, ApiError> {
+    let bytes = hyper::body::to_bytes(req.into_body())
+        .await
+        .map_err(|err| ApiError::bad_request(err.to_string()))?;
+    serde_json::from_slice(&bytes).map_err(|err| ApiError::bad_request(err.to_string()))
+}
+
+/* The store blocks while it waits for the node, so requests run on the blocking pool.
+ * Callers don't wait for each other, also not those of the same account */
+async fn blocking<A, K, N, T, F>(
+    store: Arc<CryptoSecretStore<A, K, N>>,
+    f: F,
+) -> Result<T, ApiError>
+where
+    A: AccessControl + 'static,
+    K: KeyServer + 'static,
+    N: Node + 'static,
+    CryptoSecretStore<A, K, N>: Send + Sync,
+    F: FnOnce(&CryptoSecretStore<A, K, N>) -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(move || f(&store))
+        .await
+        .map_err(|err| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "other", err.to_string()))?
+        .map_err(ApiError::from)
+}
+
+fn json_response(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acl::LocalAcl;
+    use crate::keyserver::reference::ReferenceKeyServer;
+    use crate::node::local::LocalNode;
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::runtime::Builder;
+
+    type MockState = State<LocalAcl, ReferenceKeyServer, LocalNode>;
+
+    /* Alice has the tokens "alice" and "alice-2", Bob the token "bob" */
+    fn state() -> (MockState, H160, H160) {
+        let node = LocalNode::new();
+        let alice = node.new_account("alicepwd");
+        let bob = node.new_account("bobpwd");
+        let acl = LocalAcl::in_memory();
+        let store = CryptoSecretStore::with_node(
+            &format!("{:x}", alice),
+            StaticPassword::new("alicepwd"),
+            acl.clone(),
+            ReferenceKeyServer::new(3, &acl),
+            node,
+            &Config::default(),
+        )
+        .unwrap();
+        let other = store
+            .as_identity(&format!("{:x}", bob), StaticPassword::new("bobpwd"))
+            .unwrap();
+        let store = Arc::new(store);
+
+        let mut state = State::new();
+        state.add("alice", "alice", store.clone()).unwrap();
+        state.add("alice 2", "alice-2", store.clone()).unwrap();
+        state.add("bob", "bob", Arc::new(other)).unwrap();
+        assert!(state.add("again", "alice", store).is_err());
+        (state, alice, bob)
+    }
+
+    /* Status and JSON body of a request through the whole API */
+    fn request(
+        state: &Arc<MockState>,
+        token: Option<&str>,
+        method: Method,
+        path: &str,
+        body: Value,
+    ) -> (StatusCode, Value) {
+        let mut req = Request::builder().method(method).uri(path);
+        if let Some(token) = token {
+            req = req.header(hyper::header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let req = req.body(Body::from(body.to_string())).unwrap();
+        let mut rt = Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let response = handle(state.clone(), req).await.unwrap();
+            let status = response.status();
+            let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            (status, serde_json::from_slice(&bytes).unwrap())
+        })
+    }
+
+    #[test]
+    fn token_auth() {
+        let (state, alice, bob) = state();
+        let state = Arc::new(state);
+        let identity = |token| request(&state, token, Method::GET, "/v1/identity", json!({}));
+
+        let (status, body) = identity(None);
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"]["class"], "unauthorized");
+        assert_eq!(identity(Some("carol")).0, StatusCode::UNAUTHORIZED);
+        assert_eq!(identity(Some("alic")).0, StatusCode::UNAUTHORIZED);
+
+        for (token, address) in &[("alice", alice), ("alice-2", alice), ("bob", bob)] {
+            let (status, body) = identity(Some(token));
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["address"], format!("{:x}", address));
+        }
+    }
+
+    #[test]
+    fn error_statuses() {
+        let (state, alice, bob) = state();
+        let state = Arc::new(state);
+        let post = |token, path, body| request(&state, Some(token), Method::POST, path, body);
+
+        let (status, body) = post("alice", "/v1/encrypt", json!({"document": "Hello world"}));
+        assert_eq!(status, StatusCode::OK);
+        let id = body["id"].as_str().unwrap().to_string();
+        let ciphertext = body["ciphertext"].as_str().unwrap().to_string();
+
+        let (status, body) = post(
+            "alice",
+            "/v1/encrypt",
+            json!({"id": id, "document": "Other document"}),
+        );
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["error"]["class"], "key_exists");
+
+        let decrypt = json!({"id": id, "ciphertext": ciphertext});
+        let (status, body) = post("bob", "/v1/decrypt", decrypt.clone());
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"]["class"], "access_denied");
+
+        let grant = json!({"id": id, "addresses": [format!("{:x}", alice), format!("{:x}", bob)]});
+        assert_eq!(post("alice", "/v1/grant", grant).0, StatusCode::OK);
+        let (status, body) = post("bob", "/v1/decrypt", decrypt);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["document"], "Hello world");
+
+        let (status, body) = post(
+            "alice",
+            "/v1/grant",
+            json!({"id": id, "addresses": ["0x12"]}),
+        );
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["class"], "invalid_request");
+        assert_eq!(
+            post("alice", "/v1/decrypt", json!({"id": id})).0,
+            StatusCode::BAD_REQUEST
+        );
+        let (status, body) = post("alice", "/v1/missing", json!({}));
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["class"], "not_found");
+    }
+
+    #[test]
+    fn loopback_only() {
+        assert!(check_listen(&"127.0.0.1:8600".parse().unwrap()).is_ok());
+        assert!(check_listen(&"[::1]:8600".parse().unwrap()).is_ok());
+        assert!(check_listen(&"0.0.0.0:8600".parse().unwrap()).is_err());
+        assert!(check_listen(&"192.168.1.2:8600".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn socket_replacement() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rt = Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            /* A socket left by a previous run is replaced, only the user can connect */
+            let path = dir.path().join("daemon.sock");
+            drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+            drop(bind_socket(&path).unwrap());
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+
+            /* Anything else is kept */
+            let path = dir.path().join("notes.txt");
+            fs::write(&path, "keep me").unwrap();
+            assert!(bind_socket(&path).is_err());
+            assert_eq!(fs::read_to_string(&path).unwrap(), "keep me");
+        });
+    }
+}
@@ -0,0 +1,85 @@
+/* Coarse classes of the errors returned by the store, for bindings which can't match on the
+ * Rust error types */
+use crate::api::shadow::{Error as ShadowError, ErrorKind};
+use crate::api::transport;
+use crate::threshold::ThresholdError;
+use failure::Error;
+
+/// What went wrong, independent of the backend which failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    AccessDenied,
+    KeyNotFound,
+    /// A key was already generated or stored for the document ID
+    KeyExists,
+    InvalidThreshold,
+    /// The Secret Store or the node can't be reached right now, retrying can help
+    Unavailable,
+    Other,
+}
+
+impl ErrorClass {
+    pub fn of(error: &Error) -> ErrorClass {
+        if let Some(error) = error.downcast_ref::<ShadowError>() {
+            return match error.kind() {
+                Some(ErrorKind::AccessDenied) | Some(ErrorKind::BadSignature) => {
+                    ErrorClass::AccessDenied
+                }
+                Some(ErrorKind::KeyNotFound) | Some(ErrorKind::DocumentKeyNotFound) => {
+                    ErrorClass::KeyNotFound
+                }
+                Some(ErrorKind::KeyAlreadyGenerated) | Some(ErrorKind::KeyAlreadyStored) => {
+                    ErrorClass::KeyExists
+                }
+                Some(ErrorKind::InvalidThreshold) | Some(ErrorKind::NotEnoughNodesForThreshold) => {
+                    ErrorClass::InvalidThreshold
+                }
+                Some(kind) if kind.is_temporary() => ErrorClass::Unavailable,
+                Some(_) => ErrorClass::Other,
+                /* The request didn't get an answer */
+                None => ErrorClass::Unavailable,
+            };
+        }
+
+        if error.downcast_ref::<ThresholdError>().is_some() {
+            ErrorClass::InvalidThreshold
+        } else if error.downcast_ref::<transport::Error>().is_some()
+            || error.downcast_ref::<reqwest::Error>().is_some()
+        {
+            ErrorClass::Unavailable
+        } else {
+            ErrorClass::Other
+        }
+    }
+
+    /* Kind of a Secret Store error in snake case, as in its serialized form */
+    pub fn kind_name(error: &Error) -> Option<String> {
+        let kind = error.downcast_ref::<ShadowError>()?.kind()?;
+        serde_json::to_value(kind)
+            .ok()
+            .and_then(|kind| kind.as_str().map(String::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use failure::format_err;
+
+    #[test]
+    fn classes() {
+        let error = ShadowError::new(ErrorKind::AccessDenied).into();
+        assert_eq!(ErrorClass::of(&error), ErrorClass::AccessDenied);
+        assert_eq!(
+            ErrorClass::kind_name(&error).as_deref(),
+            Some("access_denied")
+        );
+        let error = ShadowError::new(ErrorKind::ConsensusTemporaryUnreachable).into();
+        assert_eq!(ErrorClass::of(&error), ErrorClass::Unavailable);
+        let error = ThresholdError::UnknownClusterSize.into();
+        assert_eq!(ErrorClass::of(&error), ErrorClass::InvalidThreshold);
+        let error = format_err!("Sender not authorized.");
+        assert_eq!(ErrorClass::of(&error), ErrorClass::Other);
+        assert_eq!(ErrorClass::kind_name(&error), None);
+    }
+}
@@ -0,0 +1,155 @@
+use crate::permit::keccak256;
+use primitive_types::{H160, H256, U256};
+use web3::types::Log;
+
+/// Access of a user to a document as seen by the ACL contract.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Grant {
+    /// Whether the Secret Store gives the user access right now
+    pub allowed: bool,
+    /// Block timestamp from which the grant is valid
+    pub valid_from: Option<u64>,
+    /// Block timestamp at which the grant runs out
+    pub valid_until: Option<u64>,
+}
+
+impl Grant {
+    pub fn is_allowed(&self) -> bool {
+        self.allowed
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.valid_until.map_or(false, |until| until <= now)
+    }
+}
+
+/// Changes of the grants of a document, emitted by the ACL contract.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessEvent {
+    Granted {
+        document_id: H256,
+        user: H160,
+        valid_from: Option<u64>,
+        valid_until: Option<u64>,
+    },
+    Revoked {
+        document_id: H256,
+        user: H160,
+    },
+    /* Clients should drop cached keys of the document for this user */
+    Expired {
+        document_id: H256,
+        user: H160,
+        valid_until: u64,
+    },
+}
+
+pub fn granted_topic() -> H256 {
+    H256::from(keccak256(b"AccessGranted(bytes32,address,uint256,uint256)"))
+}
+
+pub fn revoked_topic() -> H256 {
+    H256::from(keccak256(b"AccessRevoked(bytes32,address)"))
+}
+
+pub fn expired_topic() -> H256 {
+    H256::from(keccak256(b"AccessExpired(bytes32,address,uint256)"))
+}
+
+/* 0 is used by the contract for "no limit" */
+pub fn timestamp(value: U256) -> Option<u64> {
+    if value.is_zero() {
+        None
+    } else {
+        Some(value.low_u64())
+    }
+}
+
+impl AccessEvent {
+    /* Decode a log of the ACL contract, None for logs of other events */
+    pub fn from_log(log: &Log) -> Option<AccessEvent> {
+        if log.topics.len() != 3 {
+            return None;
+        }
+        let document_id = log.topics[1];
+        let user = H160::from_slice(&log.topics[2][12..]);
+        let word = |i: usize| {
+            log.data
+                .0
+                .get(i * 32..(i + 1) * 32)
+                .map(U256::from_big_endian)
+        };
+
+        let topic = log.topics[0];
+        if topic == granted_topic() {
+            Some(AccessEvent::Granted {
+                document_id,
+                user,
+                valid_from: timestamp(word(0)?),
+                valid_until: timestamp(word(1)?),
+            })
+        } else if topic == revoked_topic() {
+            Some(AccessEvent::Revoked { document_id, user })
+        } else if topic == expired_topic() {
+            Some(AccessEvent::Expired {
+                document_id,
+                user,
+                valid_until: word(0)?.low_u64(),
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn document_id(&self) -> H256 {
+        match self {
+            AccessEvent::Granted { document_id, .. }
+            | AccessEvent::Revoked { document_id, .. }
+            | AccessEvent::Expired { document_id, .. } => *document_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use web3::types::Bytes;
+
+    fn log(topic: H256, data: Vec<u8>) -> Log {
+        let mut user = [0u8; 32];
+        user[12..].copy_from_slice(&[0xab; 20]);
+        Log {
+            address: H160::zero(),
+            topics: vec![topic, H256::repeat_byte(1), H256::from(user)],
+            data: Bytes(data),
+            block_hash: None,
+            block_number: None,
+            transaction_hash: None,
+            transaction_index: None,
+            log_index: None,
+            transaction_log_index: None,
+            log_type: None,
+            removed: None,
+        }
+    }
+
+    #[test]
+    fn decode_events() {
+        let mut data = [0u8; 64];
+        data[63] = 42;
+        let event = AccessEvent::from_log(&log(granted_topic(), data.to_vec())).unwrap();
+        assert_eq!(
+            event,
+            AccessEvent::Granted {
+                document_id: H256::repeat_byte(1),
+                user: H160::repeat_byte(0xab),
+                valid_from: None,
+                valid_until: Some(42),
+            }
+        );
+
+        let event = AccessEvent::from_log(&log(revoked_topic(), vec![])).unwrap();
+        assert_eq!(event.document_id(), H256::repeat_byte(1));
+        assert!(AccessEvent::from_log(&log(H256::zero(), vec![])).is_none());
+    }
+}
@@ -0,0 +1,246 @@
+use crate::secret::Secret;
+use failure::Error;
+use hmac::{Hmac, Mac};
+use primitive_types::H256;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Random part of a derived document ID.
+pub type Nonce = [u8; 32];
+
+/// ID of a document in the Secret Store and in the ACL contract.
+///
+/// The Secret Store and the contract only ever see the hashed form, a raw ID is hashed with
+/// SHA-256 first. The hashed form is written with a `0x` prefix, so both forms survive a
+/// round trip through a string.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DocumentId {
+    Raw(String),
+    Hashed(H256),
+}
+
+/// Secret shared by the members of a room, used to derive document IDs which don't reveal
+/// anything about the document.
+#[derive(Clone)]
+pub struct RoomSecret(Secret<[u8; 32]>);
+
+impl RoomSecret {
+    pub fn generate() -> Self {
+        RoomSecret(Secret::new(rand::random()))
+    }
+
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        RoomSecret(Secret::new(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        self.0.expose()
+    }
+}
+
+impl PartialEq for RoomSecret {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl fmt::Debug for RoomSecret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RoomSecret(..)")
+    }
+}
+
+impl DocumentId {
+    /* HMAC over room ID, transaction ID and a random nonce, so two identical messages never
+     * share an ID and the ID can't be linked to the content. Everything is known before the
+     * event is sent, the nonce has to be sent along so receivers can check the ID */
+    pub fn derive(secret: &RoomSecret, room_id: &str, transaction_id: &str) -> (DocumentId, Nonce) {
+        let nonce = rand::random();
+        (
+            DocumentId::derive_with_nonce(secret, room_id, transaction_id, &nonce),
+            nonce,
+        )
+    }
+
+    /* The ID derive returned together with nonce */
+    pub fn derive_with_nonce(
+        secret: &RoomSecret,
+        room_id: &str,
+        transaction_id: &str,
+        nonce: &Nonce,
+    ) -> DocumentId {
+        let mut mac = HmacSha256::new_varkey(secret.as_bytes()).expect("HMAC takes any key size");
+        mac.input(room_id.as_bytes());
+        mac.input(&[0]);
+        mac.input(transaction_id.as_bytes());
+        mac.input(&[0]);
+        mac.input(nonce);
+        DocumentId::Hashed(H256::from_slice(&mac.result().code()))
+    }
+
+    /* A fresh ID which isn't related to anything */
+    pub fn random() -> DocumentId {
+        DocumentId::Hashed(H256::random())
+    }
+
+    pub fn is_hashed(&self) -> bool {
+        match self {
+            DocumentId::Raw(_) => false,
+            DocumentId::Hashed(_) => true,
+        }
+    }
+
+    /* Hashing is only done once, an already hashed ID is returned as is */
+    pub fn hashed(&self) -> H256 {
+        match self {
+            DocumentId::Raw(id) => H256::from_slice(&Sha256::digest(id.as_bytes())),
+            DocumentId::Hashed(hash) => *hash,
+        }
+    }
+
+    /* Hex encoding of the hashed ID as it's used by the Secret Store */
+    pub fn key_id(&self) -> String {
+        format!("{:x}", self.hashed())
+    }
+}
+
+impl fmt::Display for DocumentId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DocumentId::Raw(id) => write!(f, "{}", id),
+            DocumentId::Hashed(hash) => write!(f, "0x{:x}", hash),
+        }
+    }
+}
+
+impl FromStr for DocumentId {
+    type Err = Error;
+
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        if id.starts_with("0x") {
+            Ok(DocumentId::Hashed(H256::from_str(&id[2..])?))
+        } else {
+            Ok(DocumentId::Raw(id.into()))
+        }
+    }
+}
+
+/// Conversion of the ways a document ID can be passed to the store.
+///
+/// Strings are parsed like `DocumentId::from_str`, a string with a `0x` prefix which isn't a
+/// valid hash is an error instead of being taken as a raw ID.
+pub trait IntoDocumentId {
+    fn into_document_id(self) -> Result<DocumentId, Error>;
+}
+
+impl IntoDocumentId for DocumentId {
+    fn into_document_id(self) -> Result<DocumentId, Error> {
+        Ok(self)
+    }
+}
+
+impl IntoDocumentId for &DocumentId {
+    fn into_document_id(self) -> Result<DocumentId, Error> {
+        Ok(self.clone())
+    }
+}
+
+impl IntoDocumentId for H256 {
+    fn into_document_id(self) -> Result<DocumentId, Error> {
+        Ok(DocumentId::Hashed(self))
+    }
+}
+
+impl IntoDocumentId for &str {
+    fn into_document_id(self) -> Result<DocumentId, Error> {
+        DocumentId::from_str(self)
+    }
+}
+
+impl IntoDocumentId for &String {
+    fn into_document_id(self) -> Result<DocumentId, Error> {
+        DocumentId::from_str(self)
+    }
+}
+
+impl IntoDocumentId for String {
+    fn into_document_id(self) -> Result<DocumentId, Error> {
+        DocumentId::from_str(&self)
+    }
+}
+
+impl From<H256> for DocumentId {
+    fn from(hash: H256) -> Self {
+        DocumentId::Hashed(hash)
+    }
+}
+
+impl Serialize for DocumentId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for DocumentId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = String::deserialize(deserializer)?;
+        DocumentId::from_str(&id).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_only_once() {
+        let raw = "hello".into_document_id().unwrap();
+        assert!(!raw.is_hashed());
+        let hashed = DocumentId::from(raw.hashed());
+        assert!(hashed.is_hashed());
+        assert_eq!(hashed.hashed(), raw.hashed());
+        assert_eq!(hashed.to_string().into_document_id().unwrap(), hashed);
+    }
+
+    #[test]
+    fn malformed_hash() {
+        assert!("0x1234".into_document_id().is_err());
+        assert!("0xnothex".into_document_id().is_err());
+    }
+
+    #[test]
+    fn derived_ids() {
+        let secret = RoomSecret::generate();
+        let (first, nonce) = DocumentId::derive(&secret, "!room:example.org", "txn1");
+        let (second, _) = DocumentId::derive(&secret, "!room:example.org", "txn1");
+        assert!(first.is_hashed());
+        /* A fresh nonce every time, the nonce gives the same ID back */
+        assert_ne!(first, second);
+        assert_eq!(
+            first,
+            DocumentId::derive_with_nonce(&secret, "!room:example.org", "txn1", &nonce)
+        );
+        assert_ne!(
+            first,
+            DocumentId::derive_with_nonce(&secret, "!room:example.org", "txn2", &nonce)
+        );
+        assert_ne!(
+            first,
+            DocumentId::derive_with_nonce(&secret, "!other:example.org", "txn1", &nonce)
+        );
+        assert_ne!(
+            first,
+            DocumentId::derive_with_nonce(
+                &RoomSecret::generate(),
+                "!room:example.org",
+                "txn1",
+                &nonce
+            )
+        );
+    }
+}
@@ -0,0 +1,112 @@
+/* Backend which generates and stores the keys, the Secret Store HTTP API (Shadow) in
+ * production. The node's JSON-RPC API is still used to sign and to encrypt and decrypt,
+ * since that needs the account's key */
+pub mod reference;
+
+pub use crate::api::shadow::{Error, ErrorKind, Shadow};
+use crate::blockchain::DecryptionKeys;
+use crate::Data;
+use async_trait::async_trait;
+use primitive_types::H512;
+
+#[async_trait(?Send)]
+pub trait KeyServer {
+    /* Returns the public server key */
+    async fn generate_server_key(
+        &self,
+        document_key_id: &str,
+        signed_document_key_id: &Data,
+        threshold: u32,
+    ) -> Result<Data, Error>;
+
+    /* Store the document key encrypted with the server key */
+    async fn store_document_key(
+        &self,
+        document_key_id: &str,
+        signed_document_key_id: &Data,
+        common_point: H512,
+        encrypted_point: H512,
+    ) -> Result<(), Error>;
+
+    async fn get_server_key(
+        &self,
+        document_key_id: &str,
+        signed_document_key_id: &Data,
+    ) -> Result<Data, Error>;
+
+    /* Threshold the server key was generated with, None if the backend doesn't tell */
+    async fn server_key_threshold(
+        &self,
+        _document_key_id: &str,
+        _signed_document_key_id: &Data,
+    ) -> Result<Option<u32>, Error> {
+        Ok(None)
+    }
+
+    /* Document key encrypted with the public key of the requester */
+    async fn get_encrypted_document_key(
+        &self,
+        document_key_id: &str,
+        signed_document_key_id: &Data,
+    ) -> Result<Data, Error>;
+
+    /* Shadow retrieval, the document key is only put together by the requester */
+    async fn get_document_key(
+        &self,
+        document_key_id: &str,
+        signed_document_key_id: &Data,
+    ) -> Result<DecryptionKeys, Error>;
+}
+
+#[async_trait(?Send)]
+impl KeyServer for Shadow {
+    async fn generate_server_key(
+        &self,
+        document_key_id: &str,
+        signed_document_key_id: &Data,
+        threshold: u32,
+    ) -> Result<Data, Error> {
+        Shadow::generate_server_key(self, document_key_id, signed_document_key_id, threshold).await
+    }
+
+    async fn store_document_key(
+        &self,
+        document_key_id: &str,
+        signed_document_key_id: &Data,
+        common_point: H512,
+        encrypted_point: H512,
+    ) -> Result<(), Error> {
+        Shadow::store_document_key(
+            self,
+            document_key_id,
+            signed_document_key_id,
+            common_point,
+            encrypted_point,
+        )
+        .await
+    }
+
+    async fn get_server_key(
+        &self,
+        document_key_id: &str,
+        signed_document_key_id: &Data,
+    ) -> Result<Data, Error> {
+        Shadow::get_server_key(self, document_key_id, signed_document_key_id).await
+    }
+
+    async fn get_encrypted_document_key(
+        &self,
+        document_key_id: &str,
+        signed_document_key_id: &Data,
+    ) -> Result<Data, Error> {
+        Shadow::get_encrypted_document_key(self, document_key_id, signed_document_key_id).await
+    }
+
+    async fn get_document_key(
+        &self,
+        document_key_id: &str,
+        signed_document_key_id: &Data,
+    ) -> Result<DecryptionKeys, Error> {
+        Shadow::get_document_key(self, document_key_id, signed_document_key_id).await
+    }
+}
@@ -0,0 +1,569 @@
+/* In-process key server for tests, it does the same key math as a Secret Store cluster of
+ * `nodes` nodes but all shares are kept in one process. Never use it for real data.
+ *
+ * The server key is split with a random polynomial of degree threshold, node i holds f(i).
+ * Shadow retrieval combines t + 1 shares with Lagrange coefficients, blinded with random
+ * decrypt shadows which are given to the requester encrypted with its public key, like
+ * parity's document key shadow retrieval session does. */
+use super::{Error, ErrorKind, KeyServer};
+use crate::acl::LocalAcl;
+use crate::blockchain::DecryptionKeys;
+use crate::permit::keccak256;
+use crate::Data;
+use aes_ctr::stream_cipher::generic_array::GenericArray;
+use aes_ctr::stream_cipher::{NewStreamCipher, SyncStreamCipher};
+use aes_ctr::Aes128Ctr;
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use num_bigint::BigUint;
+use primitive_types::{H160, H256, H512};
+use secp256k1::{Message, PublicKey, RecoveryId, SecretKey, Signature};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/* Order of the secp256k1 group */
+const ORDER: &[u8] = b"FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141";
+
+struct ServerKey {
+    author: H160,
+    threshold: u32,
+    public: PublicKey,
+    /* Share of node i + 1 */
+    shares: Vec<BigUint>,
+    /* Common and encrypted point of the stored document key */
+    document_key: Option<(PublicKey, PublicKey)>,
+}
+
+pub struct ReferenceKeyServer {
+    nodes: usize,
+    keys: Mutex<HashMap<H256, ServerKey>>,
+    /* Takes the place of the ACL contract, like the contract it doesn't know the author of a
+     * key, so the author needs a grant as well */
+    permissions: Box<dyn Fn(H160, H256) -> bool + Send + Sync>,
+}
+
+impl ReferenceKeyServer {
+    /* Asks acl like the nodes ask the ACL contract, grants made later on acl or its clones
+     * count as well */
+    pub fn new(nodes: usize, acl: &LocalAcl) -> ReferenceKeyServer {
+        let acl = acl.clone();
+        ReferenceKeyServer::with_permissions(nodes, move |user, id| acl.allows(user, id))
+    }
+
+    pub fn with_permissions<F>(nodes: usize, permissions: F) -> ReferenceKeyServer
+    where
+        F: Fn(H160, H256) -> bool + Send + Sync + 'static,
+    {
+        ReferenceKeyServer {
+            nodes,
+            keys: Mutex::new(HashMap::new()),
+            permissions: Box::new(permissions),
+        }
+    }
+
+    pub fn nodes(&self) -> usize {
+        self.nodes
+    }
+
+    fn check_access(&self, key: &ServerKey, requester: H160, id: H256) -> Result<(), Error> {
+        if (self.permissions)(requester, id) {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::AccessDenied))
+        }
+    }
+
+    /* Sum of λ_i * s_i * C of the first t + 1 nodes, i.e. s * C */
+    fn joint_shares(key: &ServerKey, common_point: &PublicKey) -> Result<Vec<PublicKey>, Error> {
+        let indices = node_indices(key.threshold);
+        indices
+            .iter()
+            .map(|i| {
+                let share = key.shares[*i as usize - 1].clone();
+                mul_point(common_point, &(lagrange(*i, &indices) * share % order()))
+            })
+            .collect()
+    }
+}
+
+#[async_trait(?Send)]
+impl KeyServer for ReferenceKeyServer {
+    async fn generate_server_key(
+        &self,
+        document_key_id: &str,
+        signed_document_key_id: &Data,
+        threshold: u32,
+    ) -> Result<Data, Error> {
+        let id = parse_id(document_key_id)?;
+        let (_, author) = requester(id, signed_document_key_id)?;
+        if threshold as usize >= self.nodes {
+            return Err(Error::new(ErrorKind::NotEnoughNodesForThreshold));
+        }
+
+        let mut keys = self.keys.lock().unwrap();
+        if keys.contains_key(&id) {
+            return Err(Error::new(ErrorKind::KeyAlreadyGenerated));
+        }
+
+        let polynomial: Vec<BigUint> = (0..=threshold).map(|_| random_scalar()).collect();
+        let shares = (1..=self.nodes as u32)
+            .map(|i| evaluate(&polynomial, i))
+            .collect();
+        let public = mul_point(&generator(), &polynomial[0])?;
+        let server_key = format!("0x{:x}", point_to_h512(&public));
+        keys.insert(
+            id,
+            ServerKey {
+                author,
+                threshold,
+                public,
+                shares,
+                document_key: None,
+            },
+        );
+        Ok(server_key)
+    }
+
+    async fn store_document_key(
+        &self,
+        document_key_id: &str,
+        signed_document_key_id: &Data,
+        common_point: H512,
+        encrypted_point: H512,
+    ) -> Result<(), Error> {
+        let id = parse_id(document_key_id)?;
+        let (_, requester) = requester(id, signed_document_key_id)?;
+        let mut keys = self.keys.lock().unwrap();
+        let key = keys
+            .get_mut(&id)
+            .ok_or_else(|| Error::new(ErrorKind::KeyNotFound))?;
+        if key.author != requester {
+            return Err(Error::new(ErrorKind::AccessDenied));
+        }
+        if key.document_key.is_some() {
+            return Err(Error::new(ErrorKind::KeyAlreadyStored));
+        }
+        key.document_key = Some((
+            h512_to_point(&common_point)?,
+            h512_to_point(&encrypted_point)?,
+        ));
+        Ok(())
+    }
+
+    async fn get_server_key(
+        &self,
+        document_key_id: &str,
+        signed_document_key_id: &Data,
+    ) -> Result<Data, Error> {
+        let id = parse_id(document_key_id)?;
+        let (_, requester) = requester(id, signed_document_key_id)?;
+        let keys = self.keys.lock().unwrap();
+        let key = keys
+            .get(&id)
+            .ok_or_else(|| Error::new(ErrorKind::KeyNotFound))?;
+        self.check_access(key, requester, id)?;
+        Ok(format!("0x{:x}", point_to_h512(&key.public)))
+    }
+
+    async fn server_key_threshold(
+        &self,
+        document_key_id: &str,
+        signed_document_key_id: &Data,
+    ) -> Result<Option<u32>, Error> {
+        let id = parse_id(document_key_id)?;
+        let (_, requester) = requester(id, signed_document_key_id)?;
+        let keys = self.keys.lock().unwrap();
+        let key = keys
+            .get(&id)
+            .ok_or_else(|| Error::new(ErrorKind::KeyNotFound))?;
+        self.check_access(key, requester, id)?;
+        Ok(Some(key.threshold))
+    }
+
+    async fn get_encrypted_document_key(
+        &self,
+        document_key_id: &str,
+        signed_document_key_id: &Data,
+    ) -> Result<Data, Error> {
+        let id = parse_id(document_key_id)?;
+        let (public, requester) = requester(id, signed_document_key_id)?;
+        let keys = self.keys.lock().unwrap();
+        let key = keys
+            .get(&id)
+            .ok_or_else(|| Error::new(ErrorKind::DocumentKeyNotFound))?;
+        let (common_point, encrypted_point) = key
+            .document_key
+            .clone()
+            .ok_or_else(|| Error::new(ErrorKind::DocumentKeyNotFound))?;
+        self.check_access(key, requester, id)?;
+
+        /* M = E - s * C */
+        let mut points = vec![encrypted_point];
+        for point in ReferenceKeyServer::joint_shares(key, &common_point)? {
+            points.push(negate(&point)?);
+        }
+        let document_key = combine(&points)?;
+        Ok(format!(
+            "0x{}",
+            hex::encode(ecies_encrypt(
+                &public,
+                point_to_h512(&document_key).as_bytes()
+            )?)
+        ))
+    }
+
+    async fn get_document_key(
+        &self,
+        document_key_id: &str,
+        signed_document_key_id: &Data,
+    ) -> Result<DecryptionKeys, Error> {
+        let id = parse_id(document_key_id)?;
+        let (public, requester) = requester(id, signed_document_key_id)?;
+        let keys = self.keys.lock().unwrap();
+        let key = keys
+            .get(&id)
+            .ok_or_else(|| Error::new(ErrorKind::DocumentKeyNotFound))?;
+        let (common_point, encrypted_point) = key
+            .document_key
+            .clone()
+            .ok_or_else(|| Error::new(ErrorKind::DocumentKeyNotFound))?;
+        self.check_access(key, requester, id)?;
+
+        /* Every node adds d_i * a to its part of the joint secret, so the result is only
+         * usable together with the decrypt shadows d_i */
+        let access_key = random_scalar();
+        let indices = node_indices(key.threshold);
+        let mut points = vec![encrypted_point];
+        let mut decrypt_shadows = Vec::new();
+        for i in &indices {
+            let shadow = random_scalar();
+            let share = key.shares[*i as usize - 1].clone();
+            let shadow_key = (lagrange(*i, &indices) * share + &shadow * &access_key) % order();
+            points.push(mul_point(&common_point, &neg_scalar(&shadow_key))?);
+            decrypt_shadows.push(format!(
+                "0x{}",
+                hex::encode(ecies_encrypt(&public, &scalar_bytes(&shadow))?)
+            ));
+        }
+
+        Ok(DecryptionKeys {
+            common_point: point_to_h512(&mul_point(&common_point, &access_key)?),
+            decrypted_secret: point_to_h512(&combine(&points)?),
+            decrypt_shadows,
+        })
+    }
+}
+
+/* What the node does in secretstore_generateDocumentKey: returns common point, encrypted
+ * point and the document key itself */
+pub fn generate_document_key(server_key: H512) -> Result<(H512, H512, H512), Error> {
+    let server_key = h512_to_point(&server_key)?;
+    let document_key = mul_point(&generator(), &random_scalar())?;
+    let k = random_scalar();
+    let common_point = mul_point(&generator(), &k)?;
+    let encrypted_point = combine(&[document_key.clone(), mul_point(&server_key, &k)?])?;
+    Ok((
+        point_to_h512(&common_point),
+        point_to_h512(&encrypted_point),
+        point_to_h512(&document_key),
+    ))
+}
+
+/* Key part of secretstore_shadowDecrypt: M = decrypted_secret + (Σ d_i) * common_point */
+pub fn shadow_decrypt(secret: &SecretKey, keys: &DecryptionKeys) -> Result<H512, Error> {
+    let mut sum = BigUint::from(0u32);
+    for shadow in &keys.decrypt_shadows {
+        let data = hex::decode(shadow.trim_start_matches("0x"))
+            .map_err(|_| Error::with_message(ErrorKind::Internal, "Invalid decrypt shadow"))?;
+        sum += BigUint::from_bytes_be(&ecies_decrypt(secret, &data)?);
+    }
+    let common_point = h512_to_point(&keys.common_point)?;
+    let decrypted_secret = h512_to_point(&keys.decrypted_secret)?;
+    let document_key = combine(&[
+        decrypted_secret,
+        mul_point(&common_point, &(sum % order()))?,
+    ])?;
+    Ok(point_to_h512(&document_key))
+}
+
+/* Parity's ECIES: 0x04 || R || iv || AES-128-CTR(message) || HMAC-SHA256(iv || ciphertext) */
+pub fn ecies_encrypt(public: &PublicKey, message: &[u8]) -> Result<Vec<u8>, Error> {
+    let ephemeral = random_scalar();
+    let r = mul_point(&generator(), &ephemeral)?;
+    let (ekey, mkey) = ecies_keys(&mul_point(public, &ephemeral)?);
+    let iv: [u8; 16] = rand::random();
+
+    let mut ciphertext = message.to_vec();
+    Aes128Ctr::new(
+        GenericArray::from_slice(&ekey),
+        GenericArray::from_slice(&iv),
+    )
+    .apply_keystream(&mut ciphertext);
+
+    let mut mac = HmacSha256::new_varkey(&mkey).expect("HMAC takes any key size");
+    mac.input(&iv);
+    mac.input(&ciphertext);
+
+    let mut out = r.serialize().to_vec();
+    out.extend_from_slice(&iv);
+    out.extend_from_slice(&ciphertext);
+    out.extend_from_slice(&mac.result().code());
+    Ok(out)
+}
+
+pub fn ecies_decrypt(secret: &SecretKey, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let invalid = || Error::with_message(ErrorKind::Internal, "Invalid ECIES message");
+    if data.len() < 65 + 16 + 32 || data[0] != 0x04 {
+        return Err(invalid());
+    }
+    let mut r = [0u8; 65];
+    r.copy_from_slice(&data[..65]);
+    let mut shared = PublicKey::parse(&r).map_err(|_| invalid())?;
+    shared.tweak_mul_assign(secret).map_err(|_| invalid())?;
+    let (ekey, mkey) = ecies_keys(&shared);
+
+    let (iv, rest) = data[65..].split_at(16);
+    let (ciphertext, tag) = rest.split_at(rest.len() - 32);
+    let mut mac = HmacSha256::new_varkey(&mkey).expect("HMAC takes any key size");
+    mac.input(iv);
+    mac.input(ciphertext);
+    mac.verify(tag).map_err(|_| invalid())?;
+
+    let mut message = ciphertext.to_vec();
+    Aes128Ctr::new(
+        GenericArray::from_slice(&ekey),
+        GenericArray::from_slice(iv),
+    )
+    .apply_keystream(&mut message);
+    Ok(message)
+}
+
+/* Concat KDF over the x coordinate of the shared point, the first half is the AES key and
+ * the hash of the second half the MAC key */
+fn ecies_keys(shared: &PublicKey) -> ([u8; 16], [u8; 32]) {
+    let mut hasher = Sha256::new();
+    hasher.input(&1u32.to_be_bytes());
+    hasher.input(&shared.serialize()[1..33]);
+    let key = hasher.result();
+
+    let mut ekey = [0u8; 16];
+    ekey.copy_from_slice(&key[..16]);
+    let mut mkey = [0u8; 32];
+    mkey.copy_from_slice(&Sha256::digest(&key[16..32]));
+    (ekey, mkey)
+}
+
+fn parse_id(document_key_id: &str) -> Result<H256, Error> {
+    H256::from_str(document_key_id.trim_start_matches("0x"))
+        .map_err(|_| Error::with_message(ErrorKind::InvalidRequest, "Invalid document key ID"))
+}
+
+/* Public key and address of whoever signed the document key ID */
+fn requester(id: H256, signature: &Data) -> Result<(PublicKey, H160), Error> {
+    let invalid = || Error::new(ErrorKind::BadSignature);
+    let bytes = hex::decode(signature.trim_start_matches("0x")).map_err(|_| invalid())?;
+    if bytes.len() != 65 {
+        return Err(invalid());
+    }
+    let mut rs = [0u8; 64];
+    rs.copy_from_slice(&bytes[..64]);
+    let v = if bytes[64] >= 27 {
+        bytes[64] - 27
+    } else {
+        bytes[64]
+    };
+
+    let public = secp256k1::recover(
+        &Message::parse(id.as_fixed_bytes()),
+        &Signature::parse(&rs),
+        &RecoveryId::parse(v).map_err(|_| invalid())?,
+    )
+    .map_err(|_| invalid())?;
+    let address = H160::from_slice(&keccak256(&public.serialize()[1..])[12..]);
+    Ok((public, address))
+}
+
+fn order() -> BigUint {
+    BigUint::parse_bytes(ORDER, 16).expect("valid constant")
+}
+
+fn random_scalar() -> BigUint {
+    loop {
+        let secret: [u8; 32] = rand::random();
+        if SecretKey::parse(&secret).is_ok() {
+            return BigUint::from_bytes_be(&secret);
+        }
+    }
+}
+
+fn scalar_bytes(scalar: &BigUint) -> [u8; 32] {
+    let bytes = scalar.to_bytes_be();
+    let mut out = [0u8; 32];
+    out[32 - bytes.len()..].copy_from_slice(&bytes);
+    out
+}
+
+fn neg_scalar(scalar: &BigUint) -> BigUint {
+    (order() - scalar % order()) % order()
+}
+
+/* a^-1 = a^(n - 2) mod n, n is prime */
+fn inverse(scalar: &BigUint) -> BigUint {
+    scalar.modpow(&(order() - 2u32), &order())
+}
+
+fn evaluate(polynomial: &[BigUint], x: u32) -> BigUint {
+    polynomial
+        .iter()
+        .rev()
+        .fold(BigUint::from(0u32), |acc, coefficient| {
+            (acc * x + coefficient) % order()
+        })
+}
+
+/* Nodes which take part in a decryption session */
+fn node_indices(threshold: u32) -> Vec<u32> {
+    (1..=threshold + 1).collect()
+}
+
+/* λ_i = Π j / (j - i) for all other nodes j */
+fn lagrange(i: u32, indices: &[u32]) -> BigUint {
+    indices
+        .iter()
+        .filter(|j| **j != i)
+        .fold(BigUint::from(1u32), |acc, j| {
+            let denominator = (BigUint::from(*j) + order() - i) % order();
+            acc * *j * inverse(&denominator) % order()
+        })
+}
+
+fn generator() -> PublicKey {
+    let mut one = [0u8; 32];
+    one[31] = 1;
+    PublicKey::from_secret_key(&SecretKey::parse(&one).expect("1 is a valid secret"))
+}
+
+fn mul_point(point: &PublicKey, scalar: &BigUint) -> Result<PublicKey, Error> {
+    let scalar = SecretKey::parse(&scalar_bytes(scalar))
+        .map_err(|_| Error::with_message(ErrorKind::Internal, "Invalid scalar"))?;
+    let mut point = point.clone();
+    point
+        .tweak_mul_assign(&scalar)
+        .map_err(|_| Error::with_message(ErrorKind::Internal, "Invalid point"))?;
+    Ok(point)
+}
+
+fn negate(point: &PublicKey) -> Result<PublicKey, Error> {
+    mul_point(point, &(order() - 1u32))
+}
+
+fn combine(points: &[PublicKey]) -> Result<PublicKey, Error> {
+    PublicKey::combine(points)
+        .map_err(|_| Error::with_message(ErrorKind::Internal, "Invalid point"))
+}
+
+fn point_to_h512(point: &PublicKey) -> H512 {
+    H512::from_slice(&point.serialize()[1..])
+}
+
+fn h512_to_point(point: &H512) -> Result<PublicKey, Error> {
+    let mut bytes = [4u8; 65];
+    bytes[1..].copy_from_slice(point.as_bytes());
+    PublicKey::parse(&bytes)
+        .map_err(|_| Error::with_message(ErrorKind::InvalidRequest, "Invalid point"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acl::AccessControl;
+
+    fn account() -> (SecretKey, PublicKey) {
+        let secret = SecretKey::parse(&scalar_bytes(&random_scalar())).unwrap();
+        let public = PublicKey::from_secret_key(&secret);
+        (secret, public)
+    }
+
+    fn sign(secret: &SecretKey, id: H256) -> Data {
+        let (signature, recovery) =
+            secp256k1::sign(&Message::parse(id.as_fixed_bytes()), secret).unwrap();
+        let mut bytes = signature.serialize().to_vec();
+        bytes.push(recovery.serialize());
+        format!("0x{}", hex::encode(bytes))
+    }
+
+    #[test]
+    fn shadow_retrieval() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let acl = LocalAcl::in_memory();
+        let server = ReferenceKeyServer::new(3, &acl);
+        let (secret, public) = account();
+        let (other, other_public) = account();
+        let id = H256::random();
+        let key_id = format!("{:x}", id);
+        let signature = sign(&secret, id);
+
+        let server_key = rt
+            .block_on(server.generate_server_key(&key_id, &signature, 1))
+            .unwrap();
+        let server_key = H512::from_str(server_key.trim_start_matches("0x")).unwrap();
+        assert_eq!(
+            rt.block_on(server.server_key_threshold(&key_id, &signature))
+                .unwrap(),
+            Some(1)
+        );
+        let (common_point, encrypted_point, document_key) =
+            generate_document_key(server_key).unwrap();
+        rt.block_on(server.store_document_key(&key_id, &signature, common_point, encrypted_point))
+            .unwrap();
+
+        match rt.block_on(server.get_document_key(&key_id, &signature)) {
+            Err(error) => assert_eq!(error.kind(), Some(ErrorKind::AccessDenied)),
+            _ => panic!("Only users with a grant have access"),
+        }
+        let address =
+            |public: &PublicKey| H160::from_slice(&keccak256(&public.serialize()[1..])[12..]);
+        rt.block_on(acl.grant(address(&public), "", id, &[address(&public)], None, None))
+            .unwrap();
+
+        let keys = rt
+            .block_on(server.get_document_key(&key_id, &signature))
+            .unwrap();
+        assert_eq!(keys.decrypt_shadows.len(), 2);
+        assert_eq!(shadow_decrypt(&secret, &keys).unwrap(), document_key);
+
+        let encrypted = rt
+            .block_on(server.get_encrypted_document_key(&key_id, &signature))
+            .unwrap();
+        let encrypted = hex::decode(encrypted.trim_start_matches("0x")).unwrap();
+        assert_eq!(
+            ecies_decrypt(&secret, &encrypted).unwrap(),
+            document_key.as_bytes()
+        );
+
+        match rt.block_on(server.get_document_key(&key_id, &sign(&other, id))) {
+            Err(error) => assert_eq!(error.kind(), Some(ErrorKind::AccessDenied)),
+            _ => panic!("Only users with a grant have access"),
+        }
+        rt.block_on(acl.grant(
+            address(&public),
+            "",
+            id,
+            &[address(&other_public)],
+            None,
+            None,
+        ))
+        .unwrap();
+        let keys = rt
+            .block_on(server.get_document_key(&key_id, &sign(&other, id)))
+            .unwrap();
+        assert_eq!(shadow_decrypt(&other, &keys).unwrap(), document_key);
+        assert!(rt
+            .block_on(server.generate_server_key(&key_id, &signature, 3))
+            .is_err());
+    }
+}
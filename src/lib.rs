@@ -1,97 +1,915 @@
 #![feature(test)]
 #![allow(non_snake_case)]
-#[macro_use]
-extern crate jsonrpc_client_core;
-extern crate jsonrpc_client_http;
 extern crate rand;
 extern crate test;
 
 use primitive_types::H160;
 use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite};
+mod acl;
 mod api;
 mod blockchain;
+mod config;
+pub mod daemon;
+mod error;
+mod grant;
+mod id;
+mod keyserver;
+pub mod matrix;
+pub mod network;
+mod node;
+mod outbox;
+mod permit;
+mod secret;
+mod stream;
+mod threshold;
 
 type Password = str;
 type Data = String;
 
+pub use crate::acl::{AccessControl, EthereumAcl, LocalAcl, LOCAL_TRANSACTION};
+use crate::api::transport::Executor;
 use crate::blockchain::Blockchain;
+pub use crate::blockchain::{ChunkResult, DecryptionKeys, KeyReuse, ShadowError, ShadowErrorKind};
+pub use crate::config::{Config, ContractConfig, TransportConfig};
+pub use crate::error::ErrorClass;
+pub use crate::grant::{AccessEvent, Grant};
+pub use crate::id::{DocumentId, IntoDocumentId, Nonce, RoomSecret};
+pub use crate::keyserver::reference::ReferenceKeyServer;
+pub use crate::keyserver::{KeyServer, Shadow};
+use crate::matrix::{EncryptedEventContent, Event};
+pub use crate::node::local::LocalNode;
+pub use crate::node::{Node, RpcNode};
+use crate::outbox::Outbox;
+pub use crate::outbox::{OutboxAction, OutboxItem, OutboxState, Queued};
+pub use crate::permit::{Permit, SignedPermit};
+pub use crate::secret::{PasswordProvider, PromptPassword, Secret, SecretString, StaticPassword};
+use crate::stream::{ChunkCipher, Header, DEFAULT_CHUNK_SIZE};
+pub use crate::threshold::{ThresholdError, ThresholdPolicy};
 use ethcontract::transaction::TransactionResult;
-use failure::Error;
-use tokio::runtime::Runtime;
+use failure::{format_err, Error};
+use primitive_types::U256;
 
-pub struct CryptoSecretStore {
-    blockchain: Blockchain,
+/* The ID is a hash of the document, so anybody can confirm a guessed document and identical
+ * documents get the same ID. Use DocumentId::derive for messages */
+pub fn generate_id(document: &str) -> String {
+    generate_id_bytes(document.as_bytes())
+}
+
+/* Like generate_id for documents which aren't UTF-8 */
+pub fn generate_id_bytes(document: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(document))
+}
+
+/* Deploy a new ACL contract, its address is stored in the address file of the config */
+pub fn deploy_contract(addr: &str, password: &str, config: &Config) -> Result<H160, Error> {
+    let address = H160::from_str(addr)?;
+    Executor::current().enter(|| Blockchain::deploy_contract(address, password, config))
+}
+
+/* Connections, runtime and contract instance, shared by all identities of a store */
+struct Context<A: AccessControl, K: KeyServer, N: Node> {
+    blockchain: Blockchain<A, K, N>,
+    rt: Executor,
+    /* Only the outbox is locked, requests of different identities run at the same time */
+    outbox: Mutex<Outbox>,
+    parallel_requests: usize,
+}
+
+/// A document encrypted again under a new key by `CryptoSecretStore::rekey`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rekeyed {
+    pub old_id: DocumentId,
+    pub id: DocumentId,
+    pub ciphertext: String,
+    pub threshold: u32,
+}
+
+pub struct CryptoSecretStore<
+    A: AccessControl = EthereumAcl,
+    K: KeyServer = Shadow,
+    N: Node = RpcNode,
+> {
+    context: Arc<Context<A, K, N>>,
     address: H160,
-    password: String,
-    rt: Runtime,
+    credentials: Arc<dyn PasswordProvider>,
 }
 
 impl CryptoSecretStore {
     pub fn new(addr: &str, password: &str) -> Self {
-        let address = H160::from_str(addr).unwrap();
-        let password = password.to_string();
-        let blockchain = Blockchain::new(
+        CryptoSecretStore::with_config(addr, password, &Config::default()).unwrap()
+    }
+
+    pub fn with_config(addr: &str, password: &str, config: &Config) -> Result<Self, Error> {
+        CryptoSecretStore::with_credentials(addr, StaticPassword::new(password), config)
+    }
+
+    /* The password is requested from credentials whenever it's needed instead of being
+     * stored, e.g. use PromptPassword to ask the user */
+    pub fn with_credentials(
+        addr: &str,
+        credentials: impl PasswordProvider + 'static,
+        config: &Config,
+    ) -> Result<Self, Error> {
+        let address = H160::from_str(addr)?;
+        let rt = Executor::current();
+        let blockchain = {
+            let password = credentials.password(address)?;
+            rt.enter(|| Blockchain::new(address, password.expose(), config))?
+        };
+        CryptoSecretStore::from_blockchain(address, credentials, blockchain, rt, config)
+    }
+
+    /* Remove grants which ran out, they show up as AccessEvent::Expired */
+    pub fn expire(
+        &self,
+        document_id: impl IntoDocumentId,
+        addresses: &[H160],
+    ) -> Result<TransactionResult, Error> {
+        let document_id = &document_id.into_document_id()?.key_id();
+        let password = self.password()?;
+        let Context { blockchain, rt, .. } = &*self.context;
+        rt.block_on(blockchain.expire(self.address, password.expose(), document_id, addresses))
+    }
+
+    /* Grant changes since from_block together with their block number, optionally only
+     * for one document */
+    pub fn access_events(
+        &self,
+        from_block: u64,
+        document_id: Option<DocumentId>,
+    ) -> Result<Vec<(u64, AccessEvent)>, Error> {
+        let document_id = document_id.map(|id| id.key_id());
+        let Context { blockchain, rt, .. } = &*self.context;
+        rt.block_on(blockchain.access_events(from_block, document_id.as_deref()))
+    }
+
+    /* Create a grant signed by this account which can be submitted by a relayer,
+     * deadline is a unix timestamp after which the permit isn't valid anymore */
+    pub fn sign_permit(
+        &self,
+        document_id: impl IntoDocumentId,
+        addresses: &[H160],
+        deadline: u64,
+    ) -> Result<SignedPermit, Error> {
+        let document_id = &document_id.into_document_id()?.key_id();
+        let password = self.password()?;
+        let Context { blockchain, rt, .. } = &*self.context;
+        rt.block_on(blockchain.sign_permit(
+            self.address,
+            password.expose(),
+            document_id,
+            addresses,
+            U256::from(deadline),
+        ))
+    }
+
+    /* Submit a permit signed by another account, this account pays for the gas */
+    pub fn submit_permit(&self, permit: &SignedPermit) -> Result<TransactionResult, Error> {
+        let password = self.password()?;
+        let Context { blockchain, rt, .. } = &*self.context;
+        rt.block_on(blockchain.submit_permit(self.address, password.expose(), permit))
+    }
+}
+
+impl<A: AccessControl> CryptoSecretStore<A> {
+    /* Use another access control than the ACL contract, e.g. LocalAcl in tests */
+    pub fn with_access_control(
+        addr: &str,
+        credentials: impl PasswordProvider + 'static,
+        acl: A,
+        config: &Config,
+    ) -> Result<Self, Error> {
+        let address = H160::from_str(addr)?;
+        let blockchain = Blockchain::with_access_control(acl, config)?;
+        CryptoSecretStore::from_blockchain(
             address,
-            &password,
-            "http://127.0.0.1:8010",
-            "http://127.0.0.1:8545",
+            credentials,
+            blockchain,
+            Executor::current(),
+            config,
         )
-        .unwrap();
+    }
+}
 
-        let rt = Runtime::new().unwrap();
+impl<A: AccessControl, K: KeyServer> CryptoSecretStore<A, K> {
+    /* Use another backend than the Secret Store HTTP API, e.g. ReferenceKeyServer for tests */
+    pub fn with_key_server(
+        addr: &str,
+        credentials: impl PasswordProvider + 'static,
+        acl: A,
+        key_server: K,
+        config: &Config,
+    ) -> Result<Self, Error> {
+        let address = H160::from_str(addr)?;
+        let blockchain = Blockchain::with_key_server(acl, key_server, config)?;
+        CryptoSecretStore::from_blockchain(
+            address,
+            credentials,
+            blockchain,
+            Executor::current(),
+            config,
+        )
+    }
+}
 
-        CryptoSecretStore {
+impl<A: AccessControl, K: KeyServer, N: Node> CryptoSecretStore<A, K, N> {
+    /* Use another node for the account keys, e.g. LocalNode to run tests without a node */
+    pub fn with_node(
+        addr: &str,
+        credentials: impl PasswordProvider + 'static,
+        acl: A,
+        key_server: K,
+        node: N,
+        config: &Config,
+    ) -> Result<Self, Error> {
+        let address = H160::from_str(addr)?;
+        let blockchain = Blockchain::with_node(acl, key_server, node, config);
+        CryptoSecretStore::from_blockchain(
+            address,
+            credentials,
             blockchain,
+            Executor::current(),
+            config,
+        )
+    }
+
+    fn from_blockchain(
+        address: H160,
+        credentials: impl PasswordProvider + 'static,
+        blockchain: Blockchain<A, K, N>,
+        rt: Executor,
+        config: &Config,
+    ) -> Result<Self, Error> {
+        let outbox = match &config.outbox_file {
+            Some(path) => Outbox::open(path)?,
+            None => Outbox::in_memory(),
+        };
+
+        Ok(CryptoSecretStore {
+            context: Arc::new(Context {
+                blockchain,
+                rt,
+                outbox: Mutex::new(outbox),
+                parallel_requests: config.parallel_requests,
+            }),
             address,
-            password,
-            rt,
+            credentials: Arc::new(credentials),
+        })
+    }
+
+    /* A handle acting as another account which shares the connections of this store, e.g.
+     * for a bridge acting for many users. The credentials are checked against the node
+     * before the handle is returned, and a handle only ever uses its own credentials */
+    pub fn as_identity(
+        &self,
+        addr: &str,
+        credentials: impl PasswordProvider + 'static,
+    ) -> Result<CryptoSecretStore<A, K, N>, Error> {
+        let address = H160::from_str(addr)?;
+        {
+            let password = credentials.password(address)?;
+            let Context { blockchain, rt, .. } = &*self.context;
+            rt.block_on(blockchain.verify_credentials(address, password.expose()))?;
         }
+
+        Ok(CryptoSecretStore {
+            context: self.context.clone(),
+            address,
+            credentials: Arc::new(credentials),
+        })
+    }
+
+    pub fn address(&self) -> H160 {
+        self.address
+    }
+
+    /* Number of Secret Store nodes from the config or the server set contract */
+    pub fn cluster_size(&self) -> Option<usize> {
+        self.context.blockchain.cluster_size()
+    }
+
+    /* Threshold used for a document, impossible thresholds are rejected before anything is
+     * sent to the Secret Store */
+    pub fn threshold(&self, policy: impl Into<ThresholdPolicy>) -> Result<u32, Error> {
+        Ok(policy.into().threshold(self.cluster_size())?)
     }
 
-    pub fn generate_id(&mut self, document: &str) -> String {
-        format!("{:x}", Sha256::digest(&document.as_bytes()))
+    fn password(&self) -> Result<SecretString, Error> {
+        self.credentials.password(self.address)
     }
 
-    pub fn encrypt(&mut self, id: &str, document: &str, threshold: u32) -> Result<String, Error> {
-        let document_id = &format!("{:x}", Sha256::digest(&id.as_bytes()));
-        self.rt.block_on(self.blockchain.encrypt(
+    fn outbox(&self) -> Result<MutexGuard<Outbox>, Error> {
+        self.context
+            .outbox
+            .lock()
+            .map_err(|_| format_err!("A previous request panicked while using the outbox"))
+    }
+
+    pub fn generate_id(&self, document: &str) -> String {
+        generate_id(document)
+    }
+
+    pub fn encrypt(
+        &self,
+        id: impl IntoDocumentId,
+        document: &str,
+        threshold: impl Into<ThresholdPolicy>,
+    ) -> Result<String, Error> {
+        let document_id = &id.into_document_id()?.key_id();
+        let threshold = self.threshold(threshold)?;
+        let password = self.password()?;
+        let Context { blockchain, rt, .. } = &*self.context;
+        rt.block_on(blockchain.encrypt(
+            self.address,
+            password.expose(),
+            document_id,
+            document,
+            threshold,
+            KeyReuse::Deny,
+        ))
+    }
+
+    /* Like encrypt, but if a document key was already stored for this ID the document is
+     * encrypted with the existing key. Requires access to the document. A server key left
+     * without document key is reused even if the Secret Store can't confirm its threshold */
+    pub fn reencrypt(
+        &self,
+        id: impl IntoDocumentId,
+        document: &str,
+        threshold: impl Into<ThresholdPolicy>,
+    ) -> Result<String, Error> {
+        let document_id = &id.into_document_id()?.key_id();
+        let threshold = self.threshold(threshold)?;
+        let password = self.password()?;
+        let Context { blockchain, rt, .. } = &*self.context;
+        rt.block_on(blockchain.encrypt(
             self.address,
-            &self.password,
+            password.expose(),
             document_id,
             document,
             threshold,
+            KeyReuse::Allow,
         ))
     }
 
-    pub fn decrypt(&mut self, id: &str, encrypted_document: &str) -> Result<String, Error> {
-        let document_id = &format!("{:x}", Sha256::digest(&id.as_bytes()));
-        self.rt.block_on(self.blockchain.decrypt(
+    pub fn decrypt(
+        &self,
+        id: impl IntoDocumentId,
+        encrypted_document: &str,
+    ) -> Result<String, Error> {
+        let document_id = &id.into_document_id()?.key_id();
+        let password = self.password()?;
+        let Context { blockchain, rt, .. } = &*self.context;
+        rt.block_on(blockchain.decrypt(
             self.address,
-            &self.password,
+            password.expose(),
             document_id,
             &encrypted_document.to_string(),
         ))
     }
 
+    /* Encrypt many documents at once, e.g. to import the history of a room. Every step is
+     * a single batch for all documents instead of one request per document. Returns the
+     * ciphertext or the error of every document */
+    pub fn encrypt_many(
+        &self,
+        documents: &[(DocumentId, &str)],
+        threshold: impl Into<ThresholdPolicy>,
+    ) -> Result<Vec<Result<String, Error>>, Error> {
+        let threshold = self.threshold(threshold)?;
+        let ids: Vec<String> = documents.iter().map(|(id, _)| id.key_id()).collect();
+        let documents: Vec<(&str, &str)> = ids
+            .iter()
+            .zip(documents)
+            .map(|(id, (_, document))| (id.as_str(), *document))
+            .collect();
+        let password = self.password()?;
+        let Context {
+            blockchain,
+            rt,
+            parallel_requests,
+            ..
+        } = &*self.context;
+        rt.block_on(blockchain.encrypt_many(
+            self.address,
+            password.expose(),
+            &documents,
+            threshold,
+            *parallel_requests,
+        ))
+    }
+
+    /* Decrypt many documents at once, see encrypt_many */
+    pub fn decrypt_many(
+        &self,
+        documents: &[(DocumentId, &str)],
+    ) -> Result<Vec<Result<String, Error>>, Error> {
+        let ids: Vec<String> = documents.iter().map(|(id, _)| id.key_id()).collect();
+        let documents: Vec<(&str, &str)> = ids
+            .iter()
+            .zip(documents)
+            .map(|(id, (_, encrypted_document))| (id.as_str(), *encrypted_document))
+            .collect();
+        let password = self.password()?;
+        let Context {
+            blockchain,
+            rt,
+            parallel_requests,
+            ..
+        } = &*self.context;
+        rt.block_on(blockchain.decrypt_many(
+            self.address,
+            password.expose(),
+            &documents,
+            *parallel_requests,
+        ))
+    }
+
+    /* Encrypt a document again under a new key and a fresh ID, e.g. after a member was
+     * removed who may have cached the old key, or to raise the threshold. The grants of the
+     * old document are copied to the new one, except for addresses in exclude */
+    pub fn rekey(
+        &self,
+        old_id: impl IntoDocumentId,
+        ciphertext: &str,
+        threshold: impl Into<ThresholdPolicy>,
+        exclude: &[H160],
+    ) -> Result<Rekeyed, Error> {
+        let old_id = old_id.into_document_id()?;
+        let threshold = self.threshold(threshold)?;
+        let document = Secret::new(self.decrypt(&old_id, ciphertext)?);
+
+        let id = DocumentId::random();
+        let ciphertext = self.encrypt(&id, document.expose(), threshold)?;
+        /* Claim the new document before anybody else can, the first grant sets the owner */
+        self.allow_access(&id, &[self.address])?;
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        /* One transaction per validity window instead of one per member */
+        let mut windows: BTreeMap<(Option<u64>, Option<u64>), Vec<H160>> = BTreeMap::new();
+        /* The owner has access without a grant, so it isn't a member and gets an unlimited
+         * grant on the new document */
+        let owner = self.owner_of(&old_id)?;
+        if !owner.is_zero() && owner != self.address && !exclude.contains(&owner) {
+            windows.entry((None, None)).or_default().push(owner);
+        }
+        let members = self.members_of(&old_id)?;
+        for &member in &members {
+            if exclude.contains(&member) || member == self.address || member == owner {
+                continue;
+            }
+            let grant = self.check_permissions(member, &old_id)?;
+            if grant.is_expired(now) {
+                continue;
+            }
+            windows
+                .entry((grant.valid_from, grant.valid_until))
+                .or_default()
+                .push(member);
+        }
+        for ((valid_from, valid_until), members) in windows {
+            if valid_from.is_some() || valid_until.is_some() {
+                self.allow_access_until(&id, &members, valid_from, valid_until)?;
+            } else {
+                self.allow_access(&id, &members)?;
+            }
+        }
+
+        /* Only the owner can revoke, for anybody else the excluded members keep their grants
+         * on the old document. They had its key already, the new one is what matters */
+        let excluded: Vec<H160> = members
+            .into_iter()
+            .filter(|member| exclude.contains(member))
+            .collect();
+        if owner == self.address && !excluded.is_empty() {
+            self.revoke_access(&old_id, &excluded)?;
+        }
+
+        Ok(Rekeyed {
+            old_id,
+            id,
+            ciphertext,
+            threshold,
+        })
+    }
+
+    /* Rekey many documents, e.g. the history of a room. A failure only affects its document */
+    pub fn rekey_many(
+        &self,
+        documents: &[(DocumentId, String)],
+        threshold: impl Into<ThresholdPolicy>,
+        exclude: &[H160],
+    ) -> Vec<Result<Rekeyed, Error>> {
+        let threshold = threshold.into();
+        documents
+            .iter()
+            .map(|(id, ciphertext)| self.rekey(id, ciphertext, threshold, exclude))
+            .collect()
+    }
+
+    /* Encrypt a Matrix event into the content of an m.room.encrypted event, the document ID
+     * is derived from the room secret so it doesn't reveal anything about the event.
+     * transaction_id is the one the event is going to be sent with */
+    pub fn encrypt_event(
+        &self,
+        room_secret: &RoomSecret,
+        room_id: &str,
+        transaction_id: &str,
+        event: &Event,
+        threshold: impl Into<ThresholdPolicy>,
+    ) -> Result<EncryptedEventContent, Error> {
+        let threshold = self.threshold(threshold)?;
+        let (id, nonce) = DocumentId::derive(room_secret, room_id, transaction_id);
+        let payload = matrix::seal_payload(room_id, transaction_id, &nonce, event)?;
+        let ciphertext = self.encrypt(&id, &payload, threshold)?;
+        Ok(EncryptedEventContent::new(
+            ciphertext,
+            id,
+            threshold,
+            self.address,
+        ))
+    }
+
+    /* Decrypt the content of an m.room.encrypted event received in room_id */
+    pub fn decrypt_event(
+        &self,
+        room_secret: &RoomSecret,
+        room_id: &str,
+        content: &EncryptedEventContent,
+    ) -> Result<Event, Error> {
+        content.check_algorithm()?;
+        let payload = self.decrypt(&content.document_key_id, &content.ciphertext)?;
+        Ok(matrix::open_payload(room_secret, room_id, &content.document_key_id, &payload)?.event)
+    }
+
+    /* Encrypt a large document in fixed size chunks, only the chunk key is encrypted by the
+     * Secret Store. Returns the number of bytes read */
+    pub fn encrypt_stream<R, W>(
+        &self,
+        id: impl IntoDocumentId,
+        reader: &mut R,
+        writer: &mut W,
+        threshold: impl Into<ThresholdPolicy>,
+    ) -> Result<u64, Error>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let document_id = &id.into_document_id()?.key_id();
+        let key = Secret::new(rand::random::<[u8; 32]>());
+        let encoded_key = Secret::new(hex::encode(key.expose()));
+        let threshold = self.threshold(threshold)?;
+        let password = self.password()?;
+        let Context { blockchain, rt, .. } = &*self.context;
+        let wrapped_key = rt.block_on(blockchain.encrypt(
+            self.address,
+            password.expose(),
+            document_id,
+            encoded_key.expose(),
+            threshold,
+            KeyReuse::Deny,
+        ))?;
+        let cipher = ChunkCipher::new(key.expose(), Header::new(DEFAULT_CHUNK_SIZE, wrapped_key));
+        Ok(rt.block_on(stream::encrypt(&cipher, reader, writer))?)
+    }
+
+    /* Decrypt a document created by encrypt_stream, every chunk is verified before it's
+     * written. Returns the number of bytes written */
+    pub fn decrypt_stream<R, W>(
+        &self,
+        id: impl IntoDocumentId,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<u64, Error>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let header = self.context.rt.block_on(Header::read(reader))?;
+        let cipher = self.stream_cipher(&id.into_document_id()?, header)?;
+        Ok(self
+            .context
+            .rt
+            .block_on(stream::decrypt(&cipher, reader, writer))?)
+    }
+
+    /* Decrypt only the chunk at index of a document created by encrypt_stream */
+    pub fn decrypt_stream_chunk<R>(
+        &self,
+        id: impl IntoDocumentId,
+        reader: &mut R,
+        index: u64,
+    ) -> Result<Vec<u8>, Error>
+    where
+        R: AsyncRead + AsyncSeek + Unpin,
+    {
+        let header = self.context.rt.block_on(Header::read(reader))?;
+        let cipher = self.stream_cipher(&id.into_document_id()?, header)?;
+        Ok(self
+            .context
+            .rt
+            .block_on(stream::decrypt_chunk(&cipher, reader, index))?)
+    }
+
+    fn stream_cipher(&self, id: &DocumentId, header: Header) -> Result<ChunkCipher, Error> {
+        let document_id = &id.key_id();
+        let password = self.password()?;
+        let Context { blockchain, rt, .. } = &*self.context;
+        let key = Secret::new(rt.block_on(blockchain.decrypt(
+            self.address,
+            password.expose(),
+            document_id,
+            &header.wrapped_key,
+        ))?);
+        let key = Secret::new(hex::decode(key.expose())?);
+        let key = <&[u8; 32]>::try_from(key.expose().as_slice())
+            .map_err(|_| format_err!("Invalid chunk key length {}", key.expose().len()))?;
+        Ok(ChunkCipher::new(key, header))
+    }
+
     pub fn allow_access(
-        &mut self,
-        document_id: &str,
+        &self,
+        document_id: impl IntoDocumentId,
+        addresses: &[H160],
+    ) -> Result<TransactionResult, Error> {
+        let document_id = &document_id.into_document_id()?.key_id();
+        let password = self.password()?;
+        let Context { blockchain, rt, .. } = &*self.context;
+        rt.block_on(blockchain.allow_access(
+            self.address,
+            password.expose(),
+            document_id,
+            addresses,
+        ))
+    }
+
+    /* Grant access to many documents, e.g. the history of a room for a new member. The
+     * documents are split into as few transactions as fit into a block, the result of
+     * every transaction is returned */
+    pub fn allow_access_batch(
+        &self,
+        document_ids: &[DocumentId],
+        addresses: &[H160],
+    ) -> Result<Vec<ChunkResult>, Error> {
+        let ids: Vec<String> = document_ids.iter().map(DocumentId::key_id).collect();
+        let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+        let password = self.password()?;
+        let Context { blockchain, rt, .. } = &*self.context;
+        rt.block_on(blockchain.allow_access_batch(self.address, password.expose(), &ids, addresses))
+    }
+
+    pub fn revoke_access_batch(
+        &self,
+        document_ids: &[DocumentId],
+        addresses: &[H160],
+    ) -> Result<Vec<ChunkResult>, Error> {
+        let ids: Vec<String> = document_ids.iter().map(DocumentId::key_id).collect();
+        let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+        let password = self.password()?;
+        let Context { blockchain, rt, .. } = &*self.context;
+        rt.block_on(blockchain.revoke_access_batch(
+            self.address,
+            password.expose(),
+            &ids,
+            addresses,
+        ))
+    }
+
+    pub fn revoke_access(
+        &self,
+        document_id: impl IntoDocumentId,
+        addresses: &[H160],
+    ) -> Result<TransactionResult, Error> {
+        let document_id = &document_id.into_document_id()?.key_id();
+        let password = self.password()?;
+        let Context { blockchain, rt, .. } = &*self.context;
+        rt.block_on(blockchain.revoke_access(
+            self.address,
+            password.expose(),
+            document_id,
+            addresses,
+        ))
+    }
+
+    /* Like allow_access, but the grant is kept in the outbox until the node confirms it,
+     * so it isn't lost when the node is unreachable. Only fails if the item couldn't be
+     * queued, a failed flush is reported in the result */
+    pub fn queue_allow_access(
+        &self,
+        document_id: impl IntoDocumentId,
+        addresses: &[H160],
+    ) -> Result<Queued, Error> {
+        self.queue(
+            OutboxAction::Grant,
+            document_id.into_document_id()?,
+            addresses,
+        )
+    }
+
+    pub fn queue_revoke_access(
+        &self,
+        document_id: impl IntoDocumentId,
         addresses: &[H160],
+    ) -> Result<Queued, Error> {
+        self.queue(
+            OutboxAction::Revoke,
+            document_id.into_document_id()?,
+            addresses,
+        )
+    }
+
+    fn queue(
+        &self,
+        action: OutboxAction,
+        document_id: DocumentId,
+        addresses: &[H160],
+    ) -> Result<Queued, Error> {
+        let id = self
+            .outbox()?
+            .push(self.address, action, document_id, addresses)?;
+        Ok(Queued {
+            id,
+            flushed: self.flush_outbox(),
+        })
+    }
+
+    /* Send the queued grants and revocations of this account in the order they were queued
+     * and check the ones which were sent. Stops when the node isn't reachable, call it again
+     * later. Returns the items which are still open */
+    pub fn flush_outbox(&self) -> Result<Vec<OutboxItem>, Error> {
+        let address = self.address;
+        let password = self.password()?;
+        let Context { blockchain, rt, .. } = &*self.context;
+        /* Locked until the flush is done, so two flushes never send the same item */
+        let mut outbox = self.outbox()?;
+
+        rt.block_on(async {
+            for item in outbox.open_items(address) {
+                if !blockchain.is_reachable().await {
+                    break;
+                }
+
+                if item.state == OutboxState::Sent {
+                    if let Some(transaction) = item.transaction {
+                        match blockchain.receipt_status(transaction).await? {
+                            Some(true) => {
+                                outbox.update(item.id, OutboxState::Confirmed, None, None)?
+                            }
+                            Some(false) => outbox.update(
+                                item.id,
+                                OutboxState::Failed,
+                                None,
+                                Some("Transaction was reverted".into()),
+                            )?,
+                            None => {}
+                        }
+                    }
+                    continue;
+                }
+
+                let document_id = item.document_id.key_id();
+                let result = match item.action {
+                    OutboxAction::Grant => {
+                        blockchain
+                            .allow_access(address, password.expose(), &document_id, &item.addresses)
+                            .await
+                    }
+                    OutboxAction::Revoke => {
+                        blockchain
+                            .revoke_access(
+                                address,
+                                password.expose(),
+                                &document_id,
+                                &item.addresses,
+                            )
+                            .await
+                    }
+                };
+                match result {
+                    Ok(TransactionResult::Receipt(receipt)) => {
+                        let state = if receipt.status == Some(0.into()) {
+                            OutboxState::Failed
+                        } else {
+                            OutboxState::Confirmed
+                        };
+                        outbox.update(item.id, state, Some(receipt.transaction_hash), None)?
+                    }
+                    Ok(TransactionResult::Hash(hash)) => {
+                        outbox.update(item.id, OutboxState::Sent, Some(hash), None)?
+                    }
+                    /* The node went away while sending, the item is sent again next time */
+                    Err(error)
+                        if ErrorClass::of(&error) == ErrorClass::Unavailable
+                            || !blockchain.is_reachable().await =>
+                    {
+                        outbox.update(
+                            item.id,
+                            OutboxState::Pending,
+                            None,
+                            Some(error.to_string()),
+                        )?;
+                        break;
+                    }
+                    Err(error) => outbox.update(
+                        item.id,
+                        OutboxState::Failed,
+                        None,
+                        Some(error.to_string()),
+                    )?,
+                }
+            }
+            Ok::<_, Error>(outbox.open_items(address))
+        })
+    }
+
+    /* All outbox items of this account, e.g. to show "access pending" */
+    pub fn outbox_items(&self) -> Result<Vec<OutboxItem>, Error> {
+        Ok(self
+            .outbox()?
+            .items()
+            .iter()
+            .filter(|item| item.sender == self.address)
+            .cloned()
+            .collect())
+    }
+
+    pub fn outbox_state(&self, id: u64) -> Result<Option<OutboxState>, Error> {
+        Ok(self.outbox()?.get(id).map(|item| item.state))
+    }
+
+    /* Forget confirmed and failed items */
+    pub fn clear_outbox(&self) -> Result<(), Error> {
+        self.outbox()?.clear_finished()
+    }
+
+    pub fn check_permissions(
+        &self,
+        address: H160,
+        document_id: impl IntoDocumentId,
+    ) -> Result<Grant, Error> {
+        let document_id = &document_id.into_document_id()?.key_id();
+        let Context { blockchain, rt, .. } = &*self.context;
+        rt.block_on(blockchain.check_permissions(address, document_id))
+    }
+
+    /* Grant access between two unix timestamps (compared with the block time), e.g. guest
+     * access to the history of a room or self-destructing messages */
+    pub fn allow_access_until(
+        &self,
+        document_id: impl IntoDocumentId,
+        addresses: &[H160],
+        valid_from: Option<u64>,
+        valid_until: Option<u64>,
     ) -> Result<TransactionResult, Error> {
-        let document_id = &format!("{:x}", Sha256::digest(&document_id.as_bytes()));
-        self.rt.block_on(self.blockchain.allow_access(
+        let document_id = &document_id.into_document_id()?.key_id();
+        let password = self.password()?;
+        let Context { blockchain, rt, .. } = &*self.context;
+        rt.block_on(blockchain.allow_access_until(
             self.address,
-            &self.password,
+            password.expose(),
             document_id,
             addresses,
+            valid_from,
+            valid_until,
         ))
     }
 
-    pub fn check_permissions(&mut self, address: H160, document_id: &str) -> Result<bool, Error> {
-        let document_id = &format!("{:x}", Sha256::digest(&document_id.as_bytes()));
-        self.rt
-            .block_on(self.blockchain.check_permissions(address, document_id))
+    pub fn owner_of(&self, document_id: impl IntoDocumentId) -> Result<H160, Error> {
+        let document_id = &document_id.into_document_id()?.key_id();
+        let Context { blockchain, rt, .. } = &*self.context;
+        rt.block_on(blockchain.owner_of(document_id))
+    }
+
+    /* Addresses with a grant for the document, without the owner */
+    pub fn members_of(&self, document_id: impl IntoDocumentId) -> Result<Vec<H160>, Error> {
+        let document_id = &document_id.into_document_id()?.key_id();
+        let Context { blockchain, rt, .. } = &*self.context;
+        rt.block_on(blockchain.members_of(document_id))
+    }
+
+    pub fn member_count(&self, document_id: impl IntoDocumentId) -> Result<u64, Error> {
+        let document_id = &document_id.into_document_id()?.key_id();
+        let Context { blockchain, rt, .. } = &*self.context;
+        rt.block_on(blockchain.member_count(document_id))
+    }
+
+    /* Page through the members of documents with many members */
+    pub fn members_page(
+        &self,
+        document_id: impl IntoDocumentId,
+        start: u64,
+        count: u64,
+    ) -> Result<Vec<H160>, Error> {
+        let document_id = &document_id.into_document_id()?.key_id();
+        let Context { blockchain, rt, .. } = &*self.context;
+        rt.block_on(blockchain.members_page(document_id, start, count))
     }
 }
 
@@ -149,7 +967,11 @@ mod tests {
 
         let _ = writeln!(file, "[");
 
-        let limit = 25;
+        /* Only thresholds the cluster can handle */
+        let limit = store
+            .borrow()
+            .cluster_size()
+            .map_or(25, |nodes| nodes as u32);
         for threshold in 0..limit {
             let now = time::Instant::now();
             for _i in 0..5 {
@@ -157,7 +979,7 @@ mod tests {
                 {
                     let address = H160::from_str(address).unwrap();
                     let addresses = vec![address];
-                    let mut store = store.borrow_mut();
+                    let store = store.borrow();
                     let result = store.allow_access(&message.id, &addresses).unwrap();
                     assert_eq!(result.is_receipt(), true);
                 }
@@ -201,7 +1023,7 @@ mod tests {
                 {
                     let address = H160::from_str(address).unwrap();
                     let addresses = vec![address];
-                    let mut store = store.borrow_mut();
+                    let store = store.borrow();
                     let result = store.allow_access(&msg.id, &addresses).unwrap();
                     assert_eq!(result, true);
                 }
@@ -239,7 +1061,11 @@ mod tests {
             .unwrap();
 
         let _ = writeln!(file, "[");
-        let limit = 25;
+        /* Only thresholds the cluster can handle */
+        let limit = store
+            .borrow()
+            .cluster_size()
+            .map_or(25, |nodes| nodes as u32);
         let step: u32 = 1;
         for threshold in (step..limit) {
             let mut messages: Vec<Message> = vec![];
@@ -254,7 +1080,7 @@ mod tests {
             for i in 0..5 {
                 let address = H160::from_str(address).unwrap();
                 let addresses = vec![address];
-                let mut store = store.borrow_mut();
+                let store = store.borrow();
                 let result = store.allow_access(&messages[i].id, &addresses).unwrap();
                 assert_eq!(result.is_receipt(), true);
             }
@@ -313,7 +1139,7 @@ mod tests {
             for i in 0..5 {
                 let address = H160::from_str(address).unwrap();
                 let addresses = vec![address];
-                let mut store = store.borrow_mut();
+                let store = store.borrow();
                 let result = store.allow_access(&messages[i].id, &addresses).unwrap();
                 assert_eq!(result.is_receipt(), true);
             }
@@ -352,13 +1178,147 @@ mod tests {
         {
             let address = H160::from_str(address).unwrap();
             let addresses = vec![address];
-            let mut store = store.borrow_mut();
+            let store = store.borrow();
             let result = store.check_permissions(address, &message.id).unwrap();
-            assert_eq!(result, false);
+            assert_eq!(result.is_allowed(), false);
             let result = store.allow_access(&message.id, &addresses).unwrap();
             assert_eq!(result.is_receipt(), true);
             let result = store.check_permissions(address, &message.id).unwrap();
-            assert_eq!(result, true);
+            assert_eq!(result.is_allowed(), true);
+        }
+        decrypt(store, message);
+    }
+
+    #[test]
+    fn encrypt_decrypt_stream() {
+        let address = "27d39a0fe767025e7ea0f78dccd4665929e3a8f2";
+        let password = "alicepwd";
+        let store = CryptoSecretStore::new(address, password);
+        let document: Vec<u8> = (0..1_000_000u32).map(|_| rand::random()).collect();
+        let id = store.generate_id(&hex::encode(&document[..64]));
+
+        let mut encrypted = vec![];
+        let read = store
+            .encrypt_stream(&id, &mut std::io::Cursor::new(&document), &mut encrypted, 1)
+            .unwrap();
+        assert_eq!(read, document.len() as u64);
+        {
+            let addresses = vec![H160::from_str(address).unwrap()];
+            let result = store.allow_access(&id, &addresses).unwrap();
+            assert_eq!(result.is_receipt(), true);
+        }
+
+        let mut decrypted = vec![];
+        store
+            .decrypt_stream(&id, &mut std::io::Cursor::new(&encrypted), &mut decrypted)
+            .unwrap();
+        assert_eq!(decrypted, document);
+
+        let chunk = store
+            .decrypt_stream_chunk(&id, &mut std::io::Cursor::new(&encrypted), 3)
+            .unwrap();
+        let offset = 3 * DEFAULT_CHUNK_SIZE as usize;
+        assert_eq!(
+            chunk,
+            &document[offset..offset + DEFAULT_CHUNK_SIZE as usize]
+        );
+    }
+
+    #[test]
+    fn encrypt_decrypt_event() {
+        let address = "27d39a0fe767025e7ea0f78dccd4665929e3a8f2";
+        let password = "alicepwd";
+        let store = CryptoSecretStore::new(address, password);
+        let body: String = thread_rng().sample_iter(&Alphanumeric).take(30).collect();
+        let event = matrix::Event {
+            event_type: "m.room.message".into(),
+            content: serde_json::json!({"msgtype": "m.text", "body": body}),
+        };
+
+        let room_secret = RoomSecret::generate();
+        let content = store
+            .encrypt_event(&room_secret, "!room:example.org", "txn1", &event, 1)
+            .unwrap();
+        assert_eq!(content.algorithm, matrix::ALGORITHM);
+        {
+            let addresses = vec![H160::from_str(address).unwrap()];
+            let result = store
+                .allow_access(&content.document_key_id, &addresses)
+                .unwrap();
+            assert_eq!(result.is_receipt(), true);
+        }
+        let decrypted = store
+            .decrypt_event(&room_secret, "!room:example.org", &content)
+            .unwrap();
+        assert_eq!(decrypted, event);
+        let replayed = store.decrypt_event(&room_secret, "!other:example.org", &content);
+        assert_eq!(replayed.is_err(), true);
+    }
+
+    /* JSON-RPC call to the user's node of the test network */
+    fn node_rpc(method: &str, params: serde_json::Value) -> serde_json::Value {
+        let response: serde_json::Value = reqwest::blocking::Client::new()
+            .post(&Config::default().rpc_url)
+            .json(
+                &serde_json::json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params}),
+            )
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        response["result"].clone()
+    }
+
+    #[test]
+    fn foreign_grant() {
+        let address = "27d39a0fe767025e7ea0f78dccd4665929e3a8f2";
+        let store = CryptoSecretStore::new(address, "alicepwd");
+        let id = DocumentId::random();
+        let result = store.allow_access(&id, &[H160::random()]).unwrap();
+        assert_eq!(result.is_receipt(), true);
+
+        /* A second account with some ether, so it can send transactions */
+        let bob = node_rpc("personal_newAccount", serde_json::json!(["bobpwd"]));
+        let bob = bob.as_str().unwrap().trim_start_matches("0x").to_string();
+        node_rpc(
+            "personal_sendTransaction",
+            serde_json::json!([{"from": format!("0x{}", address), "to": format!("0x{}", bob), "value": "0xde0b6b3a7640000"}, "alicepwd"]),
+        );
+
+        let bob_address = H160::from_str(&bob).unwrap();
+        let bob_store = CryptoSecretStore::new(&bob, "bobpwd");
+        let error = bob_store
+            .allow_access(&id, &[bob_address])
+            .unwrap_err()
+            .to_string();
+        let reason = "Sender not authorized.";
+        assert!(error.contains(reason) || error.contains(&hex::encode(reason)));
+        assert_eq!(
+            store.owner_of(&id).unwrap(),
+            H160::from_str(address).unwrap()
+        );
+        assert!(!store
+            .check_permissions(bob_address, &id)
+            .unwrap()
+            .is_allowed());
+    }
+
+    #[test]
+    fn encrypt_existing_id() {
+        let address = "27d39a0fe767025e7ea0f78dccd4665929e3a8f2";
+        let password = "alicepwd";
+        let store = Rc::new(RefCell::new(CryptoSecretStore::new(address, password)));
+        let message = encrypt(store.clone(), 30, 1);
+        {
+            let addresses = vec![H160::from_str(address).unwrap()];
+            let store = store.borrow();
+            let result = store.allow_access(&message.id, &addresses).unwrap();
+            assert_eq!(result.is_receipt(), true);
+            let result = store.encrypt(&message.id, "Other document", 1);
+            assert_eq!(result.is_err(), true);
+            let ciphertext = store.reencrypt(&message.id, "Other document", 1).unwrap();
+            let cleartext = store.decrypt(&message.id, &ciphertext).unwrap();
+            assert_eq!(cleartext, "Other document");
         }
         decrypt(store, message);
     }
@@ -368,7 +1328,7 @@ mod tests {
         let address = "27d39a0fe767025e7ea0f78dccd4665929e3a8f2";
         let password = "alicepwd";
         let store = Rc::new(RefCell::new(CryptoSecretStore::new(address, password)));
-        let mut store = store.borrow_mut();
+        let store = store.borrow();
 
         let document: String = thread_rng().sample_iter(&Alphanumeric).take(30).collect();
         let id = store.generate_id(&document);
@@ -376,22 +1336,427 @@ mod tests {
         let test_addr1 = H160::random();
         let addresses = vec![test_addr, test_addr1];
         let result = store.check_permissions(test_addr, &id).unwrap();
-        assert_eq!(result, false);
+        assert_eq!(result.is_allowed(), false);
         let result = store.check_permissions(test_addr1, &id).unwrap();
-        assert_eq!(result, false);
+        assert_eq!(result.is_allowed(), false);
         let result = store.allow_access(&id, &addresses).unwrap();
         assert_eq!(result.is_receipt(), true);
         let result = store.check_permissions(test_addr, &id).unwrap();
-        assert_eq!(result, true);
+        assert_eq!(result.is_allowed(), true);
+        let result = store.check_permissions(test_addr1, &id).unwrap();
+        assert_eq!(result.is_allowed(), true);
+        let result = store.revoke_access(&id, &[test_addr1]).unwrap();
+        assert_eq!(result.is_receipt(), true);
+        let result = store.check_permissions(test_addr, &id).unwrap();
+        assert_eq!(result.is_allowed(), true);
         let result = store.check_permissions(test_addr1, &id).unwrap();
-        assert_eq!(result, true);
+        assert_eq!(result.is_allowed(), false);
+    }
+
+    #[test]
+    fn timed_access() {
+        let address = "27d39a0fe767025e7ea0f78dccd4665929e3a8f2";
+        let password = "alicepwd";
+        let store = CryptoSecretStore::new(address, password);
+
+        let id = DocumentId::random();
+        let test_addr = H160::random();
+        let test_addr1 = H160::random();
+        let now = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let result = store
+            .allow_access_until(&id, &[test_addr], None, Some(now + 3600))
+            .unwrap();
+        assert_eq!(result.is_receipt(), true);
+        let grant = store.check_permissions(test_addr, &id).unwrap();
+        assert_eq!(grant.is_allowed(), true);
+        assert_eq!(grant.valid_until, Some(now + 3600));
+
+        // A grant which already ran out
+        let result = store
+            .allow_access_until(&id, &[test_addr1], None, Some(1))
+            .unwrap();
+        assert_eq!(result.is_receipt(), true);
+        let grant = store.check_permissions(test_addr1, &id).unwrap();
+        assert_eq!(grant.is_allowed(), false);
+        assert_eq!(grant.is_expired(now), true);
+
+        let result = store.expire(&id, &[test_addr, test_addr1]).unwrap();
+        assert_eq!(result.is_receipt(), true);
+        let events = store.access_events(0, Some(id.clone())).unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[2].1,
+            AccessEvent::Expired {
+                document_id: id.hashed(),
+                user: test_addr1,
+                valid_until: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn shared_identities() {
+        let address = "27d39a0fe767025e7ea0f78dccd4665929e3a8f2";
+        let password = "alicepwd";
+        let store = CryptoSecretStore::new(address, password);
+        assert!(store
+            .as_identity(address, StaticPassword::new("wrongpwd"))
+            .is_err());
+
+        let identity = store
+            .as_identity(address, StaticPassword::new(password))
+            .unwrap();
+        let document: String = thread_rng().sample_iter(&Alphanumeric).take(30).collect();
+        let id = DocumentId::random();
+        let ciphertext = identity.encrypt(&id, &document, 1).unwrap();
+        {
+            let addresses = vec![H160::from_str(address).unwrap()];
+            let result = store.allow_access(&id, &addresses).unwrap();
+            assert_eq!(result.is_receipt(), true);
+        }
+        let cleartext = store.decrypt(&id, &ciphertext).unwrap();
+        assert_eq!(cleartext, document);
+
+        /* Identities don't wait for each other */
+        let identity = Arc::new(identity);
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let identity = identity.clone();
+                let (id, ciphertext) = (id.clone(), ciphertext.clone());
+                std::thread::spawn(move || identity.decrypt(&id, &ciphertext).unwrap())
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), document);
+        }
+    }
+
+    #[test]
+    fn list_members() {
+        let address = "27d39a0fe767025e7ea0f78dccd4665929e3a8f2";
+        let password = "alicepwd";
+        let store = CryptoSecretStore::new(address, password);
+
+        let id = DocumentId::random();
+        let addresses: Vec<H160> = (0..5).map(|_| H160::random()).collect();
+        let result = store.allow_access(&id, &addresses).unwrap();
+        assert_eq!(result.is_receipt(), true);
+        assert_eq!(
+            store.owner_of(&id).unwrap(),
+            H160::from_str(address).unwrap()
+        );
+        assert_eq!(store.members_of(&id).unwrap(), addresses);
+
+        let result = store.revoke_access(&id, &addresses[1..2]).unwrap();
+        assert_eq!(result.is_receipt(), true);
+        assert_eq!(store.member_count(&id).unwrap(), 4);
+        let mut members = store.members_page(&id, 0, 3).unwrap();
+        assert_eq!(members.len(), 3);
+        members.extend(store.members_page(&id, 3, 3).unwrap());
+        assert!(!members.contains(&addresses[1]));
+        assert_eq!(members.len(), 4);
+    }
+
+    #[test]
+    fn batched_documents() {
+        let store = CryptoSecretStore::new("27d39a0fe767025e7ea0f78dccd4665929e3a8f2", "alicepwd");
+        let used = DocumentId::random();
+        store.encrypt(&used, "Already encrypted", 0).unwrap();
+
+        let ids: Vec<DocumentId> = (0..5).map(|_| DocumentId::random()).collect();
+        let mut documents: Vec<(DocumentId, &str)> =
+            ids.iter().map(|id| (id.clone(), "Hello world")).collect();
+        documents.push((used, "Other document"));
+        let ciphertexts = store.encrypt_many(&documents, 0).unwrap();
+        assert_eq!(ciphertexts.len(), 6);
+        assert!(ciphertexts[5].is_err());
+
+        let encrypted: Vec<(DocumentId, &str)> = ids
+            .iter()
+            .zip(&ciphertexts)
+            .map(|(id, ciphertext)| (id.clone(), ciphertext.as_ref().unwrap().as_str()))
+            .collect();
+        store.allow_access_batch(&ids, &[store.address()]).unwrap();
+        for cleartext in store.decrypt_many(&encrypted).unwrap() {
+            assert_eq!(cleartext.unwrap(), "Hello world");
+        }
+    }
+
+    #[test]
+    fn rekey_document() {
+        let address = "27d39a0fe767025e7ea0f78dccd4665929e3a8f2";
+        let password = "alicepwd";
+        let store = CryptoSecretStore::new(address, password);
+
+        let document: String = thread_rng().sample_iter(&Alphanumeric).take(30).collect();
+        let id = DocumentId::random();
+        let ciphertext = store.encrypt(&id, &document, 0).unwrap();
+        let owner = H160::from_str(address).unwrap();
+        let test_addr = H160::random();
+        let test_addr1 = H160::random();
+        let timed: Vec<H160> = (0..2).map(|_| H160::random()).collect();
+        store
+            .allow_access(&id, &[owner, test_addr, test_addr1])
+            .unwrap();
+        store
+            .allow_access_until(&id, &timed, None, Some(u64::from(u32::MAX)))
+            .unwrap();
+
+        let rekeyed = store.rekey(&id, &ciphertext, 1, &[test_addr1]).unwrap();
+        assert_ne!(rekeyed.id, id);
+        assert_eq!(rekeyed.threshold, 1);
+        assert_eq!(store.owner_of(&rekeyed.id).unwrap(), owner);
+        assert_eq!(
+            store.decrypt(&rekeyed.id, &rekeyed.ciphertext).unwrap(),
+            document
+        );
+        assert_eq!(
+            store.members_of(&rekeyed.id).unwrap(),
+            vec![owner, test_addr, timed[0], timed[1]]
+        );
+        assert_eq!(
+            store
+                .check_permissions(timed[1], &rekeyed.id)
+                .unwrap()
+                .valid_until,
+            Some(u64::from(u32::MAX))
+        );
+        /* The excluded member loses the old document as well */
+        assert!(!store.members_of(&id).unwrap().contains(&test_addr1));
+    }
+
+    #[test]
+    fn queued_access() {
+        let address = "27d39a0fe767025e7ea0f78dccd4665929e3a8f2";
+        let password = "alicepwd";
+        let store = CryptoSecretStore::new(address, password);
+
+        let id = DocumentId::random();
+        let test_addr = H160::random();
+        let queued = store.queue_allow_access(&id, &[test_addr]).unwrap();
+        assert!(queued.flushed.unwrap().is_empty());
+        assert_eq!(
+            store.outbox_state(queued.id).unwrap(),
+            Some(OutboxState::Confirmed)
+        );
+        let result = store.check_permissions(test_addr, &id).unwrap();
+        assert_eq!(result.is_allowed(), true);
+        store.clear_outbox().unwrap();
+        assert!(store.outbox_items().unwrap().is_empty());
+    }
+
+    #[test]
+    fn batch_access() {
+        let store = CryptoSecretStore::new("27d39a0fe767025e7ea0f78dccd4665929e3a8f2", "alicepwd");
+        let ids: Vec<DocumentId> = (0..20).map(|_| DocumentId::random()).collect();
+        let test_addr = H160::random();
+
+        let results = store.allow_access_batch(&ids, &[test_addr]).unwrap();
+        assert_eq!(results.last().unwrap().documents.end, ids.len());
+        for chunk in &results {
+            assert!(chunk.result.is_ok());
+        }
+        for id in &ids {
+            assert!(store.check_permissions(test_addr, id).unwrap().is_allowed());
+        }
+
+        let results = store.revoke_access_batch(&ids, &[test_addr]).unwrap();
+        assert!(results.iter().all(|chunk| chunk.result.is_ok()));
+        assert!(!store
+            .check_permissions(test_addr, &ids[0])
+            .unwrap()
+            .is_allowed());
+        assert!(store
+            .allow_access_batch(&[], &[test_addr])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn local_access_control() {
+        let address = "27d39a0fe767025e7ea0f78dccd4665929e3a8f2";
+        let password = "alicepwd";
+        let store = CryptoSecretStore::with_access_control(
+            address,
+            StaticPassword::new(password),
+            LocalAcl::in_memory(),
+            &Config::default(),
+        )
+        .unwrap();
+
+        let id = DocumentId::random();
+        let test_addr = H160::random();
+        let result = store.check_permissions(test_addr, &id).unwrap();
+        assert_eq!(result.is_allowed(), false);
+        let result = store.allow_access(&id, &[test_addr]).unwrap();
+        assert_eq!(result.hash(), LOCAL_TRANSACTION);
+        let result = store.check_permissions(test_addr, &id).unwrap();
+        assert_eq!(result.is_allowed(), true);
+        assert_eq!(
+            store.owner_of(&id).unwrap(),
+            H160::from_str(address).unwrap()
+        );
+        store.revoke_access(&id, &[test_addr]).unwrap();
+        assert!(store.members_of(&id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reference_key_server() {
+        let address = "27d39a0fe767025e7ea0f78dccd4665929e3a8f2";
+        let password = "alicepwd";
+        let acl = LocalAcl::in_memory();
+        let store = CryptoSecretStore::with_key_server(
+            address,
+            StaticPassword::new(password),
+            acl.clone(),
+            ReferenceKeyServer::new(3, &acl),
+            &Config::default(),
+        )
+        .unwrap();
+
+        let id = DocumentId::random();
+        let ciphertext = store.encrypt(&id, "Hello world", 1).unwrap();
+        /* Like with the contract, the author needs a grant */
+        assert!(store.decrypt(&id, &ciphertext).is_err());
+        store.allow_access(&id, &[store.address()]).unwrap();
+        assert_eq!(store.decrypt(&id, &ciphertext).unwrap(), "Hello world");
+        assert!(store.encrypt(&id, "Other document", 1).is_err());
+    }
+
+    #[test]
+    fn local_node() {
+        let node = LocalNode::new();
+        let alice = node.new_account("alicepwd");
+        let bob = node.new_account("bobpwd");
+        let acl = LocalAcl::in_memory();
+        let store = CryptoSecretStore::with_node(
+            &format!("{:x}", alice),
+            StaticPassword::new("alicepwd"),
+            acl.clone(),
+            ReferenceKeyServer::new(3, &acl),
+            node,
+            &Config::default(),
+        )
+        .unwrap();
+        let other = store
+            .as_identity(&format!("{:x}", bob), StaticPassword::new("bobpwd"))
+            .unwrap();
+        assert!(store
+            .as_identity(&format!("{:x}", bob), StaticPassword::new("alicepwd"))
+            .is_err());
+
+        let id = DocumentId::Raw("local node".into());
+        let ciphertext = store.encrypt(&id, "Hello world", 1).unwrap();
+        assert_ne!(ciphertext, "Hello world");
+        store.allow_access(&id, &[alice]).unwrap();
+        assert_eq!(store.decrypt(&id, &ciphertext).unwrap(), "Hello world");
+        assert!(other.decrypt(&id, &ciphertext).is_err());
+        store.allow_access(&id, &[bob]).unwrap();
+        assert_eq!(other.decrypt(&id, &ciphertext).unwrap(), "Hello world");
+
+        let documents = vec![
+            (DocumentId::Raw("local node 1".into()), "first"),
+            (DocumentId::Raw("local node 2".into()), "second"),
+        ];
+        let encrypted: Vec<String> = store
+            .encrypt_many(&documents, 1)
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        let ids: Vec<DocumentId> = documents.iter().map(|(id, _)| id.clone()).collect();
+        /* LocalAcl has no batch transactions, every document gets its own */
+        let results = store.allow_access_batch(&ids, &[alice]).unwrap();
+        assert_eq!(
+            results
+                .iter()
+                .map(|chunk| (chunk.documents.clone(), chunk.result.is_ok()))
+                .collect::<Vec<_>>(),
+            vec![(0..1, true), (1..2, true)]
+        );
+        let encrypted: Vec<(DocumentId, &str)> = documents
+            .iter()
+            .zip(&encrypted)
+            .map(|((id, _), ciphertext)| (id.clone(), ciphertext.as_str()))
+            .collect();
+        let decrypted: Vec<String> = store
+            .decrypt_many(&encrypted)
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(decrypted, vec!["first", "second"]);
+    }
+
+    #[test]
+    fn rekey_as_member() {
+        let node = LocalNode::new();
+        let alice = node.new_account("alicepwd");
+        let bob = node.new_account("bobpwd");
+        let carol = H160::random();
+        let acl = LocalAcl::in_memory();
+        let store = CryptoSecretStore::with_node(
+            &format!("{:x}", alice),
+            StaticPassword::new("alicepwd"),
+            acl.clone(),
+            ReferenceKeyServer::new(3, &acl),
+            node,
+            &Config::default(),
+        )
+        .unwrap();
+        let member = store
+            .as_identity(&format!("{:x}", bob), StaticPassword::new("bobpwd"))
+            .unwrap();
+
+        let id = DocumentId::random();
+        let ciphertext = store.encrypt(&id, "Hello world", 1).unwrap();
+        store.allow_access(&id, &[bob, carol]).unwrap();
+
+        let rekeyed = member.rekey(&id, &ciphertext, 1, &[carol]).unwrap();
+        assert_eq!(member.owner_of(&rekeyed.id).unwrap(), bob);
+        /* The owner of the old document keeps access, only the owner can revoke */
+        assert_eq!(member.members_of(&rekeyed.id).unwrap(), vec![bob, alice]);
+        assert_eq!(
+            store.decrypt(&rekeyed.id, &rekeyed.ciphertext).unwrap(),
+            "Hello world"
+        );
+        assert!(store.members_of(&id).unwrap().contains(&carol));
+    }
+
+    #[test]
+    fn relayed_permit() {
+        let address = "27d39a0fe767025e7ea0f78dccd4665929e3a8f2";
+        let password = "alicepwd";
+        let store = CryptoSecretStore::new(address, password);
+
+        let document: String = thread_rng().sample_iter(&Alphanumeric).take(30).collect();
+        let id = store.generate_id(&document);
+        let test_addr = H160::random();
+        let deadline = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 3600;
+        let permit = store.sign_permit(&id, &[test_addr], deadline).unwrap();
+        assert_eq!(permit.permit.owner, H160::from_str(address).unwrap());
+        let result = store.check_permissions(test_addr, &id).unwrap();
+        assert_eq!(result.is_allowed(), false);
+        let result = store.submit_permit(&permit).unwrap();
+        assert_eq!(result.is_receipt(), true);
+        let result = store.check_permissions(test_addr, &id).unwrap();
+        assert_eq!(result.is_allowed(), true);
+        // A permit can only be used once
+        assert_eq!(store.submit_permit(&permit).is_err(), true);
     }
 
     #[test]
     fn time_access_controll() {
         let address = "27d39a0fe767025e7ea0f78dccd4665929e3a8f2";
         let password = "alicepwd";
-        let mut store = CryptoSecretStore::new(address, password);
+        let store = CryptoSecretStore::new(address, password);
         let mut file = OpenOptions::new()
             .write(true)
             .create(true)
@@ -427,7 +1792,7 @@ mod tests {
             for i in 0..5 {
                 for addr in &test_addresses {
                     let result = store.check_permissions(addr.clone(), &test_ids[i]).unwrap();
-                    assert_eq!(result, true);
+                    assert_eq!(result.is_allowed(), true);
                 }
             }
             let durtation_get_access = now.elapsed() / 5;
@@ -453,7 +1818,7 @@ mod tests {
     }
 
     fn encrypt(store: Rc<RefCell<CryptoSecretStore>>, size: u32, threshold: u32) -> Message {
-        let mut store = store.borrow_mut();
+        let store = store.borrow();
         let document: String = thread_rng()
             .sample_iter(&Alphanumeric)
             .take(size.try_into().unwrap())
@@ -469,7 +1834,7 @@ mod tests {
     }
 
     fn decrypt(store: Rc<RefCell<CryptoSecretStore>>, message: Message) {
-        let mut store = store.borrow_mut();
+        let store = store.borrow();
         let cleartext = store.decrypt(&message.id, &message.ciphertext);
         println!("Whats now the error {:?}", cleartext);
         let cleartext = if !cleartext.is_ok() {
@@ -518,7 +1883,7 @@ mod tests {
                 {
                     let address = H160::from_str(address).unwrap();
                 let addresses = vec![address];
-                    let mut store = store.borrow_mut();
+                    let store = store.borrow();
                     let result = store.allow_access(&message.id, &addresses).unwrap();
                     assert_eq!(result, true);
                 }
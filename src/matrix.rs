@@ -0,0 +1,225 @@
+use crate::id::{DocumentId, Nonce, RoomSecret};
+use primitive_types::H160;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::convert::TryInto;
+
+/// Identifier of the Secret Store algorithm in `m.room.encrypted` events
+pub const ALGORITHM: &str = "net.sparber.secretstore.v1";
+pub const ENCRYPTED_EVENT_TYPE: &str = "m.room.encrypted";
+
+#[derive(Debug)]
+pub enum Error {
+    UnknownAlgorithm(String),
+    RoomMismatch {
+        expected: String,
+        found: String,
+    },
+    /// The document ID of the event wasn't derived from its payload
+    DocumentMismatch {
+        expected: DocumentId,
+        found: DocumentId,
+    },
+    InvalidNonce,
+    Json(serde_json::Error),
+}
+
+impl std::error::Error for Error {}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Json(error)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::UnknownAlgorithm(algorithm) => write!(f, "Unknown algorithm {}.", algorithm),
+            Error::RoomMismatch { expected, found } => write!(
+                f,
+                "Event was encrypted for room {} but received in room {}.",
+                found, expected
+            ),
+            Error::DocumentMismatch { expected, found } => write!(
+                f,
+                "Event has document ID {} but its payload belongs to {}.",
+                found, expected
+            ),
+            Error::InvalidNonce => write!(f, "Invalid nonce in the payload."),
+            Error::Json(error) => write!(f, "Json: {}", error),
+        }
+    }
+}
+
+/// A cleartext Matrix event, only `type` and `content` are encrypted.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Event {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub content: Value,
+}
+
+/// Content of an `m.room.encrypted` event using the Secret Store algorithm.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EncryptedEventContent {
+    pub algorithm: String,
+    pub ciphertext: String,
+    pub document_key_id: DocumentId,
+    pub threshold: u32,
+    pub sender_address: H160,
+}
+
+/* The room and the transaction the ciphertext belongs to are part of the encrypted payload,
+ * so the ciphertext can't be replayed into another room. The event ID is assigned by the
+ * homeserver after sending, the transaction ID is chosen by the client before. The nonce
+ * the document ID was derived with is hex */
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Payload {
+    #[serde(rename = "type")]
+    event_type: String,
+    content: Value,
+    room_id: String,
+    transaction_id: String,
+    nonce: String,
+}
+
+/// A decrypted event and the transaction ID it was sent with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Opened {
+    pub event: Event,
+    pub transaction_id: String,
+}
+
+impl EncryptedEventContent {
+    pub fn new(
+        ciphertext: String,
+        document_key_id: DocumentId,
+        threshold: u32,
+        sender: H160,
+    ) -> Self {
+        EncryptedEventContent {
+            algorithm: ALGORITHM.into(),
+            ciphertext,
+            document_key_id,
+            threshold,
+            sender_address: sender,
+        }
+    }
+
+    pub fn check_algorithm(&self) -> Result<(), Error> {
+        if self.algorithm != ALGORITHM {
+            return Err(Error::UnknownAlgorithm(self.algorithm.clone()));
+        }
+        Ok(())
+    }
+}
+
+/* Serialize the event together with the room, transaction ID and nonce */
+pub fn seal_payload(
+    room_id: &str,
+    transaction_id: &str,
+    nonce: &Nonce,
+    event: &Event,
+) -> Result<String, Error> {
+    Ok(serde_json::to_string(&Payload {
+        event_type: event.event_type.clone(),
+        content: event.content.clone(),
+        room_id: room_id.into(),
+        transaction_id: transaction_id.into(),
+        nonce: hex::encode(nonce),
+    })?)
+}
+
+/* Parse a decrypted payload and make sure it belongs to the room it was received in and the
+ * document ID was derived from it. A ciphertext replayed within the room keeps its document
+ * ID, receivers drop events with an ID they have seen before */
+pub fn open_payload(
+    room_secret: &RoomSecret,
+    room_id: &str,
+    document_id: &DocumentId,
+    payload: &str,
+) -> Result<Opened, Error> {
+    let payload: Payload = serde_json::from_str(payload)?;
+    if payload.room_id != room_id {
+        return Err(Error::RoomMismatch {
+            expected: room_id.into(),
+            found: payload.room_id,
+        });
+    }
+    let nonce: Nonce = hex::decode(&payload.nonce)
+        .ok()
+        .and_then(|nonce| nonce.as_slice().try_into().ok())
+        .ok_or(Error::InvalidNonce)?;
+    let expected =
+        DocumentId::derive_with_nonce(room_secret, room_id, &payload.transaction_id, &nonce);
+    if &expected != document_id {
+        return Err(Error::DocumentMismatch {
+            expected,
+            found: document_id.clone(),
+        });
+    }
+    Ok(Opened {
+        event: Event {
+            event_type: payload.event_type,
+            content: payload.content,
+        },
+        transaction_id: payload.transaction_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn event() -> Event {
+        Event {
+            event_type: "m.room.message".into(),
+            content: json!({"msgtype": "m.text", "body": "Hello World"}),
+        }
+    }
+
+    fn seal(secret: &RoomSecret) -> (DocumentId, String) {
+        let (id, nonce) = DocumentId::derive(secret, "!room:example.org", "txn1");
+        let payload = seal_payload("!room:example.org", "txn1", &nonce, &event()).unwrap();
+        (id, payload)
+    }
+
+    #[test]
+    fn payload_roundtrip() {
+        let secret = RoomSecret::generate();
+        let (id, payload) = seal(&secret);
+        let opened = open_payload(&secret, "!room:example.org", &id, &payload).unwrap();
+        assert_eq!(opened.event, event());
+        assert_eq!(opened.transaction_id, "txn1");
+    }
+
+    #[test]
+    fn payload_replay() {
+        let secret = RoomSecret::generate();
+        let (id, payload) = seal(&secret);
+        let result = open_payload(&secret, "!other:example.org", &id, &payload);
+        assert!(matches!(result, Err(Error::RoomMismatch { .. })));
+
+        /* A ciphertext under another document ID */
+        let (other, _) = seal(&secret);
+        let result = open_payload(&secret, "!room:example.org", &other, &payload);
+        assert!(matches!(result, Err(Error::DocumentMismatch { .. })));
+    }
+
+    #[test]
+    fn encrypted_content_format() {
+        let content = EncryptedEventContent::new(
+            "0x00".into(),
+            DocumentId::Raw("abc".into()),
+            1,
+            H160::zero(),
+        );
+        let json = serde_json::to_value(&content).unwrap();
+        assert_eq!(json["algorithm"], ALGORITHM);
+        assert_eq!(json["document_key_id"], "abc");
+        let content: EncryptedEventContent = serde_json::from_value(json).unwrap();
+        assert!(content.check_algorithm().is_ok());
+    }
+}
@@ -0,0 +1,352 @@
+/* Provisioning of a Secret Store network, replaces the sed based network/start.sh.
+ *
+ * The network key and the Secret Store key of every node are generated here, so the
+ * enodes and the Secret Store node list are known without starting parity. */
+use crate::config::Config;
+use failure::{format_err, Error};
+use primitive_types::H160;
+use secp256k1::{PublicKey, SecretKey};
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions, Permissions};
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+pub const SPEC_FILE: &str = "spec.toml";
+pub const MANIFEST_FILE: &str = "network.toml";
+
+/// Where the nodes of the network run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Layout {
+    /// All nodes on this machine, every node gets its own ports
+    Local,
+    /// One node per host, all nodes use the same ports
+    Remote(Vec<String>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NodeSpec {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub ss_port: u16,
+    /// Only one node exposes the Secret Store HTTP API
+    pub http: bool,
+    pub password: String,
+    pub node_key: String,
+    pub ss_secret: String,
+}
+
+/// Everything needed to render the node configs, stored in spec.toml so the configs can be
+/// rendered again, e.g. to set the ACL contract.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NetworkSpec {
+    pub http_port: u16,
+    pub acl_contract: Option<H160>,
+    pub nodes: Vec<NodeSpec>,
+}
+
+/* Typed version of network/ss-template.toml */
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ParityConfig {
+    pub parity: ParitySection,
+    pub ui: DisableSection,
+    pub rpc: DisableSection,
+    pub ipc: DisableSection,
+    pub websockets: DisableSection,
+    pub network: NetworkSection,
+    pub snapshots: SnapshotsSection,
+    pub account: AccountSection,
+    pub secretstore: SecretStoreSection,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ParitySection {
+    pub chain: String,
+    pub base_path: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct DisableSection {
+    pub disable: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct NetworkSection {
+    pub port: u16,
+    pub node_key: String,
+    pub bootnodes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct SnapshotsSection {
+    pub disable_periodic: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct AccountSection {
+    pub password: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct SecretStoreSection {
+    pub self_secret: String,
+    pub disable: bool,
+    pub disable_http: bool,
+    pub http_interface: String,
+    pub http_port: u16,
+    pub server_set_contract: String,
+    pub interface: String,
+    pub port: u16,
+    pub path: String,
+    pub acl_contract: String,
+    pub nodes: Vec<String>,
+}
+
+impl NodeSpec {
+    pub fn public_key(secret: &str) -> Result<String, Error> {
+        let secret = hex::decode(secret)?;
+        let secret =
+            SecretKey::parse_slice(&secret).map_err(|_| format_err!("Invalid secret key"))?;
+        /* Drop the 0x04 prefix of the uncompressed key */
+        Ok(hex::encode(
+            &PublicKey::from_secret_key(&secret).serialize()[1..],
+        ))
+    }
+
+    pub fn enode(&self) -> Result<String, Error> {
+        Ok(format!(
+            "enode://{}@{}:{}",
+            NodeSpec::public_key(&self.node_key)?,
+            self.host,
+            self.port
+        ))
+    }
+
+    pub fn ss_node(&self) -> Result<String, Error> {
+        Ok(format!(
+            "{}@{}:{}",
+            NodeSpec::public_key(&self.ss_secret)?,
+            self.host,
+            self.ss_port
+        ))
+    }
+}
+
+impl NetworkSpec {
+    pub fn generate(size: usize, layout: &Layout) -> Result<NetworkSpec, Error> {
+        if let Layout::Remote(hosts) = layout {
+            if hosts.len() < size {
+                return Err(format_err!(
+                    "{} nodes requested but only {} hosts are available",
+                    size,
+                    hosts.len()
+                ));
+            }
+        }
+
+        let nodes = (1..=size)
+            .map(|i| {
+                let (host, port, ss_port) = match layout {
+                    Layout::Local => ("127.0.0.1".to_string(), 30300 + i as u16, 8010 + i as u16),
+                    Layout::Remote(hosts) => (hosts[i - 1].clone(), 30301, 8011),
+                };
+                NodeSpec {
+                    name: format!("ss{}", i),
+                    host,
+                    port,
+                    ss_port,
+                    http: i == 1,
+                    password: hex::encode(rand::random::<[u8; 16]>()),
+                    node_key: random_secret(),
+                    ss_secret: random_secret(),
+                }
+            })
+            .collect();
+
+        Ok(NetworkSpec {
+            http_port: 8010,
+            acl_contract: None,
+            nodes,
+        })
+    }
+
+    pub fn render(&self, index: usize) -> Result<ParityConfig, Error> {
+        let node = self.nodes.get(index).ok_or_else(|| {
+            format_err!("No node {}, the network has {}", index, self.nodes.len())
+        })?;
+        let bootnodes = self
+            .nodes
+            .iter()
+            .map(NodeSpec::enode)
+            .collect::<Result<_, _>>()?;
+        let ss_nodes = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != index)
+            .map(|(_, node)| node.ss_node())
+            .collect::<Result<_, _>>()?;
+
+        Ok(ParityConfig {
+            parity: ParitySection {
+                chain: "dev".into(),
+                base_path: format!("db.{}", node.name),
+            },
+            ui: DisableSection { disable: true },
+            rpc: DisableSection { disable: true },
+            ipc: DisableSection { disable: true },
+            websockets: DisableSection { disable: true },
+            network: NetworkSection {
+                port: node.port,
+                node_key: node.node_key.clone(),
+                bootnodes,
+            },
+            snapshots: SnapshotsSection {
+                disable_periodic: true,
+            },
+            account: AccountSection {
+                password: vec![format!("{}.pwd", node.name)],
+            },
+            secretstore: SecretStoreSection {
+                self_secret: node.ss_secret.clone(),
+                disable: false,
+                disable_http: !node.http,
+                http_interface: "local".into(),
+                http_port: self.http_port,
+                server_set_contract: "none".into(),
+                interface: "all".into(),
+                port: node.ss_port,
+                path: format!("db.{}/secretstore", node.name),
+                acl_contract: self
+                    .acl_contract
+                    .map(|address| format!("{:x}", address))
+                    .unwrap_or_else(|| "none".into()),
+                nodes: ss_nodes,
+            },
+        })
+    }
+
+    /* The manifest can be loaded with Config::from_file */
+    pub fn manifest(&self) -> Result<Config, Error> {
+        let http_node = self
+            .nodes
+            .iter()
+            .find(|node| node.http)
+            .ok_or_else(|| format_err!("No node exposes the Secret Store HTTP API"))?;
+        Ok(Config {
+            shadow_url: format!("http://{}:{}", http_node.host, self.http_port),
+            nodes: self
+                .nodes
+                .iter()
+                .map(NodeSpec::ss_node)
+                .collect::<Result<_, _>>()?,
+            ..Config::default()
+        })
+    }
+
+    /* Writes ssN.toml and ssN.pwd for every node, the spec and the manifest. Everything but
+     * the manifest contains keys or passwords and is only readable by the owner */
+    pub fn write<P: AsRef<Path>>(&self, dir: P) -> Result<(), Error> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        for (i, node) in self.nodes.iter().enumerate() {
+            let config = toml::to_string(&self.render(i)?)?;
+            write_private(&dir.join(format!("{}.toml", node.name)), &config)?;
+            write_private(&dir.join(format!("{}.pwd", node.name)), &node.password)?;
+        }
+        write_private(&dir.join(SPEC_FILE), &toml::to_string(self)?)?;
+        fs::write(dir.join(MANIFEST_FILE), toml::to_string(&self.manifest()?)?)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<NetworkSpec, Error> {
+        let contents = fs::read_to_string(dir.as_ref().join(SPEC_FILE))?;
+        Ok(toml::from_str(&contents)?)
+    }
+}
+
+/* The mode only applies to new files, existing ones are restricted before they're written */
+fn write_private(path: &Path, contents: &str) -> Result<(), Error> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .mode(0o600)
+        .open(path)?;
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    file.set_len(0)?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+fn random_secret() -> String {
+    loop {
+        let secret: [u8; 32] = rand::random();
+        if SecretKey::parse(&secret).is_ok() {
+            return hex::encode(secret);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_local_network() {
+        let dir = tempfile::tempdir().unwrap();
+        let spec = NetworkSpec::generate(3, &Layout::Local).unwrap();
+        spec.write(dir.path()).unwrap();
+
+        for i in 1..=3 {
+            assert!(dir.path().join(format!("ss{}.pwd", i)).exists());
+            let config = fs::read_to_string(dir.path().join(format!("ss{}.toml", i))).unwrap();
+            let config: ParityConfig = toml::from_str(&config).unwrap();
+            assert_eq!(config.network.port, 30300 + i as u16);
+            assert_eq!(config.network.bootnodes.len(), 3);
+            assert_eq!(config.secretstore.nodes.len(), 2);
+            assert_eq!(config.secretstore.disable_http, i != 1);
+            assert!(!config
+                .secretstore
+                .nodes
+                .contains(&spec.nodes[i - 1].ss_node().unwrap()));
+        }
+
+        for file in &["ss1.pwd", "ss1.toml", SPEC_FILE] {
+            let mode = fs::metadata(dir.path().join(file))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert_ne!(spec.nodes[0].password, spec.nodes[1].password);
+
+        let manifest = Config::from_file(dir.path().join(MANIFEST_FILE)).unwrap();
+        assert_eq!(manifest.shadow_url, "http://127.0.0.1:8010");
+        assert_eq!(manifest.nodes.len(), 3);
+        assert_eq!(NetworkSpec::load(dir.path()).unwrap(), spec);
+    }
+
+    #[test]
+    fn render_acl_contract() {
+        let mut spec = NetworkSpec::generate(2, &Layout::Local).unwrap();
+        assert_eq!(spec.render(0).unwrap().secretstore.acl_contract, "none");
+        spec.acl_contract = Some(H160::repeat_byte(0xab));
+        assert_eq!(
+            spec.render(1).unwrap().secretstore.acl_contract,
+            "abababababababababababababababababababab"
+        );
+        assert!(spec.render(2).is_err());
+    }
+
+    #[test]
+    fn remote_layout_needs_hosts() {
+        let hosts = vec!["a.example.org".to_string(), "b.example.org".to_string()];
+        assert!(NetworkSpec::generate(3, &Layout::Remote(hosts.clone())).is_err());
+        let spec = NetworkSpec::generate(2, &Layout::Remote(hosts)).unwrap();
+        assert!(spec.nodes[1]
+            .enode()
+            .unwrap()
+            .ends_with("@b.example.org:30301"));
+    }
+}
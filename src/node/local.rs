@@ -0,0 +1,151 @@
+/* Node for tests which keeps the account keys in memory and does the secretstore_* calls in
+ * process, with the same ECIES and document encryption as the node. Together with
+ * ReferenceKeyServer and LocalAcl documents are encrypted and decrypted without any node
+ * running. Never use it for real data. */
+use super::Node;
+use crate::blockchain::{DecryptionKeys, EncryptedDocumentKey};
+use crate::keyserver::reference::{
+    ecies_decrypt, ecies_encrypt, generate_document_key, shadow_decrypt,
+};
+use crate::permit::keccak256;
+use crate::secret::SecretString;
+use crate::Data;
+use crate::Password;
+use aes_ctr::stream_cipher::generic_array::GenericArray;
+use aes_ctr::stream_cipher::{NewStreamCipher, SyncStreamCipher};
+use aes_ctr::Aes128Ctr;
+use async_trait::async_trait;
+use failure::{format_err, Error};
+use primitive_types::{H160, H256, H512};
+use secp256k1::{Message, PublicKey, SecretKey};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Accounts kept in memory, see `LocalNode::new_account`.
+#[derive(Default)]
+pub struct LocalNode {
+    accounts: Mutex<HashMap<H160, (SecretString, SecretKey)>>,
+}
+
+impl LocalNode {
+    pub fn new() -> LocalNode {
+        LocalNode::default()
+    }
+
+    /* Account with a random key, like personal_newAccount */
+    pub fn new_account(&self, password: &str) -> H160 {
+        loop {
+            if let Ok(address) = self.add_account(&rand::random(), password) {
+                return address;
+            }
+        }
+    }
+
+    /* Account with a known key, so the address stays the same between runs */
+    pub fn add_account(&self, secret: &[u8; 32], password: &str) -> Result<H160, Error> {
+        let secret = SecretKey::parse(secret).map_err(|_| format_err!("Invalid secret key"))?;
+        let public = PublicKey::from_secret_key(&secret);
+        let address = H160::from_slice(&keccak256(&public.serialize()[1..])[12..]);
+        self.accounts
+            .lock()
+            .unwrap()
+            .insert(address, (password.into(), secret));
+        Ok(address)
+    }
+
+    fn secret(&self, address: H160, password: &Password) -> Result<SecretKey, Error> {
+        match self.accounts.lock().unwrap().get(&address) {
+            Some((expected, secret)) if expected.expose() == password => Ok(secret.clone()),
+            _ => Err(format_err!("Invalid credentials for account {:?}", address)),
+        }
+    }
+}
+
+/* The node only uses the first 16 bytes of the document key, the IV is appended to the
+ * ciphertext */
+fn apply_document_key(key: &[u8], iv: &[u8], data: &mut [u8]) -> Result<(), Error> {
+    if key.len() != 64 {
+        return Err(format_err!("Invalid document key"));
+    }
+    Aes128Ctr::new(
+        GenericArray::from_slice(&key[..16]),
+        GenericArray::from_slice(iv),
+    )
+    .apply_keystream(data);
+    Ok(())
+}
+
+fn decode(data: &str) -> Result<Vec<u8>, Error> {
+    Ok(hex::decode(data.trim_start_matches("0x"))?)
+}
+
+#[async_trait(?Send)]
+impl Node for LocalNode {
+    async fn sign_raw_hash(
+        &self,
+        address: H160,
+        password: &Password,
+        hash: H256,
+    ) -> Result<Data, Error> {
+        let secret = self.secret(address, password)?;
+        let (signature, recovery) =
+            secp256k1::sign(&Message::parse(hash.as_fixed_bytes()), &secret)
+                .map_err(|_| format_err!("Signing failed"))?;
+        let mut bytes = signature.serialize().to_vec();
+        bytes.push(recovery.serialize());
+        Ok(format!("0x{}", hex::encode(bytes)))
+    }
+
+    async fn generate_document_key(
+        &self,
+        address: H160,
+        password: &Password,
+        server_key: H512,
+    ) -> Result<EncryptedDocumentKey, Error> {
+        let public = PublicKey::from_secret_key(&self.secret(address, password)?);
+        let (common_point, encrypted_point, document_key) = generate_document_key(server_key)?;
+        Ok(EncryptedDocumentKey {
+            common_point,
+            encrypted_point,
+            encrypted_key: format!(
+                "0x{}",
+                hex::encode(ecies_encrypt(&public, document_key.as_bytes())?)
+            ),
+        })
+    }
+
+    async fn encrypt(
+        &self,
+        address: H160,
+        password: &Password,
+        encrypted_key: Data,
+        data: Data,
+    ) -> Result<Data, Error> {
+        let secret = self.secret(address, password)?;
+        let key = ecies_decrypt(&secret, &decode(&encrypted_key)?)?;
+        let iv: [u8; 16] = rand::random();
+        let mut document = decode(&data)?;
+        apply_document_key(&key, &iv, &mut document)?;
+        document.extend_from_slice(&iv);
+        Ok(format!("0x{}", hex::encode(document)))
+    }
+
+    async fn shadow_decrypt(
+        &self,
+        address: H160,
+        password: &Password,
+        keys: DecryptionKeys,
+        data: &Data,
+    ) -> Result<Data, Error> {
+        let secret = self.secret(address, password)?;
+        let key = shadow_decrypt(&secret, &keys)?;
+        let data = decode(data)?;
+        if data.len() < 16 {
+            return Err(format_err!("Invalid encrypted document"));
+        }
+        let (ciphertext, iv) = data.split_at(data.len() - 16);
+        let mut document = ciphertext.to_vec();
+        apply_document_key(key.as_bytes(), iv, &mut document)?;
+        Ok(format!("0x{}", hex::encode(document)))
+    }
+}
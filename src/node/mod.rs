@@ -0,0 +1,352 @@
+/* Account side of the Secret Store protocol: signing document key IDs and encrypting and
+ * decrypting with document keys needs the account's key. The node does this over its
+ * secretstore_* JSON-RPC API (RpcNode), LocalNode keeps keys in memory for tests. */
+pub mod local;
+
+use crate::api::transport::{self, RpcTransport};
+use crate::blockchain::{DecryptionKeys, EncryptedDocumentKey};
+use crate::Data;
+use crate::Password;
+use async_trait::async_trait;
+use failure::{format_err, Error};
+use primitive_types::{H160, H256, H512};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+/* JSON-RPC error codes of the node for a locked or unknown account and a wrong password */
+const ACCOUNT_ERRORS: [i64; 3] = [-32020, -32021, -32023];
+
+#[async_trait(?Send)]
+pub trait Node {
+    /* Signature of hash with the account key, 65 bytes as hex */
+    async fn sign_raw_hash(
+        &self,
+        address: H160,
+        password: &Password,
+        hash: H256,
+    ) -> Result<Data, Error>;
+
+    /* New document key for a server key, encrypted with it and with the account key */
+    async fn generate_document_key(
+        &self,
+        address: H160,
+        password: &Password,
+        server_key: H512,
+    ) -> Result<EncryptedDocumentKey, Error>;
+
+    /* Encrypt data (hex) with a document key encrypted for the account */
+    async fn encrypt(
+        &self,
+        address: H160,
+        password: &Password,
+        encrypted_key: Data,
+        data: Data,
+    ) -> Result<Data, Error>;
+
+    /* Put the document key together from a shadow retrieval and decrypt data with it,
+     * returns the document as hex */
+    async fn shadow_decrypt(
+        &self,
+        address: H160,
+        password: &Password,
+        keys: DecryptionKeys,
+        data: &Data,
+    ) -> Result<Data, Error>;
+
+    /* Make sure the password belongs to the address */
+    async fn verify_credentials(&self, address: H160, password: &Password) -> Result<(), Error> {
+        self.sign_raw_hash(address, password, H256::zero())
+            .await
+            .map_err(|_| format_err!("Invalid credentials for account {:?}", address))?;
+        Ok(())
+    }
+
+    /* The batch versions return a result at the position of every input, inputs which are
+     * errors already are passed through */
+    async fn sign_raw_hashes(
+        &self,
+        address: H160,
+        password: &Password,
+        hashes: Vec<Result<H256, Error>>,
+    ) -> Result<Vec<Result<Data, Error>>, Error> {
+        let mut signatures = Vec::new();
+        for hash in hashes {
+            signatures.push(match hash {
+                Ok(hash) => self.sign_raw_hash(address, password, hash).await,
+                Err(error) => Err(error),
+            });
+        }
+        Ok(signatures)
+    }
+
+    async fn generate_document_keys(
+        &self,
+        address: H160,
+        password: &Password,
+        server_keys: Vec<Result<H512, Error>>,
+    ) -> Result<Vec<Result<EncryptedDocumentKey, Error>>, Error> {
+        let mut keys = Vec::new();
+        for server_key in server_keys {
+            keys.push(match server_key {
+                Ok(server_key) => {
+                    self.generate_document_key(address, password, server_key)
+                        .await
+                }
+                Err(error) => Err(error),
+            });
+        }
+        Ok(keys)
+    }
+
+    async fn encrypt_many(
+        &self,
+        address: H160,
+        password: &Password,
+        documents: Vec<Result<(Data, Data), Error>>,
+    ) -> Result<Vec<Result<Data, Error>>, Error> {
+        let mut encrypted = Vec::new();
+        for document in documents {
+            encrypted.push(match document {
+                Ok((key, data)) => self.encrypt(address, password, key, data).await,
+                Err(error) => Err(error),
+            });
+        }
+        Ok(encrypted)
+    }
+
+    async fn shadow_decrypt_many(
+        &self,
+        address: H160,
+        password: &Password,
+        documents: Vec<Result<(DecryptionKeys, Data), Error>>,
+    ) -> Result<Vec<Result<Data, Error>>, Error> {
+        let mut decrypted = Vec::new();
+        for document in documents {
+            decrypted.push(match document {
+                Ok((keys, data)) => self.shadow_decrypt(address, password, keys, &data).await,
+                Err(error) => Err(error),
+            });
+        }
+        Ok(decrypted)
+    }
+}
+
+/// The node's secretstore_* JSON-RPC API, the batch calls are single JSON-RPC batches.
+pub struct RpcNode {
+    rpc: RpcTransport,
+}
+
+impl RpcNode {
+    pub fn new(rpc: RpcTransport) -> RpcNode {
+        RpcNode { rpc }
+    }
+
+    /* One batch with a call of method for every params which isn't an error yet, the
+     * errors are passed through at their position */
+    async fn call_many<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Vec<Result<Value, Error>>,
+    ) -> Result<Vec<Result<T, Error>>, Error> {
+        let mut calls = Vec::new();
+        let mut failed = Vec::new();
+        for params in params {
+            match params {
+                Ok(Value::Array(params)) => {
+                    calls.push((method, params));
+                    failed.push(None);
+                }
+                Ok(params) => {
+                    calls.push((method, vec![params]));
+                    failed.push(None);
+                }
+                Err(error) => failed.push(Some(error)),
+            }
+        }
+
+        let mut responses = self.rpc.batch(calls).await?.into_iter();
+        Ok(failed
+            .into_iter()
+            .map(|error| match error {
+                Some(error) => Err(error),
+                None => match responses.next() {
+                    Some(Ok(value)) => Ok(serde_json::from_value(value)?),
+                    Some(Err(error)) => Err(error.into()),
+                    None => Err(format_err!("Missing response of {}", method)),
+                },
+            })
+            .collect())
+    }
+}
+
+#[async_trait(?Send)]
+impl Node for RpcNode {
+    async fn sign_raw_hash(
+        &self,
+        address: H160,
+        password: &Password,
+        hash: H256,
+    ) -> Result<Data, Error> {
+        Ok(self
+            .rpc
+            .call(
+                "secretstore_signRawHash",
+                vec![json!(address), json!(password), json!(hash)],
+            )
+            .await?)
+    }
+
+    async fn generate_document_key(
+        &self,
+        address: H160,
+        password: &Password,
+        server_key: H512,
+    ) -> Result<EncryptedDocumentKey, Error> {
+        Ok(self
+            .rpc
+            .call(
+                "secretstore_generateDocumentKey",
+                vec![json!(address), json!(password), json!(server_key)],
+            )
+            .await?)
+    }
+
+    async fn encrypt(
+        &self,
+        address: H160,
+        password: &Password,
+        encrypted_key: Data,
+        data: Data,
+    ) -> Result<Data, Error> {
+        Ok(self
+            .rpc
+            .call(
+                "secretstore_encrypt",
+                vec![
+                    json!(address),
+                    json!(password),
+                    json!(encrypted_key),
+                    json!(data),
+                ],
+            )
+            .await?)
+    }
+
+    async fn shadow_decrypt(
+        &self,
+        address: H160,
+        password: &Password,
+        keys: DecryptionKeys,
+        data: &Data,
+    ) -> Result<Data, Error> {
+        Ok(self
+            .rpc
+            .call(
+                "secretstore_shadowDecrypt",
+                vec![
+                    json!(address),
+                    json!(password),
+                    json!(keys.decrypted_secret),
+                    json!(keys.common_point),
+                    json!(keys.decrypt_shadows),
+                    json!(data),
+                ],
+            )
+            .await?)
+    }
+
+    /* Only account errors mean wrong credentials, anything else is passed on */
+    async fn verify_credentials(&self, address: H160, password: &Password) -> Result<(), Error> {
+        match self
+            .rpc
+            .call::<Data>(
+                "secretstore_signRawHash",
+                vec![json!(address), json!(password), json!(H256::zero())],
+            )
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(transport::Error::Rpc { code, .. }) if ACCOUNT_ERRORS.contains(&code) => {
+                Err(format_err!("Invalid credentials for account {:?}", address))
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    async fn sign_raw_hashes(
+        &self,
+        address: H160,
+        password: &Password,
+        hashes: Vec<Result<H256, Error>>,
+    ) -> Result<Vec<Result<Data, Error>>, Error> {
+        self.call_many(
+            "secretstore_signRawHash",
+            hashes
+                .into_iter()
+                .map(|hash| Ok(json!([address, password, hash?])))
+                .collect(),
+        )
+        .await
+    }
+
+    async fn generate_document_keys(
+        &self,
+        address: H160,
+        password: &Password,
+        server_keys: Vec<Result<H512, Error>>,
+    ) -> Result<Vec<Result<EncryptedDocumentKey, Error>>, Error> {
+        self.call_many(
+            "secretstore_generateDocumentKey",
+            server_keys
+                .into_iter()
+                .map(|key| Ok(json!([address, password, key?])))
+                .collect(),
+        )
+        .await
+    }
+
+    async fn encrypt_many(
+        &self,
+        address: H160,
+        password: &Password,
+        documents: Vec<Result<(Data, Data), Error>>,
+    ) -> Result<Vec<Result<Data, Error>>, Error> {
+        self.call_many(
+            "secretstore_encrypt",
+            documents
+                .into_iter()
+                .map(|document| {
+                    let (key, data) = document?;
+                    Ok(json!([address, password, key, data]))
+                })
+                .collect(),
+        )
+        .await
+    }
+
+    async fn shadow_decrypt_many(
+        &self,
+        address: H160,
+        password: &Password,
+        documents: Vec<Result<(DecryptionKeys, Data), Error>>,
+    ) -> Result<Vec<Result<Data, Error>>, Error> {
+        self.call_many(
+            "secretstore_shadowDecrypt",
+            documents
+                .into_iter()
+                .map(|document| {
+                    let (keys, data) = document?;
+                    Ok(json!([
+                        address,
+                        password,
+                        keys.decrypted_secret,
+                        keys.common_point,
+                        keys.decrypt_shadows,
+                        data
+                    ]))
+                })
+                .collect(),
+        )
+        .await
+    }
+}
@@ -0,0 +1,248 @@
+/* Grants and revocations which couldn't be sent yet, kept on disk so they survive a restart
+ * and are replayed in order once the node is reachable again */
+use crate::id::DocumentId;
+use failure::Error;
+use primitive_types::{H160, H256};
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutboxAction {
+    Grant,
+    Revoke,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutboxState {
+    /// Waiting for the node to be reachable
+    Pending,
+    /// Transaction was sent, but isn't mined yet
+    Sent,
+    /// Transaction was mined successfully
+    Confirmed,
+    /// Transaction was rejected, it won't be retried
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct OutboxItem {
+    pub id: u64,
+    pub sender: H160,
+    pub action: OutboxAction,
+    pub document_id: DocumentId,
+    pub addresses: Vec<H160>,
+    pub state: OutboxState,
+    pub transaction: Option<H256>,
+    pub attempts: u32,
+    pub error: Option<String>,
+}
+
+impl OutboxItem {
+    /* Items which still need something from the node */
+    pub fn is_open(&self) -> bool {
+        self.state == OutboxState::Pending || self.state == OutboxState::Sent
+    }
+}
+
+/// An item put into the outbox and the result of sending the open items right away.
+#[derive(Debug)]
+pub struct Queued {
+    pub id: u64,
+    /// Open items after the flush. On an error the item stays in the outbox and is sent by a
+    /// later `flush_outbox`
+    pub flushed: Result<Vec<OutboxItem>, Error>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Contents {
+    next_id: u64,
+    items: Vec<OutboxItem>,
+}
+
+#[derive(Debug)]
+pub struct Outbox {
+    path: Option<PathBuf>,
+    contents: Contents,
+}
+
+impl Outbox {
+    /* Items are lost when the outbox is dropped */
+    pub fn in_memory() -> Outbox {
+        Outbox {
+            path: None,
+            contents: Contents::default(),
+        }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Outbox, Error> {
+        let path = path.as_ref().to_path_buf();
+        let contents = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            Contents::default()
+        };
+        Ok(Outbox {
+            path: Some(path),
+            contents,
+        })
+    }
+
+    /* Queue an action. If the latest open item for the document is the same action for the
+     * same addresses its ID is returned instead, any other item in between could undo it */
+    pub fn push(
+        &mut self,
+        sender: H160,
+        action: OutboxAction,
+        document_id: DocumentId,
+        addresses: &[H160],
+    ) -> Result<u64, Error> {
+        let latest = self.contents.items.iter().rev().find(|item| {
+            item.is_open()
+                && item.sender == sender
+                && item.document_id.hashed() == document_id.hashed()
+        });
+        if let Some(item) = latest {
+            if item.action == action && item.addresses == addresses {
+                return Ok(item.id);
+            }
+        }
+
+        let id = self.contents.next_id;
+        self.contents.next_id += 1;
+        self.contents.items.push(OutboxItem {
+            id,
+            sender,
+            action,
+            document_id,
+            addresses: addresses.into(),
+            state: OutboxState::Pending,
+            transaction: None,
+            attempts: 0,
+            error: None,
+        });
+        self.save()?;
+        Ok(id)
+    }
+
+    pub fn get(&self, id: u64) -> Option<&OutboxItem> {
+        self.contents.items.iter().find(|item| item.id == id)
+    }
+
+    pub fn items(&self) -> &[OutboxItem] {
+        &self.contents.items
+    }
+
+    /* Open items of sender in the order they were queued */
+    pub fn open_items(&self, sender: H160) -> Vec<OutboxItem> {
+        self.contents
+            .items
+            .iter()
+            .filter(|item| item.sender == sender && item.is_open())
+            .cloned()
+            .collect()
+    }
+
+    pub fn update(
+        &mut self,
+        id: u64,
+        state: OutboxState,
+        transaction: Option<H256>,
+        error: Option<String>,
+    ) -> Result<(), Error> {
+        if let Some(item) = self.contents.items.iter_mut().find(|item| item.id == id) {
+            if state != OutboxState::Confirmed {
+                item.attempts += 1;
+            }
+            item.state = state;
+            item.transaction = transaction.or(item.transaction);
+            item.error = error;
+        }
+        self.save()
+    }
+
+    /* Forget items which are done */
+    pub fn clear_finished(&mut self) -> Result<(), Error> {
+        self.contents.items.retain(OutboxItem::is_open);
+        self.save()
+    }
+
+    /* Write to a temporary file first, so a crash never leaves a half written outbox */
+    fn save(&self) -> Result<(), Error> {
+        if let Some(path) = &self.path {
+            let tmp = path.with_extension("tmp");
+            fs::write(&tmp, serde_json::to_string_pretty(&self.contents)?)?;
+            fs::rename(tmp, path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persist_and_deduplicate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("outbox.json");
+        let sender = H160::repeat_byte(1);
+        let users = [H160::repeat_byte(2)];
+
+        let mut outbox = Outbox::open(&path).unwrap();
+        let first = outbox
+            .push(
+                sender,
+                OutboxAction::Grant,
+                DocumentId::Raw("doc".into()),
+                &users,
+            )
+            .unwrap();
+        let second = outbox
+            .push(
+                sender,
+                OutboxAction::Revoke,
+                DocumentId::Raw("doc".into()),
+                &users,
+            )
+            .unwrap();
+        let third = outbox
+            .push(
+                sender,
+                OutboxAction::Grant,
+                DocumentId::Raw("doc".into()),
+                &users,
+            )
+            .unwrap();
+        let again = outbox
+            .push(
+                sender,
+                OutboxAction::Grant,
+                DocumentId::Raw("doc".into()),
+                &users,
+            )
+            .unwrap();
+        /* The revocation in between would undo the first grant */
+        assert_ne!(first, third);
+        assert_ne!(second, third);
+        assert_eq!(third, again);
+
+        let mut outbox = Outbox::open(&path).unwrap();
+        let open = outbox.open_items(sender);
+        assert_eq!(open.len(), 3);
+        assert_eq!(open[0].action, OutboxAction::Grant);
+        assert_eq!(open[1].action, OutboxAction::Revoke);
+        assert_eq!(open[2].action, OutboxAction::Grant);
+        assert!(outbox.open_items(H160::zero()).is_empty());
+
+        outbox
+            .update(first, OutboxState::Confirmed, Some(H256::zero()), None)
+            .unwrap();
+        outbox.clear_finished().unwrap();
+        let outbox = Outbox::open(&path).unwrap();
+        assert!(outbox.get(first).is_none());
+        assert_eq!(outbox.get(second).unwrap().state, OutboxState::Pending);
+    }
+}
@@ -0,0 +1,103 @@
+use failure::{format_err, Error};
+use primitive_types::{H160, H256, U256};
+use serde_derive::{Deserialize, Serialize};
+use tiny_keccak::{Hasher, Keccak};
+
+/* Has to match ALLOW_ACCESS_TYPEHASH in SSPermissions.sol */
+const ALLOW_ACCESS_TYPE: &str =
+    "AllowAccess(bytes32 id,address[] users,address owner,uint256 nonce,uint256 deadline)";
+
+/// An off-chain grant for `allow_access`, following the EIP-712 typed data layout
+/// expected by `SSPermissions.allow_access_with_permit`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Permit {
+    pub document_id: H256,
+    pub users: Vec<H160>,
+    pub owner: H160,
+    pub nonce: U256,
+    pub deadline: U256,
+}
+
+/// A permit together with the owner's signature, ready to be submitted by a relayer.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SignedPermit {
+    pub permit: Permit,
+    pub v: u8,
+    pub r: H256,
+    pub s: H256,
+}
+
+impl Permit {
+    pub fn struct_hash(&self) -> H256 {
+        let mut users = Vec::with_capacity(self.users.len() * 32);
+        for user in &self.users {
+            users.extend_from_slice(&encode_address(user));
+        }
+
+        let mut data = Vec::with_capacity(6 * 32);
+        data.extend_from_slice(&keccak256(ALLOW_ACCESS_TYPE.as_bytes()));
+        data.extend_from_slice(self.document_id.as_bytes());
+        data.extend_from_slice(&keccak256(&users));
+        data.extend_from_slice(&encode_address(&self.owner));
+        data.extend_from_slice(&encode_uint(self.nonce));
+        data.extend_from_slice(&encode_uint(self.deadline));
+        H256::from(keccak256(&data))
+    }
+
+    /// The hash the owner has to sign, `domain_separator` is read from the contract.
+    pub fn digest(&self, domain_separator: H256) -> H256 {
+        let mut data = Vec::with_capacity(2 + 2 * 32);
+        data.extend_from_slice(b"\x19\x01");
+        data.extend_from_slice(domain_separator.as_bytes());
+        data.extend_from_slice(self.struct_hash().as_bytes());
+        H256::from(keccak256(&data))
+    }
+}
+
+impl SignedPermit {
+    /* The node returns the signature as hex encoded r || s || v, where v is the recovery id */
+    pub fn from_signature(permit: Permit, signature: &str) -> Result<Self, Error> {
+        let signature = hex::decode(
+            signature
+                .trim_matches(&['"', '\\'] as &[char])
+                .trim_start_matches("0x"),
+        )?;
+        if signature.len() != 65 {
+            return Err(format_err!(
+                "Invalid signature length {}, expected 65 bytes",
+                signature.len()
+            ));
+        }
+        let v = if signature[64] < 27 {
+            signature[64] + 27
+        } else {
+            signature[64]
+        };
+        Ok(SignedPermit {
+            permit,
+            v,
+            r: H256::from_slice(&signature[0..32]),
+            s: H256::from_slice(&signature[32..64]),
+        })
+    }
+}
+
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Keccak::v256();
+    let mut output = [0u8; 32];
+    hasher.update(data);
+    hasher.finalize(&mut output);
+    output
+}
+
+fn encode_address(address: &H160) -> [u8; 32] {
+    let mut encoded = [0u8; 32];
+    encoded[12..].copy_from_slice(address.as_bytes());
+    encoded
+}
+
+fn encode_uint(value: U256) -> [u8; 32] {
+    let mut encoded = [0u8; 32];
+    value.to_big_endian(&mut encoded);
+    encoded
+}
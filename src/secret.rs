@@ -0,0 +1,103 @@
+use failure::{format_err, Error};
+use primitive_types::H160;
+use std::fmt;
+use zeroize::Zeroize;
+
+/// Wrapper for passwords and keys, the value is zeroed when it's dropped and never shows up
+/// in `Debug` output.
+pub struct Secret<T: Zeroize>(T);
+
+pub type SecretString = Secret<String>;
+
+impl<T: Zeroize> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Secret(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<T: Zeroize + Clone> Clone for Secret<T> {
+    fn clone(&self) -> Self {
+        Secret(self.0.clone())
+    }
+}
+
+impl<T: Zeroize> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret([REDACTED])")
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(value: &str) -> Self {
+        Secret(value.to_string())
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(value: String) -> Self {
+        Secret(value)
+    }
+}
+
+/// Source of the account password, asked every time the password is needed so it doesn't
+/// have to be kept in memory.
+pub trait PasswordProvider: Send + Sync {
+    fn password(&self, address: H160) -> Result<SecretString, Error>;
+}
+
+impl<F> PasswordProvider for F
+where
+    F: Fn(H160) -> Result<SecretString, Error> + Send + Sync,
+{
+    fn password(&self, address: H160) -> Result<SecretString, Error> {
+        self(address)
+    }
+}
+
+/// A password that is known up front.
+pub struct StaticPassword(SecretString);
+
+impl StaticPassword {
+    pub fn new(password: impl Into<SecretString>) -> Self {
+        StaticPassword(password.into())
+    }
+}
+
+impl PasswordProvider for StaticPassword {
+    fn password(&self, _address: H160) -> Result<SecretString, Error> {
+        Ok(self.0.clone())
+    }
+}
+
+/// Asks for the password on the terminal every time it's needed.
+pub struct PromptPassword;
+
+impl PasswordProvider for PromptPassword {
+    fn password(&self, address: H160) -> Result<SecretString, Error> {
+        rpassword::read_password_from_tty(Some(&format!("Password for {:?}: ", address)))
+            .map(Secret::new)
+            .map_err(|error| format_err!("Reading password failed: {}", error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_is_redacted() {
+        let secret = SecretString::from("alicepwd");
+        assert_eq!(format!("{:?}", secret), "Secret([REDACTED])");
+        assert_eq!(secret.expose(), "alicepwd");
+    }
+}
@@ -0,0 +1,424 @@
+/* Framed format for large documents:
+ *
+ * header: MAGIC | chunk size (u32) | nonce prefix (7 bytes) | key length (u32) | wrapped key
+ * frames: ciphertext length (u32) | ciphertext with tag
+ *
+ * The wrapped key is the chunk key encrypted with the Secret Store, so it's the only
+ * thing that goes through the JSON-RPC API. Every chunk is sealed with ChaCha20Poly1305,
+ * the nonce is the nonce prefix, the chunk index and a flag for the last chunk, the header
+ * is used as associated data. All frames except the last one have the same size, therefore
+ * a chunk can be found by seeking without reading the chunks before it. */
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::{thread_rng, Rng};
+use std::convert::TryInto;
+use std::io::SeekFrom;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+pub const MAGIC: &[u8; 4] = b"SSE1";
+pub const DEFAULT_CHUNK_SIZE: u32 = 64 * 1024;
+const TAG_SIZE: usize = 16;
+const NONCE_PREFIX_SIZE: usize = 7;
+/* Upper bounds for the wrapped key and the chunks, so a corrupted header can't make us
+ * allocate gigabytes */
+const MAX_WRAPPED_KEY_SIZE: u32 = 4096;
+pub const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidHeader,
+    InvalidFrame(u64),
+    Authentication(u64),
+    Truncated,
+    Io(std::io::Error),
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::InvalidHeader => write!(f, "Not a valid encrypted stream header."),
+            Error::InvalidFrame(index) => write!(f, "Chunk {} has an invalid size.", index),
+            Error::Authentication(index) => write!(f, "Chunk {} failed authentication.", index),
+            Error::Truncated => write!(f, "Stream ended before the last chunk."),
+            Error::Io(error) => write!(f, "Io: {}", error),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub chunk_size: u32,
+    pub nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+    /// Chunk key encrypted by the Secret Store
+    pub wrapped_key: String,
+}
+
+impl Header {
+    pub fn new(chunk_size: u32, wrapped_key: String) -> Self {
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_SIZE];
+        thread_rng().fill(&mut nonce_prefix);
+        Header {
+            chunk_size,
+            nonce_prefix,
+            wrapped_key,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.encoded_len() as usize);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&self.chunk_size.to_be_bytes());
+        bytes.extend_from_slice(&self.nonce_prefix);
+        bytes.extend_from_slice(&(self.wrapped_key.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(self.wrapped_key.as_bytes());
+        bytes
+    }
+
+    pub fn encoded_len(&self) -> u64 {
+        (MAGIC.len() + 4 + NONCE_PREFIX_SIZE + 4 + self.wrapped_key.len()) as u64
+    }
+
+    pub async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Header, Error> {
+        let mut fixed = [0u8; 4 + 4 + NONCE_PREFIX_SIZE + 4];
+        reader.read_exact(&mut fixed).await?;
+        if &fixed[0..4] != MAGIC {
+            return Err(Error::InvalidHeader);
+        }
+        let chunk_size = u32::from_be_bytes(fixed[4..8].try_into().unwrap());
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_SIZE];
+        nonce_prefix.copy_from_slice(&fixed[8..8 + NONCE_PREFIX_SIZE]);
+        let key_len = u32::from_be_bytes(fixed[8 + NONCE_PREFIX_SIZE..].try_into().unwrap());
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE || key_len > MAX_WRAPPED_KEY_SIZE {
+            return Err(Error::InvalidHeader);
+        }
+        let mut wrapped_key = vec![0u8; key_len as usize];
+        reader.read_exact(&mut wrapped_key).await?;
+        let wrapped_key = String::from_utf8(wrapped_key).map_err(|_| Error::InvalidHeader)?;
+        Ok(Header {
+            chunk_size,
+            nonce_prefix,
+            wrapped_key,
+        })
+    }
+
+    /* Offset of a frame, valid for every chunk since all frames before it are full */
+    pub fn frame_offset(&self, index: u64) -> u64 {
+        self.encoded_len() + index * (4 + self.chunk_size as u64 + TAG_SIZE as u64)
+    }
+
+    fn nonce(&self, index: u64, last: bool) -> Result<[u8; 12], Error> {
+        let index: u32 = index.try_into().map_err(|_| Error::InvalidFrame(index))?;
+        let mut nonce = [0u8; 12];
+        nonce[..NONCE_PREFIX_SIZE].copy_from_slice(&self.nonce_prefix);
+        nonce[NONCE_PREFIX_SIZE..11].copy_from_slice(&index.to_be_bytes());
+        nonce[11] = last as u8;
+        Ok(nonce)
+    }
+}
+
+pub struct ChunkCipher {
+    cipher: ChaCha20Poly1305,
+    header: Header,
+    aad: Vec<u8>,
+}
+
+impl ChunkCipher {
+    pub fn new(key: &[u8; 32], header: Header) -> Self {
+        ChunkCipher {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key)),
+            aad: header.to_bytes(),
+            header,
+        }
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn seal(&self, index: u64, chunk: &[u8], last: bool) -> Result<Vec<u8>, Error> {
+        let nonce = self.header.nonce(index, last)?;
+        self.cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: chunk,
+                    aad: &self.aad,
+                },
+            )
+            .map_err(|_| Error::Authentication(index))
+    }
+
+    pub fn open(&self, index: u64, frame: &[u8], last: bool) -> Result<Vec<u8>, Error> {
+        let nonce = self.header.nonce(index, last)?;
+        self.cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: frame,
+                    aad: &self.aad,
+                },
+            )
+            .map_err(|_| Error::Authentication(index))
+    }
+}
+
+/* Returns the number of plaintext bytes read */
+pub async fn encrypt<R, W>(
+    cipher: &ChunkCipher,
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<u64, Error>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let chunk_size = cipher.header().chunk_size as usize;
+    if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE as usize {
+        return Err(Error::InvalidHeader);
+    }
+    writer.write_all(&cipher.header().to_bytes()).await?;
+
+    let mut total = 0;
+    let mut index = 0;
+    let mut chunk = vec![0u8; chunk_size];
+    let mut filled = read_full(reader, &mut chunk).await?;
+    loop {
+        /* Read ahead, the last chunk has to be known before sealing it */
+        let mut next = vec![0u8; chunk_size];
+        let next_filled = if filled == chunk_size {
+            read_full(reader, &mut next).await?
+        } else {
+            0
+        };
+        let last = next_filled == 0;
+        let frame = cipher.seal(index, &chunk[..filled], last)?;
+        writer
+            .write_all(&(frame.len() as u32).to_be_bytes())
+            .await?;
+        writer.write_all(&frame).await?;
+        total += filled as u64;
+        if last {
+            break;
+        }
+        index += 1;
+        chunk = next;
+        filled = next_filled;
+    }
+    writer.flush().await?;
+    Ok(total)
+}
+
+/* The header has to be read already, returns the number of plaintext bytes written */
+pub async fn decrypt<R, W>(
+    cipher: &ChunkCipher,
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<u64, Error>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut total = 0;
+    let mut index = 0;
+    let mut frame = read_frame(cipher.header(), reader, index).await?;
+    loop {
+        let next = read_frame(cipher.header(), reader, index + 1).await?;
+        let last = next.is_none();
+        let frame_data = frame.ok_or(Error::Truncated)?;
+        let chunk = match cipher.open(index, &frame_data, last) {
+            Ok(chunk) => chunk,
+            /* The last chunk may be missing, the previous one wasn't sealed as last */
+            Err(error) if last => {
+                return Err(cipher
+                    .open(index, &frame_data, false)
+                    .map(|_| Error::Truncated)
+                    .unwrap_or(error))
+            }
+            Err(error) => return Err(error),
+        };
+        writer.write_all(&chunk).await?;
+        total += chunk.len() as u64;
+        if last {
+            break;
+        }
+        index += 1;
+        frame = next;
+    }
+    writer.flush().await?;
+    Ok(total)
+}
+
+/* Decrypt a single chunk without reading the chunks before it */
+pub async fn decrypt_chunk<R>(
+    cipher: &ChunkCipher,
+    reader: &mut R,
+    index: u64,
+) -> Result<Vec<u8>, Error>
+where
+    R: AsyncRead + AsyncSeek + Unpin,
+{
+    reader
+        .seek(SeekFrom::Start(cipher.header().frame_offset(index)))
+        .await?;
+    let frame = read_frame(cipher.header(), reader, index)
+        .await?
+        .ok_or(Error::Truncated)?;
+    /* Only the last chunk is shorter than the chunk size, but a full chunk can be the last one */
+    let full = frame.len() == cipher.header().chunk_size as usize + TAG_SIZE;
+    match cipher.open(index, &frame, !full) {
+        Ok(chunk) => Ok(chunk),
+        Err(error) if full => cipher.open(index, &frame, true).map_err(|_| error),
+        Err(error) => Err(error),
+    }
+}
+
+async fn read_frame<R: AsyncRead + Unpin>(
+    header: &Header,
+    reader: &mut R,
+    index: u64,
+) -> Result<Option<Vec<u8>>, Error> {
+    let mut len = [0u8; 4];
+    let read = read_full(reader, &mut len).await?;
+    if read == 0 {
+        return Ok(None);
+    } else if read < len.len() {
+        return Err(Error::Truncated);
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len < TAG_SIZE || len > header.chunk_size as usize + TAG_SIZE {
+        return Err(Error::InvalidFrame(index));
+    }
+    let mut frame = vec![0u8; len];
+    reader.read_exact(&mut frame).await?;
+    Ok(Some(frame))
+}
+
+/* Like read_exact, but a short read at the end of the stream isn't an error */
+async fn read_full<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let read = reader.read(&mut buf[filled..]).await?;
+        if read == 0 {
+            break;
+        }
+        filled += read;
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn cipher(chunk_size: u32) -> ChunkCipher {
+        ChunkCipher::new(&[7u8; 32], Header::new(chunk_size, "0xwrapped".into()))
+    }
+
+    async fn roundtrip(data: &[u8], chunk_size: u32) -> Vec<u8> {
+        let cipher = cipher(chunk_size);
+        let mut encrypted = vec![];
+        encrypt(&cipher, &mut Cursor::new(data), &mut encrypted)
+            .await
+            .unwrap();
+
+        let mut reader = Cursor::new(encrypted);
+        let header = Header::read(&mut reader).await.unwrap();
+        assert_eq!(&header, cipher.header());
+        let mut decrypted = vec![];
+        decrypt(
+            &ChunkCipher::new(&[7u8; 32], header),
+            &mut reader,
+            &mut decrypted,
+        )
+        .await
+        .unwrap();
+        decrypted
+    }
+
+    #[tokio::test]
+    async fn stream_roundtrip() {
+        let data: Vec<u8> = (0..1000u32).map(|x| x as u8).collect();
+        assert_eq!(roundtrip(&data, 100).await, data);
+        assert_eq!(roundtrip(&data, 333).await, data);
+        assert_eq!(roundtrip(&data, 4096).await, data);
+        assert_eq!(roundtrip(&[], 100).await, Vec::<u8>::new());
+    }
+
+    #[tokio::test]
+    async fn stream_detects_tampering() {
+        let data = vec![42u8; 250];
+        let cipher = cipher(100);
+        let mut encrypted = vec![];
+        encrypt(&cipher, &mut Cursor::new(&data), &mut encrypted)
+            .await
+            .unwrap();
+
+        let mut tampered = encrypted.clone();
+        let offset = cipher.header().frame_offset(1) as usize + 10;
+        tampered[offset] ^= 1;
+        let mut reader = Cursor::new(tampered);
+        let header = Header::read(&mut reader).await.unwrap();
+        let result = decrypt(
+            &ChunkCipher::new(&[7u8; 32], header),
+            &mut reader,
+            &mut Vec::<u8>::new(),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Authentication(1))));
+
+        /* Dropping the last frame must not go unnoticed */
+        let truncated = encrypted[..cipher.header().frame_offset(2) as usize].to_vec();
+        let mut reader = Cursor::new(truncated);
+        let header = Header::read(&mut reader).await.unwrap();
+        let result = decrypt(
+            &ChunkCipher::new(&[7u8; 32], header),
+            &mut reader,
+            &mut Vec::<u8>::new(),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Truncated)));
+    }
+
+    #[tokio::test]
+    async fn stream_rejects_large_chunks() {
+        let mut header = Header::new(MAX_CHUNK_SIZE + 1, "0xwrapped".into()).to_bytes();
+        let result = Header::read(&mut Cursor::new(header.clone())).await;
+        assert!(matches!(result, Err(Error::InvalidHeader)));
+
+        header[4..8].copy_from_slice(&MAX_CHUNK_SIZE.to_be_bytes());
+        assert!(Header::read(&mut Cursor::new(header)).await.is_ok());
+
+        let result = encrypt(
+            &cipher(MAX_CHUNK_SIZE + 1),
+            &mut Cursor::new(vec![1u8; 10]),
+            &mut Vec::<u8>::new(),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::InvalidHeader)));
+    }
+
+    #[tokio::test]
+    async fn stream_seek_chunk() {
+        let data: Vec<u8> = (0..250u32).map(|x| x as u8).collect();
+        let cipher = cipher(100);
+        let mut encrypted = vec![];
+        encrypt(&cipher, &mut Cursor::new(&data), &mut encrypted)
+            .await
+            .unwrap();
+
+        let mut reader = Cursor::new(encrypted);
+        let chunk = decrypt_chunk(&cipher, &mut reader, 2).await.unwrap();
+        assert_eq!(chunk, &data[200..]);
+        let chunk = decrypt_chunk(&cipher, &mut reader, 1).await.unwrap();
+        assert_eq!(chunk, &data[100..200]);
+    }
+}
@@ -0,0 +1,150 @@
+/* A document encrypted with threshold t can be decrypted by any t + 1 Secret Store nodes,
+ * so the highest possible threshold is the number of nodes - 1 */
+
+#[derive(Debug, PartialEq)]
+pub enum ThresholdError {
+    UnknownClusterSize,
+    TooHigh {
+        threshold: u32,
+        nodes: usize,
+    },
+    InvalidFraction(f64),
+    TooManyFailures {
+        failures: u32,
+        nodes: usize,
+    },
+    /// A server key for the document exists already, but with another threshold
+    Mismatch {
+        stored: u32,
+        requested: u32,
+    },
+}
+
+impl std::error::Error for ThresholdError {}
+
+impl std::fmt::Display for ThresholdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ThresholdError::UnknownClusterSize => write!(
+                f,
+                "The number of Secret Store nodes is unknown, set nodes or server_set_contract in the config."
+            ),
+            ThresholdError::TooHigh { threshold, nodes } => write!(
+                f,
+                "Threshold {} needs {} nodes but the Secret Store has only {}.",
+                threshold,
+                threshold + 1,
+                nodes
+            ),
+            ThresholdError::InvalidFraction(fraction) => write!(
+                f,
+                "Fraction {} of nodes isn't between 0 and 1.",
+                fraction
+            ),
+            ThresholdError::TooManyFailures { failures, nodes } => write!(
+                f,
+                "The Secret Store has {} nodes, it can't tolerate {} failures.",
+                nodes, failures
+            ),
+            ThresholdError::Mismatch { stored, requested } => write!(
+                f,
+                "The server key of the document has threshold {}, not {}.",
+                stored, requested
+            ),
+        }
+    }
+}
+
+/// How the threshold of a document is chosen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThresholdPolicy {
+    /// Exactly this threshold
+    Fixed(u32),
+    /// Fraction of the nodes which is needed to decrypt, e.g. 0.5 for half of the nodes
+    Fraction(f64),
+    /// Number of nodes which can be offline while the document can still be decrypted,
+    /// the highest threshold which allows this is used
+    MaxFailures(u32),
+}
+
+impl ThresholdPolicy {
+    /* The cluster size is None if it isn't known, then only fixed thresholds can be used
+     * and they can't be checked */
+    pub fn threshold(&self, nodes: Option<usize>) -> Result<u32, ThresholdError> {
+        match (*self, nodes) {
+            (ThresholdPolicy::Fixed(threshold), None) => Ok(threshold),
+            (ThresholdPolicy::Fixed(threshold), Some(nodes)) => {
+                if threshold as usize >= nodes {
+                    return Err(ThresholdError::TooHigh { threshold, nodes });
+                }
+                Ok(threshold)
+            }
+            (ThresholdPolicy::Fraction(fraction), Some(nodes)) => {
+                if !(fraction > 0.0 && fraction <= 1.0) {
+                    return Err(ThresholdError::InvalidFraction(fraction));
+                }
+                /* Even threshold 0 needs a node */
+                if nodes == 0 {
+                    return Err(ThresholdError::TooHigh {
+                        threshold: 0,
+                        nodes,
+                    });
+                }
+                let needed = (fraction * nodes as f64).ceil().max(1.0) as u32;
+                Ok(needed - 1)
+            }
+            (ThresholdPolicy::MaxFailures(failures), Some(nodes)) => {
+                if failures as usize >= nodes {
+                    return Err(ThresholdError::TooManyFailures { failures, nodes });
+                }
+                Ok((nodes - 1) as u32 - failures)
+            }
+            (_, None) => Err(ThresholdError::UnknownClusterSize),
+        }
+    }
+}
+
+impl From<u32> for ThresholdPolicy {
+    fn from(threshold: u32) -> Self {
+        ThresholdPolicy::Fixed(threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_threshold() {
+        assert_eq!(ThresholdPolicy::Fixed(25).threshold(None), Ok(25));
+        assert_eq!(ThresholdPolicy::Fixed(2).threshold(Some(3)), Ok(2));
+        assert_eq!(
+            ThresholdPolicy::Fixed(3).threshold(Some(3)),
+            Err(ThresholdError::TooHigh {
+                threshold: 3,
+                nodes: 3
+            })
+        );
+    }
+
+    #[test]
+    fn computed_threshold() {
+        assert_eq!(ThresholdPolicy::Fraction(0.5).threshold(Some(5)), Ok(2));
+        assert_eq!(ThresholdPolicy::Fraction(1.0).threshold(Some(5)), Ok(4));
+        assert_eq!(ThresholdPolicy::Fraction(0.01).threshold(Some(5)), Ok(0));
+        assert!(ThresholdPolicy::Fraction(1.5).threshold(Some(5)).is_err());
+        assert_eq!(
+            ThresholdPolicy::Fraction(0.5).threshold(Some(0)),
+            Err(ThresholdError::TooHigh {
+                threshold: 0,
+                nodes: 0
+            })
+        );
+        assert_eq!(ThresholdPolicy::MaxFailures(1).threshold(Some(5)), Ok(3));
+        assert!(ThresholdPolicy::MaxFailures(5).threshold(Some(5)).is_err());
+        assert_eq!(
+            ThresholdPolicy::MaxFailures(1).threshold(None),
+            Err(ThresholdError::UnknownClusterSize)
+        );
+    }
+}